@@ -0,0 +1,87 @@
+//! Time of the recursive, the iterator and the compiled trace generators on polybench
+//! kernels.
+//!
+//! The references of the kernels have affine subscripts, `matmul` from `dace!` and the
+//! others from the polybench constructors, so the compiled program updates their
+//! addresses incrementally.  Every generator is run over the whole trace and their
+//! element addresses are checked to be the same.  The speedup is over the recursive
+//! generator of `static_rd::trace`.
+
+use dace::arybase::set_arybase;
+use dace::ast::Node;
+use dace::compile::compile;
+use dace::iter::TraceIter;
+use dace::record::AccessRecord;
+use dace_tests::polybench::{cholesky, gemm, lu, mvt, syrk, trisolv};
+use static_rd::sink::TraceSink;
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+
+fn matmul(n: usize) -> Arc<Node> {
+    dace::dace! {
+        let C[n][n];
+        let A[n][n];
+        let B[n][n];
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    C[i][j] += A[i][k] * B[k][j];
+                }
+            }
+        }
+    }
+}
+
+/// A sink that passes the element addresses on to `emit`.
+struct Emit<'a>(&'a mut dyn FnMut(usize));
+
+impl TraceSink for Emit<'_> {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        (self.0)(rec.elem)
+    }
+}
+
+/// A checksum of the element addresses, with the number of accesses and the seconds
+/// it took to make them.
+fn measure(run: impl FnOnce(&mut dyn FnMut(usize))) -> (usize, usize, f64) {
+    let start = Instant::now();
+    let (mut sum, mut count) = (0usize, 0);
+    run(&mut |elem| {
+        sum = sum.wrapping_mul(31).wrapping_add(elem);
+        count += 1;
+    });
+    (sum, count, start.elapsed().as_secs_f64())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("Format:   exe   kernel   n");
+        return;
+    }
+    let n = args[2].parse::<usize>().unwrap();
+    let mut code = match args[1].as_str() {
+        "lu" => lu(n),
+        "mvt" => mvt(n),
+        "trisolv" => trisolv(n),
+        "syrk" => syrk(n, n),
+        "cholesky" => cholesky(n),
+        "gemm" => gemm(n),
+        _ => matmul(n),
+    };
+    set_arybase(&mut code);
+
+    let (expected, count, recursive) =
+        measure(|emit| static_rd::trace::trace_to(&code, &mut [&mut Emit(emit)]));
+    let (sum, _, iterator) = measure(|emit| TraceIter::new(&code).for_each(|rec| emit(rec.elem)));
+    assert_eq!(sum, expected, "the iterator trace is the recursive one");
+    let program = compile(&code);
+    let (sum, _, compiled) = measure(|emit| program.run(|elem, _| emit(elem)));
+    assert_eq!(sum, expected, "the compiled trace is the recursive one");
+    println!("{} {}: {} accesses", args[1], n, count);
+    println!("recursive: {:8.3} s", recursive);
+    println!("iterator:  {:8.3} s", iterator);
+    println!("compiled:  {:8.3} s", compiled);
+    println!("speedup:   {:8.1}x", recursive / compiled);
+}
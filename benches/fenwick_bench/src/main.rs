@@ -0,0 +1,83 @@
+//! Time and peak memory of the reuse distance simulators on polybench traces.
+//!
+//! The trace of a kernel is enumerated once into a vector, then run through each
+//! simulator.  Memory is the peak of live heap bytes during the run, counted by the
+//! global allocator.
+
+use dace::arybase::set_arybase;
+use dace::iter::TraceIter;
+use dace_tests::polybench::{_2mm, cholesky, gemm, lu, mvt, syrk, trisolv};
+use hist::Hist;
+use stack_alg_sim::fenwick::LRUFenwick;
+use stack_alg_sim::olken::LRUSplay;
+use stack_alg_sim::scale_tree::LRUSplay as LRUScaleTree;
+use stack_alg_sim::LRU;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The histogram of `trace`, with the seconds it took and the peak heap bytes it added.
+fn measure<T: LRU<usize>>(trace: &[usize], make: impl FnOnce() -> T) -> (Hist, f64, usize) {
+    let base = LIVE.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+    let start = Instant::now();
+    let mut analyzer = make();
+    let mut hist = Hist::new();
+    trace
+        .iter()
+        .for_each(|&x| hist.add_dist(analyzer.rec_access(x)));
+    let secs = start.elapsed().as_secs_f64();
+    drop(analyzer);
+    (hist, secs, PEAK.load(Ordering::Relaxed) - base)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("Format:   exe   kernel   n");
+        return;
+    }
+    let n = args[2].parse::<usize>().unwrap();
+    let mut code = match args[1].as_str() {
+        "lu" => lu(n),
+        "mvt" => mvt(n),
+        "trisolv" => trisolv(n),
+        "syrk" => syrk(n, n),
+        "cholesky" => cholesky(n),
+        "2mm" => _2mm(n, n, n, n),
+        _ => gemm(n),
+    };
+    set_arybase(&mut code);
+    let trace: Vec<usize> = TraceIter::new(&code).map(|rec| rec.elem).collect();
+    println!("{} {}: {} accesses", args[1], n, trace.len());
+
+    let (exact, secs, bytes) = measure(&trace, LRUSplay::new);
+    println!("olken splay:   {:8.3} s {:12} bytes", secs, bytes);
+    let (hist, secs, bytes) = measure(&trace, LRUFenwick::new);
+    println!("fenwick:       {:8.3} s {:12} bytes", secs, bytes);
+    assert_eq!(hist.to_vec(), exact.to_vec(), "the Fenwick tree is exact");
+    let (_, secs, bytes) = measure(&trace, || LRUScaleTree::new(0.1, 10000));
+    println!("scale tree:    {:8.3} s {:12} bytes", secs, bytes);
+}
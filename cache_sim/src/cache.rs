@@ -0,0 +1,345 @@
+use crate::policy::{Policy, ReplacementPolicy};
+use dace::record::AccessRecord;
+use static_rd::sink::TraceSink;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// How the line address of an access picks its set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexHash {
+    /// The line address modulo the number of sets.
+    Modulo,
+    /// The line address xor-ed with its tag, modulo the number of sets, which spreads
+    /// power-of-two strides over the sets like the hashed indices of many real caches.
+    Xor,
+}
+
+/// Geometry of a cache.  Sizes are in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub line_size: usize,
+    pub assoc: usize,
+    pub hash: IndexHash,
+    pub policy: Policy,
+}
+
+impl CacheConfig {
+    pub fn new(size: usize, line_size: usize, assoc: usize) -> Self {
+        CacheConfig {
+            size,
+            line_size,
+            assoc,
+            hash: IndexHash::Modulo,
+            policy: Policy::Lru,
+        }
+    }
+
+    pub fn with_hash(self, hash: IndexHash) -> Self {
+        CacheConfig { hash, ..self }
+    }
+
+    pub fn with_policy(self, policy: Policy) -> Self {
+        CacheConfig { policy, ..self }
+    }
+
+    /// A fully associative cache of `lines` lines.
+    pub fn fully_associative(lines: usize, line_size: usize) -> Self {
+        CacheConfig::new(lines * line_size, line_size, lines)
+    }
+
+    pub fn sets(&self) -> usize {
+        self.size / (self.line_size * self.assoc)
+    }
+
+    /// The set of the line address `line`.
+    pub fn set_of(&self, line: usize) -> usize {
+        let sets = self.sets();
+        match self.hash {
+            IndexHash::Modulo => line % sets,
+            IndexHash::Xor => (line ^ (line / sets)) % sets,
+        }
+    }
+}
+
+/// What an access did to the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Hit,
+    /// The line was brought in, replacing `evicted` if its set was full.
+    Miss {
+        evicted: Option<usize>,
+    },
+}
+
+impl Outcome {
+    pub fn is_hit(&self) -> bool {
+        *self == Outcome::Hit
+    }
+}
+
+/// A set-associative cache holding line addresses.
+pub struct Cache {
+    config: CacheConfig,
+    /// The line in every way, set by set.
+    ways: Vec<Option<usize>>,
+    policy: Box<dyn ReplacementPolicy>,
+}
+
+impl Cache {
+    /// A cache with the replacement policy of `config`.
+    pub fn new(config: CacheConfig) -> Self {
+        let policy = config.policy.build(config.sets(), config.assoc);
+        Cache::with_policy(config, policy)
+    }
+
+    /// A cache replacing lines by `policy` rather than by `config.policy`.
+    pub fn with_policy(config: CacheConfig, policy: Box<dyn ReplacementPolicy>) -> Self {
+        assert!(
+            config.line_size > 0 && config.assoc > 0,
+            "a cache needs a line size and an associativity"
+        );
+        assert!(
+            config.sets() > 0 && config.sets() * config.line_size * config.assoc == config.size,
+            "cache size {} is not a multiple of {} lines of {} bytes",
+            config.size,
+            config.assoc,
+            config.line_size
+        );
+        Cache {
+            config,
+            ways: vec![None; config.sets() * config.assoc],
+            policy,
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    fn set(&self, set: usize) -> &[Option<usize>] {
+        &self.ways[set * self.config.assoc..(set + 1) * self.config.assoc]
+    }
+
+    /// Access the line address `line`.  A miss fills an empty way if there is one.
+    pub fn access(&mut self, line: usize) -> Outcome {
+        let set = self.config.set_of(line);
+        let ways = self.set(set);
+        if let Some(way) = ways.iter().position(|&l| l == Some(line)) {
+            self.policy.on_hit(set, way);
+            return Outcome::Hit;
+        }
+        let way = match ways.iter().position(|l| l.is_none()) {
+            Some(way) => way,
+            None => self.policy.victim(set),
+        };
+        let evicted = self.ways[set * self.config.assoc + way].replace(line);
+        self.policy.on_fill(set, way);
+        Outcome::Miss { evicted }
+    }
+
+    pub fn contains(&self, line: usize) -> bool {
+        self.set(self.config.set_of(line)).contains(&Some(line))
+    }
+
+    /// Remove `line` if the cache holds it, and tell whether it did.
+    pub fn invalidate(&mut self, line: usize) -> bool {
+        let set = self.config.set_of(line);
+        let Some(way) = self.set(set).iter().position(|&l| l == Some(line)) else {
+            return false;
+        };
+        self.ways[set * self.config.assoc + way] = None;
+        self.policy.on_invalidate(set, way);
+        true
+    }
+}
+
+/// Hit, miss and eviction counts.  An eviction is counted for the access whose miss
+/// caused it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+impl Stats {
+    pub fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Hit => self.hits += 1,
+            Outcome::Miss { evicted } => {
+                self.misses += 1;
+                self.evictions += evicted.is_some() as usize;
+            }
+        }
+    }
+
+    pub fn accesses(&self) -> usize {
+        self.hits + self.misses
+    }
+
+    pub fn miss_ratio(&self) -> f64 {
+        self.misses as f64 / self.accesses().max(1) as f64
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.4}), {} evictions",
+            self.hits,
+            self.misses,
+            self.miss_ratio(),
+            self.evictions
+        )
+    }
+}
+
+/// Simulates a cache on the byte addresses of a trace, counting the outcomes in total,
+/// per reference and per array.
+pub struct CacheSim {
+    cache: Cache,
+    pub total: Stats,
+    pub by_ref: HashMap<usize, Stats>,
+    pub by_array: HashMap<usize, Stats>,
+}
+
+impl CacheSim {
+    pub fn new(config: CacheConfig) -> Self {
+        CacheSim {
+            cache: Cache::new(config),
+            total: Stats::default(),
+            by_ref: HashMap::new(),
+            by_array: HashMap::new(),
+        }
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+}
+
+impl TraceSink for CacheSim {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        let outcome = self.cache.access(rec.addr / self.cache.config.line_size);
+        self.total.record(outcome);
+        self.by_ref.entry(rec.ref_id).or_default().record(outcome);
+        self.by_array.entry(rec.ary_id).or_default().record(outcome);
+    }
+}
+
+impl fmt::Display for CacheSim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let config = self.cache.config;
+        writeln!(
+            f,
+            "Cache of {} bytes, {} byte lines, {}-way, {} sets, {:?} replacement:\n\t{}",
+            config.size,
+            config.line_size,
+            config.assoc,
+            config.sets(),
+            config.policy,
+            self.total
+        )?;
+        for (what, stats) in [("ref", &self.by_ref), ("array", &self.by_array)] {
+            let mut ids: Vec<_> = stats.keys().collect();
+            ids.sort();
+            for id in ids {
+                writeln!(f, "{} {}: {}", what, id, stats[id])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dace::arybase::set_arybase;
+    use dace::ast::Node;
+    use stack_alg_sim::olken::LRUSplay;
+    use stack_alg_sim::LRU;
+    use static_rd::trace::trace_to;
+
+    #[test]
+    fn direct_mapped_conflicts() {
+        // lines 0 and 4 share set 0 of a 4-set direct-mapped cache
+        let mut cache = Cache::new(CacheConfig::new(4 * 64, 64, 1));
+        assert_eq!(cache.access(0), Outcome::Miss { evicted: None });
+        assert_eq!(cache.access(4), Outcome::Miss { evicted: Some(0) });
+        assert_eq!(cache.access(0), Outcome::Miss { evicted: Some(4) });
+        assert_eq!(cache.access(1), Outcome::Miss { evicted: None });
+        assert!(cache.access(0).is_hit());
+
+        // with xor indexing, 4 maps to set 1 and the two lines stay
+        let mut cache = Cache::new(CacheConfig::new(4 * 64, 64, 1).with_hash(IndexHash::Xor));
+        cache.access(0);
+        assert_eq!(cache.access(4), Outcome::Miss { evicted: None });
+        assert!(cache.access(0).is_hit() && cache.contains(4));
+    }
+
+    #[test]
+    fn fully_associative_is_lru() {
+        // a fully associative LRU cache of c lines hits exactly when the reuse distance
+        // is at most c
+        let trace: Vec<usize> = (0..2000).map(|i| (i * 7 + i / 13) % 41).collect();
+        for c in [1, 4, 16, 40] {
+            let mut cache = Cache::new(CacheConfig::fully_associative(c, 64));
+            let mut lru = LRUSplay::new();
+            for &line in trace.iter() {
+                let hit = lru.rec_access(line).is_some_and(|d| d <= c);
+                assert_eq!(cache.access(line).is_hit(), hit);
+            }
+        }
+    }
+
+    #[test]
+    fn per_ref_and_array() {
+        // i = 0, 64 { A[i] B[0] }, 8 elements per line
+        let mut iloop = Node::new_single_loop("i", 0, 64);
+        let mut aref = Node::new_ref("A", vec![64], |i| vec![i[0] as i64]);
+        let mut bref = Node::new_ref("B", vec![8], |_| vec![0]);
+        Node::extend_loop_body(&mut iloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut bref);
+        set_arybase(&mut iloop);
+
+        let mut sim = CacheSim::new(CacheConfig::new(1024, 64, 2));
+        trace_to(&iloop, &mut [&mut sim]);
+        assert_eq!(sim.total.accesses(), 128);
+        let a = sim.by_array[&0];
+        assert_eq!((a.hits, a.misses), (56, 8));
+        // B[0] is in line 8, which shares set 0 of the 2-way cache with line 0 of A only
+        let b = sim.by_ref[&1];
+        assert_eq!((b.hits, b.misses, b.evictions), (63, 1, 0));
+        assert_eq!(sim.total.evictions, 0);
+        assert!(sim.to_string().contains("ref 1: 63 hits"));
+    }
+
+    #[test]
+    fn policies_share_a_trace() {
+        // i = 0, 16 { j = 0, 16 { A[j][i] } }: column order over 32 lines of a 16-line cache
+        let mut iloop = Node::new_single_loop("i", 0, 16);
+        let mut jloop = Node::new_single_loop("j", 0, 16);
+        let mut aref = Node::new_ref("A", vec![16, 16], |ij| vec![ij[1] as i64, ij[0] as i64]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+
+        let config = CacheConfig::new(1024, 64, 4);
+        let mut sims: Vec<CacheSim> = [Policy::Lru, Policy::Fifo, Policy::TreePlru, Policy::Srrip]
+            .into_iter()
+            .map(|policy| CacheSim::new(config.with_policy(policy)))
+            .collect();
+        let mut sinks: Vec<&mut dyn TraceSink> = sims.iter_mut().map(|s| s as _).collect();
+        trace_to(&iloop, &mut sinks);
+        // the 16 lines of a column are all even or all odd and map to 2 of the 4 sets,
+        // so they conflict and no policy hits on all of them
+        for sim in sims.iter() {
+            assert_eq!(sim.total.accesses(), 256);
+            assert!(sim.total.misses >= 32);
+        }
+        assert_eq!(sims[0].total, sims[1].total);
+    }
+}
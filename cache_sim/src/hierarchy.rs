@@ -0,0 +1,248 @@
+use crate::cache::{Cache, CacheConfig, Outcome, Stats};
+use dace::record::AccessRecord;
+use static_rd::sink::TraceSink;
+
+use std::fmt;
+
+/// How the contents of a level relate to those of the levels above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inclusion {
+    /// Every line of a level is also in the levels below it: a line evicted from a lower
+    /// level is invalidated above.
+    Inclusive,
+    /// A line is in at most one level: it moves up to the first level on an access, and
+    /// victims move down one level.
+    Exclusive,
+    /// Non-inclusive non-exclusive: misses fill every level on the way up, and the
+    /// levels evict independently.
+    Nine,
+}
+
+/// Caches from the first level down to the last, in front of memory.  All levels have
+/// the same line size.
+pub struct Hierarchy {
+    levels: Vec<Cache>,
+    inclusion: Inclusion,
+    /// Lookups of every level.  An eviction is counted for the level that evicts.
+    pub stats: Vec<Stats>,
+    /// Lines moved between every level and the one below it, the last entry between the
+    /// last level and memory.  Lines are clean, so evictions from the last level move
+    /// nothing.
+    pub traffic: Vec<usize>,
+    /// Lines removed from upper levels of an inclusive hierarchy.
+    pub back_invalidations: usize,
+}
+
+impl Hierarchy {
+    pub fn new(configs: &[CacheConfig], inclusion: Inclusion) -> Self {
+        assert!(!configs.is_empty(), "a hierarchy needs a level");
+        assert!(
+            configs.iter().all(|c| c.line_size == configs[0].line_size),
+            "the levels of a hierarchy must have the same line size"
+        );
+        Hierarchy {
+            levels: configs.iter().map(|&c| Cache::new(c)).collect(),
+            inclusion,
+            stats: vec![Stats::default(); configs.len()],
+            traffic: vec![0; configs.len()],
+            back_invalidations: 0,
+        }
+    }
+
+    pub fn levels(&self) -> &[Cache] {
+        &self.levels
+    }
+
+    /// Access the line address `line` and return the level that had it, `None` if it
+    /// came from memory.
+    pub fn access(&mut self, line: usize) -> Option<usize> {
+        let found = match self.inclusion {
+            Inclusion::Exclusive => self.access_exclusive(line),
+            _ => self.access_filling(line),
+        };
+        let moved = found.unwrap_or(self.levels.len());
+        self.traffic[..moved].iter_mut().for_each(|t| *t += 1);
+        found
+    }
+
+    /// Look the line up level by level, filling each level that misses.
+    fn access_filling(&mut self, line: usize) -> Option<usize> {
+        for i in 0..self.levels.len() {
+            let outcome = self.levels[i].access(line);
+            self.stats[i].record(outcome);
+            match outcome {
+                Outcome::Hit => return Some(i),
+                Outcome::Miss {
+                    evicted: Some(victim),
+                } if self.inclusion == Inclusion::Inclusive => {
+                    for upper in self.levels[..i].iter_mut() {
+                        self.back_invalidations += upper.invalidate(victim) as usize;
+                    }
+                }
+                Outcome::Miss { .. } => {}
+            }
+        }
+        None
+    }
+
+    /// Move the line to the first level, and every victim one level down.
+    fn access_exclusive(&mut self, line: usize) -> Option<usize> {
+        let found = self.levels.iter().position(|c| c.contains(line));
+        let lookups = found.map_or(self.levels.len(), |k| k + 1);
+        for i in 0..lookups {
+            let hit = found == Some(i);
+            let outcome = if hit {
+                Outcome::Hit
+            } else {
+                Outcome::Miss { evicted: None }
+            };
+            self.stats[i].record(outcome);
+        }
+        match found {
+            Some(0) => {
+                self.levels[0].access(line);
+                return found;
+            }
+            Some(k) => {
+                self.levels[k].invalidate(line);
+            }
+            None => {}
+        }
+        let mut line = Some(line);
+        for i in 0..self.levels.len() {
+            let Some(l) = line else {
+                break;
+            };
+            if i > 0 {
+                self.traffic[i - 1] += 1;
+            }
+            line = match self.levels[i].access(l) {
+                Outcome::Miss { evicted } => evicted,
+                Outcome::Hit => None,
+            };
+            self.stats[i].evictions += line.is_some() as usize;
+        }
+        found
+    }
+}
+
+impl TraceSink for Hierarchy {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        self.access(rec.addr / self.levels[0].config().line_size);
+    }
+}
+
+impl fmt::Display for Hierarchy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} hierarchy of {} levels:",
+            self.inclusion,
+            self.levels.len()
+        )?;
+        for (i, (cache, stats)) in self.levels.iter().zip(self.stats.iter()).enumerate() {
+            let c = cache.config();
+            writeln!(
+                f,
+                "L{}: {} bytes, {}-way, {:?}: {}",
+                i + 1,
+                c.size,
+                c.assoc,
+                c.policy,
+                stats
+            )?;
+            let below = if i + 1 < self.levels.len() {
+                format!("L{}", i + 2)
+            } else {
+                "memory".to_string()
+            };
+            writeln!(f, "\tL{} <-> {}: {} lines", i + 1, below, self.traffic[i])?;
+        }
+        if self.inclusion == Inclusion::Inclusive {
+            writeln!(f, "{} back invalidations", self.back_invalidations)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::CacheSim;
+    use dace::arybase::set_arybase;
+    use dace::ast::Node;
+    use static_rd::trace::trace_to;
+
+    fn two_levels(l1: usize, l2: usize, inclusion: Inclusion) -> Hierarchy {
+        let configs = [
+            CacheConfig::fully_associative(l1, 64),
+            CacheConfig::fully_associative(l2, 64),
+        ];
+        Hierarchy::new(&configs, inclusion)
+    }
+
+    fn hits(h: &Hierarchy) -> Vec<(usize, usize)> {
+        h.stats.iter().map(|s| (s.hits, s.misses)).collect()
+    }
+
+    #[test]
+    fn exclusive_adds_capacity() {
+        let trace = [0, 1, 0, 1];
+        let mut nine = two_levels(1, 1, Inclusion::Nine);
+        let found: Vec<_> = trace.iter().map(|&l| nine.access(l)).collect();
+        assert_eq!(found, [None; 4]);
+
+        let mut excl = two_levels(1, 1, Inclusion::Exclusive);
+        let found: Vec<_> = trace.iter().map(|&l| excl.access(l)).collect();
+        assert_eq!(found, [None, None, Some(1), Some(1)]);
+        assert_eq!(hits(&excl), [(0, 4), (2, 2)]);
+        // two lines come up from memory and two from L2, and three victims go down
+        assert_eq!(excl.traffic, [7, 2]);
+        assert_eq!(excl.stats[0].evictions, 3);
+    }
+
+    #[test]
+    fn inclusive_back_invalidates() {
+        // hits in L1 do not refresh 0 in L2, which evicts it for 2 and removes it from L1
+        let mut incl = two_levels(2, 2, Inclusion::Inclusive);
+        let found: Vec<_> = [0, 1, 0, 2, 0].iter().map(|&l| incl.access(l)).collect();
+        assert_eq!(found, [None, None, Some(0), None, None]);
+        assert_eq!(incl.back_invalidations, 1);
+
+        let mut nine = two_levels(2, 2, Inclusion::Nine);
+        let found: Vec<_> = [0, 1, 0, 2, 0].iter().map(|&l| nine.access(l)).collect();
+        assert_eq!(found, [None, None, Some(0), None, Some(0)]);
+    }
+
+    #[test]
+    fn driven_by_trace() {
+        // i = 0, 8 { j = 0, 64 { A[j] B[i][j] } }
+        let mut iloop = Node::new_single_loop("i", 0, 8);
+        let mut jloop = Node::new_single_loop("j", 0, 64);
+        let mut aref = Node::new_ref("A", vec![64], |ij| vec![ij[1] as i64]);
+        let mut bref = Node::new_ref("B", vec![8, 64], |ij| vec![ij[0] as i64, ij[1] as i64]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+
+        let l1 = CacheConfig::new(512, 64, 2);
+        let l2 = CacheConfig::new(4096, 64, 4);
+        let mut nine = Hierarchy::new(&[l1, l2], Inclusion::Nine);
+        let mut incl = Hierarchy::new(&[l1, l2], Inclusion::Inclusive);
+        let mut excl = Hierarchy::new(&[l1, l2], Inclusion::Exclusive);
+        let mut alone = CacheSim::new(l1);
+        trace_to(&iloop, &mut [&mut nine, &mut incl, &mut excl, &mut alone]);
+
+        // a NINE L1 is the same as the cache by itself
+        assert_eq!(nine.stats[0], alone.total);
+        for h in [&nine, &incl, &excl] {
+            assert_eq!(h.stats[0].accesses(), 1024);
+            assert_eq!(h.stats[1].accesses(), h.stats[0].misses);
+            // A fits in L2, so only the 8 lines of A and the 64 of B come from memory
+            assert_eq!(h.stats[1].misses, 72);
+            assert_eq!(h.traffic[1], 72);
+        }
+        assert!(nine.to_string().contains("L2 <-> memory: 72 lines"));
+    }
+}
@@ -0,0 +1,6 @@
+//! Cache simulators driven by the traces of `static_rd::trace`, to check what reuse
+//! distances predict against caches of limited associativity.
+
+pub mod cache;
+pub mod hierarchy;
+pub mod policy;
@@ -0,0 +1,473 @@
+//! Replacement policies.  A policy keeps its own state for every set and is told about
+//! the hits and fills of the cache; when a set is full it picks the way to evict.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub trait ReplacementPolicy {
+    /// The line in `way` of `set` was accessed again.
+    fn on_hit(&mut self, set: usize, way: usize);
+
+    /// A line was brought into `way` of `set` after a miss.
+    fn on_fill(&mut self, set: usize, way: usize);
+
+    /// The line in `way` of `set` was removed without being replaced.
+    fn on_invalidate(&mut self, _set: usize, _way: usize) {}
+
+    /// The way of the full `set` whose line is evicted next.
+    fn victim(&mut self, set: usize) -> usize;
+}
+
+/// The replacement policies of the simulator, to choose one in a `CacheConfig`.  Seeds
+/// make the randomized policies repeatable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Lru,
+    Fifo,
+    Random {
+        seed: u64,
+    },
+    /// Tree pseudo-LRU, for power-of-two associativities.
+    TreePlru,
+    /// Pseudo-LRU with one MRU bit per way, cleared when all are set.
+    BitPlru,
+    /// Not recently used: one reference bit per way, cleared when no way is unreferenced.
+    Nru,
+    /// Static re-reference interval prediction with 2-bit counters.
+    Srrip,
+    /// Bimodal RRIP: like SRRIP but most lines are inserted with a distant prediction.
+    Brrip {
+        seed: u64,
+    },
+    /// Dynamic RRIP: leader sets duel SRRIP against BRRIP and the others follow the winner.
+    Drrip {
+        seed: u64,
+    },
+    /// Least frequently used, ties broken by the lowest way.
+    Lfu,
+}
+
+impl Policy {
+    pub fn build(self, sets: usize, assoc: usize) -> Box<dyn ReplacementPolicy> {
+        match self {
+            Policy::Lru => Box::new(Lru::new(sets, assoc, false)),
+            Policy::Fifo => Box::new(Lru::new(sets, assoc, true)),
+            Policy::Random { seed } => Box::new(Random {
+                assoc,
+                rng: StdRng::seed_from_u64(seed),
+            }),
+            Policy::TreePlru => Box::new(TreePlru::new(sets, assoc)),
+            Policy::BitPlru => Box::new(BitPlru::new(sets, assoc)),
+            Policy::Nru => Box::new(Nru::new(sets, assoc)),
+            Policy::Srrip => Box::new(Rrip::new(sets, assoc, RripMode::Static, 0)),
+            Policy::Brrip { seed } => Box::new(Rrip::new(sets, assoc, RripMode::Bimodal, seed)),
+            Policy::Drrip { seed } => Box::new(Rrip::new(sets, assoc, RripMode::Dynamic, seed)),
+            Policy::Lfu => Box::new(Lfu::new(sets, assoc)),
+        }
+    }
+}
+
+/// LRU, or FIFO if only fills are stamped.
+struct Lru {
+    assoc: usize,
+    stamps: Vec<u64>,
+    clock: u64,
+    fifo: bool,
+}
+
+impl Lru {
+    fn new(sets: usize, assoc: usize, fifo: bool) -> Self {
+        Lru {
+            assoc,
+            stamps: vec![0; sets * assoc],
+            clock: 0,
+            fifo,
+        }
+    }
+
+    fn stamp(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.stamps[set * self.assoc + way] = self.clock;
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        if !self.fifo {
+            self.stamp(set, way);
+        }
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.stamp(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let stamps = &self.stamps[set * self.assoc..(set + 1) * self.assoc];
+        (0..self.assoc).min_by_key(|&w| stamps[w]).unwrap()
+    }
+}
+
+struct Random {
+    assoc: usize,
+    rng: StdRng,
+}
+
+impl ReplacementPolicy for Random {
+    fn on_hit(&mut self, _set: usize, _way: usize) {}
+
+    fn on_fill(&mut self, _set: usize, _way: usize) {}
+
+    fn victim(&mut self, _set: usize) -> usize {
+        self.rng.gen_range(0..self.assoc)
+    }
+}
+
+/// A binary tree over the ways of every set.  Each of the `assoc - 1` inner nodes points
+/// to the half that was used less recently.
+struct TreePlru {
+    assoc: usize,
+    bits: Vec<bool>,
+}
+
+impl TreePlru {
+    fn new(sets: usize, assoc: usize) -> Self {
+        assert!(
+            assoc.is_power_of_two(),
+            "tree PLRU needs a power-of-two associativity, not {}",
+            assoc
+        );
+        TreePlru {
+            assoc,
+            bits: vec![false; sets * assoc],
+        }
+    }
+
+    fn touch(&mut self, set: usize, way: usize) {
+        let bits = &mut self.bits[set * self.assoc..];
+        // node n has children 2n+1 and 2n+2; false points left
+        let (mut node, mut lo, mut size) = (0, 0, self.assoc);
+        while size > 1 {
+            size /= 2;
+            let right = way >= lo + size;
+            bits[node] = !right;
+            if right {
+                lo += size;
+            }
+            node = 2 * node + 1 + right as usize;
+        }
+    }
+}
+
+impl ReplacementPolicy for TreePlru {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let bits = &self.bits[set * self.assoc..];
+        let (mut node, mut lo, mut size) = (0, 0, self.assoc);
+        while size > 1 {
+            size /= 2;
+            let right = bits[node];
+            if right {
+                lo += size;
+            }
+            node = 2 * node + 1 + right as usize;
+        }
+        lo
+    }
+}
+
+struct BitPlru {
+    assoc: usize,
+    mru: Vec<bool>,
+}
+
+impl BitPlru {
+    fn new(sets: usize, assoc: usize) -> Self {
+        BitPlru {
+            assoc,
+            mru: vec![false; sets * assoc],
+        }
+    }
+
+    fn touch(&mut self, set: usize, way: usize) {
+        let bits = &mut self.mru[set * self.assoc..(set + 1) * self.assoc];
+        bits[way] = true;
+        if bits.iter().all(|&b| b) {
+            bits.iter_mut().for_each(|b| *b = false);
+            bits[way] = true;
+        }
+    }
+}
+
+impl ReplacementPolicy for BitPlru {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn on_invalidate(&mut self, set: usize, way: usize) {
+        self.mru[set * self.assoc + way] = false;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let bits = &self.mru[set * self.assoc..(set + 1) * self.assoc];
+        bits.iter().position(|&b| !b).unwrap_or(0)
+    }
+}
+
+struct Nru {
+    assoc: usize,
+    referenced: Vec<bool>,
+}
+
+impl Nru {
+    fn new(sets: usize, assoc: usize) -> Self {
+        Nru {
+            assoc,
+            referenced: vec![false; sets * assoc],
+        }
+    }
+}
+
+impl ReplacementPolicy for Nru {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.referenced[set * self.assoc + way] = true;
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.referenced[set * self.assoc + way] = true;
+    }
+
+    fn on_invalidate(&mut self, set: usize, way: usize) {
+        self.referenced[set * self.assoc + way] = false;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let bits = &mut self.referenced[set * self.assoc..(set + 1) * self.assoc];
+        if let Some(way) = bits.iter().position(|&b| !b) {
+            return way;
+        }
+        bits.iter_mut().for_each(|b| *b = false);
+        0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RripMode {
+    Static,
+    Bimodal,
+    Dynamic,
+}
+
+/// The largest re-reference prediction value of 2-bit counters: a distant re-reference.
+const RRPV_MAX: u8 = 3;
+/// BRRIP inserts one line in this many with a long rather than distant prediction.
+const BRRIP_LONG: u32 = 32;
+/// DRRIP leader sets: SRRIP leaders are the sets `0 mod DUEL_PERIOD`, BRRIP leaders
+/// the sets `1 mod DUEL_PERIOD`.
+const DUEL_PERIOD: usize = 32;
+const PSEL_MAX: u32 = 1023;
+
+struct Rrip {
+    assoc: usize,
+    rrpv: Vec<u8>,
+    mode: RripMode,
+    rng: StdRng,
+    /// Misses of SRRIP leaders minus those of BRRIP leaders, offset by half its range.
+    psel: u32,
+}
+
+impl Rrip {
+    fn new(sets: usize, assoc: usize, mode: RripMode, seed: u64) -> Self {
+        Rrip {
+            assoc,
+            rrpv: vec![RRPV_MAX; sets * assoc],
+            mode,
+            rng: StdRng::seed_from_u64(seed),
+            psel: PSEL_MAX / 2,
+        }
+    }
+
+    fn bimodal(&self, set: usize) -> bool {
+        match self.mode {
+            RripMode::Static => false,
+            RripMode::Bimodal => true,
+            RripMode::Dynamic => match set % DUEL_PERIOD {
+                0 => false,
+                1 => true,
+                _ => self.psel > PSEL_MAX / 2,
+            },
+        }
+    }
+}
+
+impl ReplacementPolicy for Rrip {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.rrpv[set * self.assoc + way] = 0;
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        if self.mode == RripMode::Dynamic {
+            match set % DUEL_PERIOD {
+                0 => self.psel = (self.psel + 1).min(PSEL_MAX),
+                1 => self.psel = self.psel.saturating_sub(1),
+                _ => {}
+            }
+        }
+        let long = !self.bimodal(set) || self.rng.gen_ratio(1, BRRIP_LONG);
+        self.rrpv[set * self.assoc + way] = if long { RRPV_MAX - 1 } else { RRPV_MAX };
+    }
+
+    fn on_invalidate(&mut self, set: usize, way: usize) {
+        self.rrpv[set * self.assoc + way] = RRPV_MAX;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let rrpv = &mut self.rrpv[set * self.assoc..(set + 1) * self.assoc];
+        let oldest = *rrpv.iter().max().unwrap();
+        rrpv.iter_mut().for_each(|v| *v += RRPV_MAX - oldest);
+        rrpv.iter().position(|&v| v == RRPV_MAX).unwrap()
+    }
+}
+
+struct Lfu {
+    assoc: usize,
+    counts: Vec<u64>,
+}
+
+impl Lfu {
+    fn new(sets: usize, assoc: usize) -> Self {
+        Lfu {
+            assoc,
+            counts: vec![0; sets * assoc],
+        }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.counts[set * self.assoc + way] += 1;
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.counts[set * self.assoc + way] = 1;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let counts = &self.counts[set * self.assoc..(set + 1) * self.assoc];
+        (0..self.assoc).min_by_key(|&w| counts[w]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::{Cache, CacheConfig, Outcome};
+
+    /// The hits of a fully associative cache of `lines` lines on `trace`.
+    fn hits(policy: Policy, lines: usize, trace: &[usize]) -> Vec<bool> {
+        let config = CacheConfig::fully_associative(lines, 64).with_policy(policy);
+        let mut cache = Cache::new(config);
+        trace.iter().map(|&l| cache.access(l).is_hit()).collect()
+    }
+
+    fn evictions(policy: Policy, lines: usize, trace: &[usize]) -> Vec<usize> {
+        let config = CacheConfig::fully_associative(lines, 64).with_policy(policy);
+        let mut cache = Cache::new(config);
+        trace
+            .iter()
+            .filter_map(|&l| match cache.access(l) {
+                Outcome::Miss { evicted } => evicted,
+                Outcome::Hit => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lru_and_fifo() {
+        // 0 is reused before 3 comes, so LRU evicts 1 but FIFO still evicts 0
+        let trace = [0, 1, 2, 0, 3, 0];
+        assert_eq!(evictions(Policy::Lru, 3, &trace), [1]);
+        assert_eq!(evictions(Policy::Fifo, 3, &trace), [0, 1]);
+    }
+
+    #[test]
+    fn random_is_seeded() {
+        let trace: Vec<usize> = (0..500).map(|i| (i * i) % 37).collect();
+        let a = hits(Policy::Random { seed: 7 }, 8, &trace);
+        assert_eq!(a, hits(Policy::Random { seed: 7 }, 8, &trace));
+        assert_ne!(a, hits(Policy::Random { seed: 8 }, 8, &trace));
+    }
+
+    #[test]
+    fn tree_plru() {
+        // after 0 1 2 3, the tree points away from 3 and then from 1: evict 0;
+        // touching 0 again points the root right, to the pair {2, 3}, and then to 2
+        assert_eq!(evictions(Policy::TreePlru, 4, &[0, 1, 2, 3, 4]), [0]);
+        assert_eq!(evictions(Policy::TreePlru, 4, &[0, 1, 2, 3, 0, 4]), [2]);
+    }
+
+    #[test]
+    fn bit_plru_and_nru() {
+        // 0 1 2 3 sets the last MRU bit, which clears the others: the victim is way 0.
+        // A hit on 0 then leaves 1 as the first way without the bit.
+        assert_eq!(evictions(Policy::BitPlru, 4, &[0, 1, 2, 3, 0, 4]), [1]);
+        // NRU keeps all reference bits until a victim is needed, then clears them
+        assert_eq!(evictions(Policy::Nru, 4, &[0, 1, 2, 3, 0, 4, 5]), [0, 1]);
+    }
+
+    #[test]
+    fn rrip_resists_scans() {
+        // a working set of 4 lines reused between scans of 2 lines: SRRIP keeps the
+        // working set, which has been hit, while LRU loses part of it at every scan
+        let mut trace = vec![];
+        for r in 0..50 {
+            trace.extend([0, 1, 2, 3, 0, 1, 2, 3]);
+            trace.extend([100 + 2 * r, 101 + 2 * r]);
+        }
+        let count = |h: Vec<bool>| h.into_iter().filter(|&h| h).count();
+        let lru = count(hits(Policy::Lru, 5, &trace));
+        let srrip = count(hits(Policy::Srrip, 5, &trace));
+        assert!(srrip > lru, "SRRIP {} LRU {}", srrip, lru);
+        let brrip = count(hits(Policy::Brrip { seed: 1 }, 5, &trace));
+        let drrip = count(hits(Policy::Drrip { seed: 1 }, 5, &trace));
+        assert!(brrip > lru && drrip > lru);
+    }
+
+    #[test]
+    fn lfu() {
+        // 0 is used three times and stays; 1 and 2 are used once each
+        assert_eq!(evictions(Policy::Lfu, 2, &[0, 0, 0, 1, 2, 3]), [1, 2]);
+    }
+
+    #[test]
+    fn all_policies_fill_empty_ways_first() {
+        let all = [
+            Policy::Lru,
+            Policy::Fifo,
+            Policy::Random { seed: 0 },
+            Policy::TreePlru,
+            Policy::BitPlru,
+            Policy::Nru,
+            Policy::Srrip,
+            Policy::Brrip { seed: 0 },
+            Policy::Drrip { seed: 0 },
+            Policy::Lfu,
+        ];
+        let trace: Vec<usize> = (0..8).chain(0..8).collect();
+        for policy in all {
+            let h = hits(policy, 8, &trace);
+            assert_eq!(h, [[false; 8], [true; 8]].concat(), "{:?}", policy);
+        }
+    }
+}
@@ -1,11 +1,14 @@
-#![feature(get_mut_unchecked)]
-
-mod ri_utils;
-mod sampling;
+use dace::arybase::set_arybase;
 use dace_tests::matmul;
 use dace_tests::polybench::{
     _2mm, _3mm, cholesky, gemm, gramschmidt_trace, lu, mvt, syr2d, syrk, trisolv, trmm_trace,
 };
+use hist::Hist;
+use static_rd::trace::trace_to;
+use static_ri::output::Tsv;
+use static_ri::trace::{Granularity, RiTracer};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::{env, time::Instant};
 use tracing_subscriber::EnvFilter;
 
@@ -61,7 +64,19 @@ fn main() {
         _ => matmul(split[0].parse::<usize>().unwrap()),
     };
     let start = Instant::now();
-    let _hist = sampling::tracing_ri(&mut trace);
+    set_arybase(&mut trace);
+    let mut hist = Hist::new();
+    let file = File::create("output.csv").expect("Unable to create file");
+    let mut tsv = Tsv::new(BufWriter::new(file)).expect("Unable to write data");
+    println!("Tracing Reuse Interval...");
+    let mut tracer = RiTracer::new(Granularity::Line, vec![&mut tsv, &mut hist]);
+    trace_to(&trace, &mut [&mut tracer]);
+    tracer.into_result().expect("Unable to write data");
     let end = Instant::now();
     println!("trace time: {:?}", end - start);
+
+    let mut hist_file = File::create("hist_output.txt").expect("Unable to create hist file");
+    hist_file
+        .write_all(hist.to_string().as_bytes())
+        .expect("Unable to write hist data");
 }
@@ -1,33 +1,75 @@
-use crate::ast::{LoopBound, LoopStmt, Node, Stmt};
+use crate::ast::{LoopBound, LoopStmt, Node, Stmt, Subprogram};
 use crate::iter::Walk;
 
 use rand::prelude::*;
 use std::collections::{BTreeSet, HashMap};
 use std::intrinsics::ceilf32;
 use std::ops::Range;
-use std::rc::Rc;
+use std::sync::Arc;
 use tracing::debug;
 
-pub fn set_arybase(aloop: &mut Rc<Node>) -> (HashMap<String, usize>, usize) {
-    let init = (HashMap::<String, usize>::new(), 0);
-    Walk::new(aloop)
-        .filter(|node| matches!(&node.stmt, Stmt::Ref(_)))
-        .fold::<(HashMap<String, usize>, usize), _>(init, |(mut tbl, mut cur_base), mut node| {
-            let ary_name = node.ref_only_ref(|a_ref| &a_ref.name).unwrap().as_str();
-            if !tbl.contains_key(ary_name) {
-                tbl.insert(ary_name.to_string(), cur_base);
-                let dim = node.ref_only_ref(|a_ref| &a_ref.dim).unwrap();
-                let ary_size: usize = dim.iter().product();
-                cur_base += ary_size;
+/// Assign every array a base address, in elements, and an id, in the order the arrays are
+/// first referenced.  Arrays passed to a call are sized by the callee's formal parameters.
+/// References without a `ref_id`, including those in the bodies of called subprograms,
+/// are numbered in walk order.
+pub fn set_arybase(aloop: &mut Arc<Node>) -> (HashMap<String, usize>, usize) {
+    let mut tbl = HashMap::<String, usize>::new();
+    let mut ids = HashMap::<String, usize>::new();
+    let mut cur_base = 0;
+    let mut next_ref_id = 0;
+    let mut array_of = |ary_name: &str, dim: &[usize]| {
+        if !tbl.contains_key(ary_name) {
+            tbl.insert(ary_name.to_string(), cur_base);
+            ids.insert(ary_name.to_string(), ids.len());
+            cur_base += dim.iter().product::<usize>();
+        }
+        (tbl[ary_name], ids[ary_name])
+    };
+    for mut node in Walk::new(aloop) {
+        let mutable = unsafe { Arc::get_mut_unchecked(&mut node) };
+        match &mut mutable.stmt {
+            Stmt::Ref(a_ref) => {
+                let (base, ary_id) = array_of(&a_ref.name, &a_ref.dim);
+                a_ref.base = Some(base);
+                a_ref.ary_id = Some(ary_id);
+                a_ref.ref_id.get_or_insert_with(|| {
+                    next_ref_id += 1;
+                    next_ref_id - 1
+                });
+            }
+            Stmt::Call(call) => {
+                for (k, ary_name) in call.arrays.iter().enumerate() {
+                    let (base, ary_id) = array_of(ary_name, &call.callee.arrays[k].1);
+                    call.bases[k] = Some(base);
+                    call.ary_ids[k] = Some(ary_id);
+                }
+                number_callee_refs(&call.callee, &mut next_ref_id);
             }
-            let ary_base = tbl.get(ary_name).unwrap();
-            let mutable = unsafe { Rc::get_mut_unchecked(&mut node) };
-            let my_base = mutable.ref_only_mut_ref(|a_ref| &mut a_ref.base).unwrap();
-            *my_base = Some(*ary_base);
-            (tbl, cur_base)
-        })
+            _ => {}
+        }
+    }
+    (tbl, cur_base)
 }
 
+fn number_callee_refs(callee: &Arc<Subprogram>, next_ref_id: &mut usize) {
+    for mut node in Walk::new(&callee.body) {
+        let mutable = unsafe { Arc::get_mut_unchecked(&mut node) };
+        match &mut mutable.stmt {
+            Stmt::Ref(a_ref) => {
+                a_ref.ref_id.get_or_insert_with(|| {
+                    *next_ref_id += 1;
+                    *next_ref_id - 1
+                });
+            }
+            Stmt::Call(call) => number_callee_refs(&call.callee, next_ref_id),
+            _ => {}
+        }
+    }
+}
+
+/// Collect the `Fixed` loops around every reference, numbering references in walk order.
+/// A call is walked into, with the loops around the call wrapping the callee body, so the
+/// references of a callee are numbered again at every call.
 pub fn sample_collect<'a>(
     code_tree: &'a Node,
     wrapping_loops: &mut Vec<&'a LoopStmt>,
@@ -68,6 +110,7 @@ pub fn sample_collect<'a>(
             }
         }
         Stmt::Branch(_) => unimplemented!("branch is not supported yet"),
+        Stmt::Call(call) => sample_collect(&call.callee.body, wrapping_loops, ans, ref_counter),
     }
 }
 
@@ -133,10 +176,10 @@ mod test {
         let n: usize = 100; // array dim
         let ubound = n as i32; // loop bound
                                // creating A[i] B[i,i+1] C[i,i+1,i+2]
-        let ref_a = Node::new_ref("A", vec![n], |i| vec![i[0] as usize]);
-        let ref_b = Node::new_ref("B", vec![n, n], |i| vec![i[0] as usize, i[0] as usize + 1]);
+        let ref_a = Node::new_ref("A", vec![n], |i| vec![i[0] as i64]);
+        let ref_b = Node::new_ref("B", vec![n, n], |i| vec![i[0] as i64, i[0] as i64 + 1]);
         let ref_c = Node::new_ref("C", vec![n, n, n], |i| {
-            vec![i[0] as usize, i[0] as usize + 1, i[0] as usize + 2]
+            vec![i[0] as i64, i[0] as i64 + 1, i[0] as i64 + 2]
         });
 
         // creating loop k = 0, n
@@ -170,4 +213,21 @@ mod test {
         );
         // Walk::new(&iloop).for_each( |node| println!("{:?}", node) );
     }
+
+    #[test]
+    fn sample_collect_call() {
+        let mut inner = Node::new_single_loop("j", 0, 4);
+        let mut x = Node::new_ref("x", vec![4], |ij| vec![ij[1] as i64]);
+        Node::extend_loop_body(&mut inner, &mut x);
+        let sub = Subprogram::new("row", vec![("x", vec![4])], 0, inner);
+        let mut outer = Node::new_single_loop("i", 0, 8);
+        Node::extend_loop_body(&mut outer, &mut Node::new_call(&sub, &["A"], vec![]));
+        Node::extend_loop_body(&mut outer, &mut Node::new_call(&sub, &["B"], vec![]));
+
+        let mut ans = HashMap::new();
+        let mut ref_counter = 0;
+        sample_collect(&outer, &mut Vec::new(), &mut ans, &mut ref_counter);
+        assert_eq!(ref_counter, 2);
+        assert_eq!(ans[&1], [("i", 0..8), ("j", 0..4)]);
+    }
 }
@@ -1,7 +1,13 @@
 use std::fmt::{Debug, Formatter};
-use std::rc::{Rc, Weak};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Weak};
 
 /// Each loop and statement is a node in a loop tree.
+///
+/// Nodes are shared through `Arc` and every closure in the tree is `Send + Sync`,
+/// so a finished tree can be read by several threads at once.  Building it
+/// (`extend_loop_body`, `set_arybase`) mutates nodes in place and must be done
+/// before the tree is shared.
 #[derive(Debug)]
 pub struct Node {
     pub stmt: Stmt,
@@ -15,15 +21,17 @@ pub enum Stmt {
     Loop(LoopStmt),
     /// A statement is a sequence of array references
     Ref(AryRef),
-    Block(Vec<Rc<Node>>),
+    Block(Vec<Arc<Node>>),
     Branch(BranchStmt),
+    /// An instance of a subprogram
+    Call(CallStmt),
 }
 
 pub struct BranchStmt {
     #[allow(clippy::type_complexity)]
-    pub cond: Box<dyn Fn(&[i32]) -> bool>,
-    pub then_body: Rc<Node>,
-    pub else_body: Option<Rc<Node>>,
+    pub cond: Box<dyn Fn(&[i32]) -> bool + Send + Sync>,
+    pub then_body: Arc<Node>,
+    pub else_body: Option<Arc<Node>>,
 }
 
 pub struct LoopStmt {
@@ -32,10 +40,86 @@ pub struct LoopStmt {
     pub ub: LoopBound,
     // The next two need the FnOnce trait, which we'll add later
     // Now we assume test is iv < ub
-    pub test: Box<dyn Fn(i32, i32) -> bool>,
+    pub test: Box<dyn Fn(i32, i32) -> bool + Send + Sync>,
     // Now we assume step is iv = iv + 1
-    pub step: Box<dyn Fn(i32) -> i32>,
-    pub body: Vec<Rc<Node>>,
+    pub step: Box<dyn Fn(i32) -> i32 + Send + Sync>,
+    pub body: Vec<Arc<Node>>,
+}
+
+/// A reusable loop tree, e.g. a GEMM tile or a stencil sweep, written against formal
+/// array parameters and scalar parameters.
+///
+/// Inside `body`, references name the formal arrays, and the iteration vector starts
+/// with the values of the scalar parameters, followed by the indices of the body's own
+/// loops.  The body does not see the loops around a call.
+pub struct Subprogram {
+    pub name: String,
+    /// Formal array parameters with their dimensions.
+    pub arrays: Vec<(String, Vec<usize>)>,
+    /// Number of scalar parameters.
+    pub scalars: usize,
+    pub body: Arc<Node>,
+}
+
+/// A call of a subprogram with bound arguments.
+pub struct CallStmt {
+    pub callee: Arc<Subprogram>,
+    /// Actual arrays, one for each formal array parameter of the callee.  In a call nested
+    /// in another subprogram these name the formal arrays of the enclosing subprogram.
+    pub arrays: Vec<String>,
+    /// Scalar arguments, evaluated with the iteration vector at the call site.
+    pub scalars: Vec<LoopBound>,
+    /// Bases of the actual arrays, set by `set_arybase` for calls outside any subprogram.
+    pub bases: Vec<Option<usize>>,
+    /// Ids of the actual arrays, set along with `bases`.
+    pub ary_ids: Vec<Option<usize>>,
+}
+
+impl Subprogram {
+    pub fn new(
+        name: &str,
+        arrays: Vec<(&str, Vec<usize>)>,
+        scalars: usize,
+        body: Arc<Node>,
+    ) -> Arc<Subprogram> {
+        Arc::new(Subprogram {
+            name: name.to_string(),
+            arrays: arrays
+                .into_iter()
+                .map(|(ary_nm, dim)| (ary_nm.to_string(), dim))
+                .collect(),
+            scalars,
+            body,
+        })
+    }
+
+    /// Position of a formal array parameter.
+    pub fn array_param(&self, ary_nm: &str) -> Option<usize> {
+        self.arrays.iter().position(|(name, _)| name == ary_nm)
+    }
+}
+
+impl Debug for Subprogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subprogram")
+            .field("name", &self.name)
+            .field("arrays", &self.arrays)
+            .field("scalars", &self.scalars)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+impl Debug for CallStmt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallStmt")
+            .field("callee", &self.callee.name)
+            .field("arrays", &self.arrays)
+            .field("scalars", &self.scalars)
+            .field("bases", &self.bases)
+            .field("ary_ids", &self.ary_ids)
+            .finish()
+    }
 }
 
 impl Debug for LoopStmt {
@@ -61,13 +145,31 @@ impl Debug for BranchStmt {
 pub enum LoopBound {
     Fixed(i32),
     #[allow(clippy::type_complexity)]
-    Dynamic(Box<dyn Fn(&[i32]) -> i32>),
+    Dynamic(Box<dyn Fn(&[i32]) -> i32 + Send + Sync>),
     Affine {
         a: Vec<i32>,
         b: i32,
     },
 }
 
+impl LoopBound {
+    /// Value of the bound at iteration `ivec`.
+    pub fn eval(&self, ivec: &[i32]) -> i32 {
+        match self {
+            LoopBound::Fixed(x) => *x,
+            LoopBound::Dynamic(f) => f(ivec),
+            LoopBound::Affine { a, b } => {
+                a.iter()
+                    .copied()
+                    .zip(ivec.iter().copied())
+                    .map(|(x, y)| x * y)
+                    .sum::<i32>()
+                    + *b
+            }
+        }
+    }
+}
+
 impl std::fmt::Debug for LoopBound {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
@@ -86,9 +188,149 @@ pub struct AryRef {
     /// Subscript expressions: one function for each data dimension.  
     /// Each function takes the indices of its loop nest and returns indices of the array access.
     #[allow(clippy::type_complexity)]
-    pub sub: Box<dyn for<'a> Fn(&'a [i32]) -> AryAcc>,
+    pub sub: Box<dyn for<'a> Fn(&'a [i32]) -> AryAcc + Send + Sync>,
     pub base: Option<usize>,
     pub ref_id: Option<usize>,
+    /// Position of the array in the order arrays are first referenced, set with `base`.
+    pub ary_id: Option<usize>,
+    /// What to do when a subscript falls outside its dimension.
+    pub boundary: Boundary,
+    pub kind: AccessKind,
+    /// The subscripts as an affine form, if known, e.g. for references made by `dace!`.
+    /// `sub` computes the same subscripts; the form lets a compiler of the tree update
+    /// addresses incrementally.
+    pub affine: Option<AffineSub>,
+}
+
+/// Affine subscripts: dimension d is `consts[d] + sum(coeffs[d][k] * ivec[k])`, with
+/// missing coefficients taken as 0.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AffineSub {
+    pub coeffs: Vec<Vec<i64>>,
+    pub consts: Vec<i64>,
+}
+
+impl AffineSub {
+    pub fn eval(&self, ivec: &[i32]) -> AryAcc {
+        self.coeffs
+            .iter()
+            .zip(self.consts.iter())
+            .map(|(c, &b)| b + c.iter().zip(ivec).map(|(&a, &i)| a * i as i64).sum::<i64>())
+            .collect()
+    }
+}
+
+/// Whether a reference reads or writes its element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AccessKind {
+    #[default]
+    Read,
+    Write,
+}
+
+/// Policy for subscripts outside `0..dim`, e.g. the halo accesses of a stencil.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Boundary {
+    /// Panic, reporting the array and the subscript.
+    #[default]
+    Error,
+    /// Use the nearest valid index.
+    Clamp,
+    /// Wrap around periodically, so -1 becomes dim - 1.
+    Wrap,
+    /// Drop the whole access.
+    Skip,
+}
+
+impl AryRef {
+    /// Row-major element offset of the access made at iteration `ivec`, relative to the base
+    /// of the array.  Out-of-range subscripts are handled by `self.boundary`; `None` means the
+    /// access is skipped.
+    pub fn offset(&self, ivec: &[i32]) -> Option<usize> {
+        self.offset_of(&(self.sub)(ivec), ivec)
+    }
+
+    /// The row-major offset of the subscripts `ary_index`, which the reference computes
+    /// at `ivec`, after applying the boundary policy.
+    pub fn offset_of(&self, ary_index: &[i64], ivec: &[i32]) -> Option<usize> {
+        if ary_index.len() != self.dim.len() {
+            panic!(
+                "{}{:?} at {:?} has {} subscripts for {} dimensions",
+                self.name,
+                ary_index,
+                ivec,
+                ary_index.len(),
+                self.dim.len()
+            );
+        }
+
+        let mut offset = 0;
+        for (pos, (&i, &d)) in ary_index.iter().zip(self.dim.iter()).enumerate() {
+            let dim = d as i64;
+            let i = if (0..dim).contains(&i) {
+                i
+            } else {
+                match self.boundary {
+                    Boundary::Error => panic!(
+                        "subscript {} of {}{:?} at {:?} is out of range 0..{} in dimension {}",
+                        i, self.name, ary_index, ivec, dim, pos
+                    ),
+                    Boundary::Clamp => i.clamp(0, dim - 1),
+                    Boundary::Wrap => i.rem_euclid(dim),
+                    Boundary::Skip => return None,
+                }
+            };
+            offset = offset * d + i as usize;
+        }
+        Some(offset)
+    }
+}
+
+/// A read-only integer array that drives data-dependent subscripts and bounds,
+/// e.g. the `col` and `rowptr` arrays of a CSR matrix.  Cloning shares the data.
+#[derive(Debug, Clone)]
+pub struct IndexArray {
+    pub name: String,
+    pub data: Arc<Vec<i64>>,
+}
+
+impl IndexArray {
+    pub fn new(name: &str, data: Vec<i64>) -> Self {
+        IndexArray {
+            name: name.to_string(),
+            data: Arc::new(data),
+        }
+    }
+
+    /// Load the array from a text file of integers separated by whitespace or commas.
+    pub fn from_file<P: AsRef<std::path::Path>>(name: &str, path: P) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let data = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<i64>().map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{s:?}: {e}"))
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Ok(IndexArray::new(name, data))
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, pos: i64) -> i64 {
+        match usize::try_from(pos).ok().and_then(|p| self.data.get(p)) {
+            Some(&value) => value,
+            None => panic!("index array {} has no element {}", self.name, pos),
+        }
+    }
 }
 
 impl std::fmt::Debug for AryRef {
@@ -99,8 +341,9 @@ impl std::fmt::Debug for AryRef {
 
 /// Type alias for the iteration vector, with i32 elements.
 pub type IterVec = Vec<i32>;
-/// Type alias for the array access indices, with usize elements.
-pub type AryAcc = Vec<usize>;
+/// Type alias for the array access indices, with i64 elements so that halo
+/// accesses such as `i - 1` can go below zero before the boundary policy applies.
+pub type AryAcc = Vec<i64>;
 
 impl From<i32> for LoopBound {
     fn from(value: i32) -> Self {
@@ -110,7 +353,7 @@ impl From<i32> for LoopBound {
 
 impl<F> From<F> for LoopBound
 where
-    for<'a> F: Fn(&'a [i32]) -> i32 + 'static,
+    for<'a> F: Fn(&'a [i32]) -> i32 + Send + Sync + 'static,
 {
     fn from(value: F) -> Self {
         LoopBound::Dynamic(Box::new(value))
@@ -128,8 +371,8 @@ impl From<(Vec<i32>, i32)> for LoopBound {
 
 #[macro_export]
 macro_rules! dynamic {
-    ($x:tt) => {
-        $crate::ast::LoopBound::Dynamic($tt)
+    ($x:expr) => {
+        $crate::ast::LoopBound::Dynamic(Box::new($x))
     };
 }
 
@@ -181,16 +424,29 @@ macro_rules! branch_node {
 
 impl Node {
     /// Create a new Node with a given statement.
-    pub fn new_node(a_stmt: Stmt) -> Rc<Node> {
-        Rc::new(Node {
+    pub fn new_node(a_stmt: Stmt) -> Arc<Node> {
+        Arc::new(Node {
             stmt: a_stmt,
             parent: Weak::new(),
         })
     }
 
-    pub fn new_ref<F>(ary_nm: &str, ary_dim: Vec<usize>, ary_sub: F) -> Rc<Node>
+    pub fn new_ref<F>(ary_nm: &str, ary_dim: Vec<usize>, ary_sub: F) -> Arc<Node>
     where
-        F: for<'a> Fn(&'a [i32]) -> AryAcc + 'static,
+        F: for<'a> Fn(&'a [i32]) -> AryAcc + Send + Sync + 'static,
+    {
+        Node::new_ref_with_boundary(ary_nm, ary_dim, Boundary::Error, ary_sub)
+    }
+
+    /// Create an array reference with a given policy for out-of-range subscripts.
+    pub fn new_ref_with_boundary<F>(
+        ary_nm: &str,
+        ary_dim: Vec<usize>,
+        boundary: Boundary,
+        ary_sub: F,
+    ) -> Arc<Node>
+    where
+        F: for<'a> Fn(&'a [i32]) -> AryAcc + Send + Sync + 'static,
     {
         let ref_stmt = AryRef {
             name: ary_nm.to_string(),
@@ -198,12 +454,97 @@ impl Node {
             sub: Box::new(ary_sub),
             base: None,
             ref_id: None,
+            ary_id: None,
+            boundary,
+            kind: AccessKind::Read,
+            affine: None,
         };
         Node::new_node(Stmt::Ref(ref_stmt))
     }
 
+    /// Create an array reference whose subscripts are the affine form `sub`.
+    pub fn new_affine_ref(ary_nm: &str, ary_dim: Vec<usize>, sub: AffineSub) -> Arc<Node> {
+        if sub.coeffs.len() != ary_dim.len() || sub.consts.len() != ary_dim.len() {
+            panic!(
+                "affine subscripts of {} do not match its dimensions",
+                ary_nm
+            );
+        }
+        let form = sub.clone();
+        let mut node = Node::new_ref(ary_nm, ary_dim, move |ivec| form.eval(ivec));
+        let fresh = Arc::get_mut(&mut node).unwrap();
+        *fresh.ref_only_mut_ref(|a_ref| &mut a_ref.affine).unwrap() = Some(sub);
+        node
+    }
+
+    /// Create an array reference that writes its element, e.g. the target of an assignment.
+    pub fn new_write_ref<F>(ary_nm: &str, ary_dim: Vec<usize>, ary_sub: F) -> Arc<Node>
+    where
+        F: for<'a> Fn(&'a [i32]) -> AryAcc + Send + Sync + 'static,
+    {
+        Node::into_write(Node::new_ref(ary_nm, ary_dim, ary_sub))
+    }
+
+    /// Turn a reference that was just created into a write.
+    pub fn into_write(mut node: Arc<Node>) -> Arc<Node> {
+        let fresh = Arc::get_mut(&mut node).expect("the reference is already shared");
+        *fresh.ref_only_mut_ref(|a_ref| &mut a_ref.kind).unwrap() = AccessKind::Write;
+        node
+    }
+
+    /// Create an indirect reference such as `x[col[k]]`.  The result is a block of two
+    /// references: the read of the index array at position `index_sub(ivec)`, then the
+    /// read of `ary_nm` whose subscript `ary_sub(ivec, value)` sees the value just read.
+    pub fn new_indirect_ref<F, G>(
+        ary_nm: &str,
+        ary_dim: Vec<usize>,
+        index: &IndexArray,
+        index_sub: F,
+        ary_sub: G,
+    ) -> Arc<Node>
+    where
+        F: for<'a> Fn(&'a [i32]) -> i64 + Send + Sync + 'static,
+        G: for<'a> Fn(&'a [i32], i64) -> AryAcc + Send + Sync + 'static,
+    {
+        let index_sub = Arc::new(index_sub);
+        let read_index = Node::new_ref(&index.name, vec![index.len()], {
+            let index_sub = Arc::clone(&index_sub);
+            move |iv| vec![index_sub(iv)]
+        });
+        let values = index.clone();
+        let read_ary = Node::new_ref(ary_nm, ary_dim, move |iv| {
+            ary_sub(iv, values.get(index_sub(iv)))
+        });
+        Node::new_node(Stmt::Block(vec![read_index, read_ary]))
+    }
+
+    /// Create a call of `callee` with the given actual arrays and scalar arguments.
+    pub fn new_call(
+        callee: &Arc<Subprogram>,
+        arrays: &[&str],
+        scalars: Vec<LoopBound>,
+    ) -> Arc<Node> {
+        if arrays.len() != callee.arrays.len() || scalars.len() != callee.scalars {
+            panic!(
+                "{} takes {} arrays and {} scalars, got {} and {}",
+                callee.name,
+                callee.arrays.len(),
+                callee.scalars,
+                arrays.len(),
+                scalars.len()
+            );
+        }
+        Node::new_node(Stmt::Call(CallStmt {
+            callee: Arc::clone(callee),
+            arrays: arrays.iter().map(|a| a.to_string()).collect(),
+            scalars,
+            bases: vec![None; arrays.len()],
+            ary_ids: vec![None; arrays.len()],
+        }))
+    }
+
     /// Create a new Node representing a simple loop with a fixed range.
-    pub fn new_single_loop(ivar: &str, low: i32, high: i32) -> Rc<Node> {
+    pub fn new_single_loop(ivar: &str, low: i32, high: i32) -> Arc<Node> {
         let loop_stmt = LoopStmt {
             iv: ivar.to_string(),
             lb: LoopBound::Fixed(low),
@@ -217,9 +558,9 @@ impl Node {
     }
 
     /// Create a new Node representing a simple loop with a fixed range.
-    pub fn new_single_loop_dyn_ub<F>(ivar: &str, low: i32, ub: F) -> Rc<Node>
+    pub fn new_single_loop_dyn_ub<F>(ivar: &str, low: i32, ub: F) -> Arc<Node>
     where
-        for<'a> F: Fn(&'a [i32]) -> i32 + 'static,
+        for<'a> F: Fn(&'a [i32]) -> i32 + Send + Sync + 'static,
     {
         let loop_stmt = LoopStmt {
             iv: ivar.to_string(),
@@ -240,10 +581,10 @@ impl Node {
         ub: LoopBound,
         test: F,
         step: G,
-    ) -> Rc<Node>
+    ) -> Arc<Node>
     where
-        for<'a> F: Fn(i32, i32) -> bool + 'static,
-        for<'a> G: Fn(i32) -> i32 + 'static,
+        for<'a> F: Fn(i32, i32) -> bool + Send + Sync + 'static,
+        for<'a> G: Fn(i32) -> i32 + Send + Sync + 'static,
     {
         let loop_stmt = LoopStmt {
             iv: ivar.to_string(),
@@ -258,13 +599,13 @@ impl Node {
     }
 
     /// Extend the body of a loop node with another node.
-    pub fn extend_loop_body(lup: &mut Rc<Node>, stmt: &mut Rc<Node>) {
-        let lup_node = unsafe { Rc::get_mut_unchecked(lup) };
-        lup_node.loop_only_mut(|lp| lp.body.push(Rc::clone(stmt)));
+    pub fn extend_loop_body(lup: &mut Arc<Node>, stmt: &mut Arc<Node>) {
+        let lup_node = unsafe { Arc::get_mut_unchecked(lup) };
+        lup_node.loop_only_mut(|lp| lp.body.push(Arc::clone(stmt)));
 
         // officiating the parent-child relationship
-        let stmt_node = unsafe { Rc::get_mut_unchecked(stmt) };
-        stmt_node.parent = Rc::downgrade(lup);
+        let stmt_node = unsafe { Arc::get_mut_unchecked(stmt) };
+        stmt_node.parent = Arc::downgrade(lup);
     }
 
     pub fn loop_only<U, F>(&self, f: F) -> Option<U>
@@ -317,7 +658,7 @@ impl Node {
         }
     }
 
-    // pub fn loop_body<'a>(&'a self, i: usize) -> &'a Rc<Node> {
+    // pub fn loop_body<'a>(&'a self, i: usize) -> &'a Arc<Node> {
     // }
 
     pub fn get_lb(&self) -> Option<i32> {
@@ -365,13 +706,96 @@ impl Node {
                     + stmt.else_body.as_ref().map(|x| x.node_count()).unwrap_or(0)
                     + 1
             }
+            // the callee is counted where it is defined, not at every call
+            Stmt::Call(_) => 1,
+        }
+    }
+
+    /// A hash of the structure of the tree, to tell traces and checkpoints of different
+    /// programs apart: the loops with their index names and fixed or affine bounds, the
+    /// references with their arrays, dimensions, kinds, boundary policies and affine
+    /// subscripts, and the calls with their callees and arguments.  Closures cannot be
+    /// hashed, so trees that differ only in a dynamic bound, a condition or a non-affine
+    /// subscript hash the same.  The value does not change between runs or builds.
+    pub fn program_hash(&self) -> u64 {
+        let mut h = Fnv(0xcbf2_9ce4_8422_2325);
+        self.hash_structure(&mut h);
+        h.finish()
+    }
+
+    fn hash_structure<H: Hasher>(&self, h: &mut H) {
+        match &self.stmt {
+            Stmt::Loop(aloop) => {
+                0u8.hash(h);
+                aloop.iv.hash(h);
+                aloop.lb.hash_structure(h);
+                aloop.ub.hash_structure(h);
+                aloop.body.len().hash(h);
+                aloop.body.iter().for_each(|n| n.hash_structure(h));
+            }
+            Stmt::Ref(aref) => {
+                1u8.hash(h);
+                aref.name.hash(h);
+                aref.dim.hash(h);
+                aref.kind.hash(h);
+                aref.boundary.hash(h);
+                aref.affine.hash(h);
+            }
+            Stmt::Block(children) => {
+                2u8.hash(h);
+                children.len().hash(h);
+                children.iter().for_each(|n| n.hash_structure(h));
+            }
+            Stmt::Branch(stmt) => {
+                3u8.hash(h);
+                stmt.then_body.hash_structure(h);
+                stmt.else_body.is_some().hash(h);
+                if let Some(else_body) = &stmt.else_body {
+                    else_body.hash_structure(h);
+                }
+            }
+            Stmt::Call(call) => {
+                4u8.hash(h);
+                call.callee.name.hash(h);
+                call.callee.arrays.hash(h);
+                call.callee.scalars.hash(h);
+                call.callee.body.hash_structure(h);
+                call.arrays.hash(h);
+                call.scalars.len().hash(h);
+                call.scalars.iter().for_each(|s| s.hash_structure(h));
+            }
+        }
+    }
+}
+
+impl LoopBound {
+    fn hash_structure<H: Hasher>(&self, h: &mut H) {
+        match self {
+            LoopBound::Fixed(x) => (0u8, x).hash(h),
+            LoopBound::Dynamic(_) => 1u8.hash(h),
+            LoopBound::Affine { a, b } => (2u8, a, b).hash(h),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same in every build.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
         }
     }
 }
 
 // impl RefStmt {
 //     // A copy of the enclosing loops just for this reference.
-//     fn my_nest(&self) -> Rc<Node> {
+//     fn my_nest(&self) -> Arc<Node> {
 //     }
 // }
 
@@ -384,11 +808,104 @@ mod tests {
         let ar = AryRef {
             name: "X".to_string(),
             dim: vec![10],
-            sub: Box::new(|iv| vec![(iv[0] as usize) + 1]),
+            sub: Box::new(|iv| vec![(iv[0] as i64) + 1]),
             base: None,
             ref_id: None,
+            ary_id: None,
+            boundary: Boundary::Error,
+            kind: AccessKind::Read,
+            affine: None,
         };
         assert_eq!((ar.sub)(&[1]), [2]);
+        assert_eq!(ar.offset(&[1]), Some(2));
+    }
+
+    #[test]
+    fn indirect_ref() {
+        // x[col[k]]
+        let col = IndexArray::new("col", vec![3, 0, 2]);
+        let node = Node::new_indirect_ref("x", vec![4], &col, |k| k[0] as i64, |_, c| vec![c]);
+        assert_eq!(node.node_count(), 3);
+        let Stmt::Block(refs) = &node.stmt else {
+            panic!("expected a block");
+        };
+        let offsets = |k: i32| {
+            refs.iter()
+                .map(move |r| r.ref_only(|r| r.offset(&[k])).unwrap())
+        };
+        assert_eq!(offsets(0).collect::<Vec<_>>(), [Some(0), Some(3)]);
+        assert_eq!(offsets(2).collect::<Vec<_>>(), [Some(2), Some(2)]);
+        assert_eq!(refs[0].ref_only(|r| r.name.clone()).unwrap(), "col");
+    }
+
+    #[test]
+    fn call_node() {
+        // y[o + i] = y[o + i] + x[i] for i in 0..4, with the offset o as a scalar parameter
+        let mut body = Node::new_single_loop("i", 0, 4);
+        let mut y = Node::new_ref("y", vec![8], |oi| vec![(oi[0] + oi[1]) as i64]);
+        let mut x = Node::new_ref("x", vec![4], |oi| vec![oi[1] as i64]);
+        Node::extend_loop_body(&mut body, &mut y);
+        Node::extend_loop_body(&mut body, &mut x);
+        let axpy = Subprogram::new("axpy", vec![("y", vec![8]), ("x", vec![4])], 1, body);
+        assert_eq!(axpy.array_param("x"), Some(1));
+
+        let call = Node::new_call(&axpy, &["B", "A"], vec![LoopBound::Fixed(4)]);
+        assert_eq!(call.node_count(), 1);
+        let Stmt::Call(stmt) = &call.stmt else {
+            panic!("expected a call");
+        };
+        assert_eq!(stmt.arrays, ["B", "A"]);
+        assert_eq!(stmt.scalars[0].eval(&[]), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "takes 1 arrays")]
+    fn call_arity() {
+        let body = Node::new_ref("x", vec![4], |_| vec![0]);
+        let sub = Subprogram::new("first", vec![("x", vec![4])], 0, body);
+        Node::new_call(&sub, &["A", "B"], vec![]);
+    }
+
+    #[test]
+    fn index_array_from_file() {
+        let path = std::env::temp_dir().join(format!("dace_index_{}.txt", std::process::id()));
+        std::fs::write(&path, "0 2,5\n7\n").unwrap();
+        let rowptr = IndexArray::from_file("rowptr", &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(*rowptr.data, [0, 2, 5, 7]);
+        assert_eq!(rowptr.get(3), 7);
+    }
+
+    #[test]
+    fn boundary_policies() {
+        let halo = |boundary| {
+            Node::new_ref_with_boundary("X", vec![4, 4], boundary, |ij| {
+                vec![ij[0] as i64 - 1, ij[1] as i64 + 1]
+            })
+        };
+        let offsets = |node: Arc<Node>| {
+            node.ref_only(|r| [r.offset(&[0, 0]), r.offset(&[2, 1]), r.offset(&[1, 3])])
+                .unwrap()
+        };
+        assert_eq!(offsets(halo(Boundary::Clamp)), [Some(1), Some(6), Some(3)]);
+        assert_eq!(offsets(halo(Boundary::Wrap)), [Some(13), Some(6), Some(0)]);
+        assert_eq!(offsets(halo(Boundary::Skip)), [None, Some(6), None]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "subscript -1 of X[2, -1] at [2, 0] is out of range 0..4 in dimension 1"
+    )]
+    fn boundary_error() {
+        let node = Node::new_ref("X", vec![4, 4], |i| vec![i[0] as i64, i[1] as i64 - 1]);
+        node.ref_only(|r| r.offset(&[2, 0]));
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Node>();
+        assert_send_sync::<Arc<Node>>();
     }
 
     #[test]
@@ -396,15 +913,9 @@ mod tests {
         let n: usize = 100; // array dim
         let ubound = n as i32; // loop bound
                                // creating C[i,j] += A[i,k] * B[k,j]
-        let ref_c = Node::new_ref("C", vec![n, n], |ijk| {
-            vec![ijk[0] as usize, ijk[1] as usize]
-        });
-        let ref_a = Node::new_ref("A", vec![n, n], |ijk| {
-            vec![ijk[0] as usize, ijk[2] as usize]
-        });
-        let ref_b = Node::new_ref("B", vec![n, n], |ijk| {
-            vec![ijk[2] as usize, ijk[1] as usize]
-        });
+        let ref_c = Node::new_ref("C", vec![n, n], |ijk| vec![ijk[0] as i64, ijk[1] as i64]);
+        let ref_a = Node::new_ref("A", vec![n, n], |ijk| vec![ijk[0] as i64, ijk[2] as i64]);
+        let ref_b = Node::new_ref("B", vec![n, n], |ijk| vec![ijk[2] as i64, ijk[1] as i64]);
 
         // creating loop k = 0, n { s_ref }
         let mut k_loop = Node::new_single_loop("k", 0, ubound);
@@ -453,7 +964,7 @@ mod tests {
         // for i in 0..n step by 2
         let ubound = 100; // loop bound
 
-        let ref_a = Node::new_ref("A", vec![100], |i| vec![i[0] as usize]);
+        let ref_a = Node::new_ref("A", vec![100], |i| vec![i[0] as i64]);
 
         let mut branch = branch_node! {
             if (|ivec| ivec[0] & 1 == 0) {
@@ -473,9 +984,9 @@ mod tests {
         // for i in 0..n step by 2
         let ubound = 100; // loop bound
 
-        let ref_a = Node::new_ref("A", vec![100], |i| vec![i[0] as usize]);
+        let ref_a = Node::new_ref("A", vec![100], |i| vec![i[0] as i64]);
 
-        let ref_b = Node::new_ref("B", vec![100], |i| vec![i[0] as usize]);
+        let ref_b = Node::new_ref("B", vec![100], |i| vec![i[0] as i64]);
 
         let mut branch = branch_node! {
             if (|ivec| ivec[0] & 1 == 0) {
@@ -499,9 +1010,9 @@ mod tests {
         // for (int c0 = 0; c0 < n; c0 += 1)
         // for (int c1 = 0; c1 < n; c1 += 1)
         //   x1[c0] = (x1[c0] + (A[c0][c1] * y_1[c1]));
-        let ref_x1 = Node::new_ref("x1", vec![n], |ij| vec![ij[0] as usize]);
-        let ref_a = Node::new_ref("a", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-        let ref_y1 = Node::new_ref("y1", vec![n], |ij| vec![ij[1] as usize]);
+        let ref_x1 = Node::new_ref("x1", vec![n], |ij| vec![ij[0] as i64]);
+        let ref_a = Node::new_ref("a", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
+        let ref_y1 = Node::new_ref("y1", vec![n], |ij| vec![ij[1] as i64]);
 
         let mut j_loop = Node::new_single_loop("j", 0, ubound);
         [ref_x1, ref_a, ref_y1]
@@ -514,6 +1025,136 @@ mod tests {
         assert_eq!(i_loop.node_count(), 5);
     }
 
+    #[test]
+    fn example_dace_macro() {
+        // same nest as matmul() above
+        let n: usize = 100;
+        let i_loop = crate::dace! {
+            let C[n][n];
+            let A[n][n];
+            let B[n][n];
+            for i in 0..n {
+                for j in 0..n {
+                    for k in 0..n {
+                        C[i][j] = A[i][k] * B[k][j];
+                    }
+                }
+            }
+        };
+        assert_eq!(i_loop.node_count(), 6);
+        let j_loop = i_loop.loop_only(|lp| lp.body[0].clone()).unwrap();
+        let k_loop = j_loop.loop_only(|lp| lp.body[0].clone()).unwrap();
+        let names: Vec<_> = k_loop
+            .loop_only(|lp| {
+                lp.body
+                    .iter()
+                    .map(|r| r.ref_only(|r| r.name.clone()).unwrap())
+                    .collect()
+            })
+            .unwrap();
+        assert_eq!(names, ["C", "A", "B"]);
+        let kinds = k_loop
+            .loop_only(|lp| {
+                lp.body
+                    .iter()
+                    .map(|r| r.ref_only(|r| r.kind).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap();
+        assert_eq!(
+            kinds,
+            [AccessKind::Write, AccessKind::Read, AccessKind::Read]
+        );
+        let sub = k_loop.loop_only(|lp| lp.body[2].clone()).unwrap();
+        assert_eq!(sub.ref_only(|r| (r.sub)(&[1, 2, 3])).unwrap(), [3, 2]);
+    }
+
+    #[test]
+    fn dace_macro_errors() {
+        // an undeclared array, a subscript that is not an expression of loop indices, and
+        // bad loop ranges are reported at their spans, as in the `.stderr` files
+        trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+    }
+
+    #[test]
+    fn dace_macro_bounds() {
+        let n = 10;
+        let tree = crate::dace! {
+            let A[n][n];
+            for i in 0..n {
+                for j in (i + 1)..=n {
+                    if (i + j) % 2 == 0 {
+                        A[i][j - 1];
+                    } else {
+                        A[j - 1][i];
+                    }
+                }
+                for j in (0..i * i).step_by(2) {
+                    A[i][0];
+                }
+            }
+        };
+        assert_eq!(tree.node_count(), 7);
+        let (lb, ub) = tree
+            .loop_only(|lp| {
+                lp.body[0].loop_only(|j| (format!("{:?}", j.lb), format!("{:?}", j.ub)))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(lb, "Affine([1], 1)");
+        assert_eq!(ub, "Fixed(11)");
+        let ub = tree
+            .loop_only(|lp| lp.body[1].loop_only(|j| format!("{:?}", j.ub)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ub, "Dynamic");
+    }
+
+    #[test]
+    fn program_hash() {
+        let nest = |n: usize, iv: &str| {
+            let mut aref = Node::new_ref("A", vec![n], |i| vec![i[0] as i64]);
+            let mut aloop = Node::new_single_loop(iv, 0, n as i32);
+            Node::extend_loop_body(&mut aloop, &mut aref);
+            aloop
+        };
+        let mut based = nest(10, "i");
+        crate::arybase::set_arybase(&mut based);
+        assert_eq!(nest(10, "i").program_hash(), based.program_hash());
+        assert_ne!(nest(10, "i").program_hash(), nest(11, "i").program_hash());
+        assert_ne!(nest(10, "i").program_hash(), nest(10, "j").program_hash());
+    }
+
+    #[test]
+    fn dace_macro_usize_scalars() {
+        let n: usize = 6;
+        let half: usize = n / 2;
+        let tree = crate::dace! {
+            let A[n];
+            for i in 0..n {
+                if i < half && i + 1 != n {
+                    A[i];
+                }
+                for j in 0..(i * i) % n {
+                    A[(i * j) % n];
+                }
+            }
+        };
+        let cond = tree
+            .loop_only(|lp| match &lp.body[0].stmt {
+                Stmt::Branch(b) => (0..n as i32).filter(|&i| (b.cond)(&[i])).count(),
+                _ => panic!("expected a branch"),
+            })
+            .unwrap();
+        assert_eq!(cond, half);
+        let sub = tree
+            .loop_only(|lp| lp.body[1].loop_only(|j| j.body[0].ref_only(|r| (r.sub)(&[5, 4]))))
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(sub, [2]);
+    }
+
     // #[test]
     // fn mat_transpose2() {
     //     let n: usize = 1024;
@@ -0,0 +1,646 @@
+//! Lowering of a loop tree into a flat loop program.
+//!
+//! `compile` turns the tree into a list of operations with jumps, so that running it needs
+//! no recursion and no walk over `Node`s.  Calls are inlined and their array bindings
+//! resolved once, at compile time.  A reference with affine subscripts (`AryRef::affine`)
+//! keeps its subscripts and its row-major offset in registers: they are computed in full
+//! when its innermost enclosing loop is entered, and each step of that loop adds the
+//! precomputed stride instead of calling the subscript closure.  Other references fall
+//! back to `AryRef::offset`.
+
+use crate::ast::*;
+use crate::record::CallFrame;
+use std::sync::Arc;
+
+#[allow(clippy::type_complexity)]
+enum Op<'a> {
+    /// Evaluate the bounds of a loop at iteration vector position `pos`, then enter it
+    /// and set up `refs`, or jump to `end` if it runs no iteration.
+    Loop {
+        stmt: &'a LoopStmt,
+        start: usize,
+        pos: usize,
+        refs: Vec<usize>,
+        end: usize,
+    },
+    /// End of a loop body: step the index and move each register of `steps` by its
+    /// stride times the change of the index, then jump back to `body`, or leave the loop.
+    Next {
+        stmt: &'a LoopStmt,
+        pos: usize,
+        steps: Vec<(usize, i64)>,
+        body: usize,
+    },
+    /// An access of the reference in slot `slot`, computed with `AryRef::offset`.
+    Access(usize),
+    Affine(AffineAccess<'a>),
+    /// A whole innermost loop at position `pos` whose body only has affine accesses.
+    /// See `Program::inner`.
+    Inner {
+        stmt: &'a LoopStmt,
+        start: usize,
+        pos: usize,
+        refs: Vec<usize>,
+        body: Vec<AffineAccess<'a>>,
+    },
+    /// Jump to `target` unless the condition holds.
+    Branch {
+        cond: &'a (dyn Fn(&[i32]) -> bool + Send + Sync),
+        start: usize,
+        target: usize,
+    },
+    Jump(usize),
+    /// Push the scalar arguments of a call, evaluated at the caller's iteration vector
+    /// from `start`, and set up `refs`.
+    Call {
+        scalars: &'a [LoopBound],
+        start: usize,
+        refs: Vec<usize>,
+    },
+    /// Drop the callee's iteration vector, which begins at `start`.
+    Return {
+        start: usize,
+    },
+}
+
+/// An access of an affine reference, whose address is in register `reg` as long as its
+/// subscripts, in the next registers, are within `dims`.
+struct AffineAccess<'a> {
+    reg: usize,
+    dims: &'a [usize],
+    ref_id: usize,
+    slot: usize,
+}
+
+/// A reference as seen from one place in the program; a subprogram called twice has
+/// a slot per call for each of its references.
+struct Slot<'a> {
+    ary_ref: &'a AryRef,
+    ref_id: usize,
+    base: usize,
+    /// Where the iteration vector of the reference begins, non-zero inside calls.
+    start: usize,
+    affine: Option<Strides>,
+}
+
+/// An affine subscript form over the whole iteration vector, calls included.
+struct Strides {
+    /// Register of the address, followed by one register per subscript.
+    first: usize,
+    dims: Vec<i64>,
+    coeffs: Vec<Vec<i64>>,
+    consts: Vec<i64>,
+    /// Change of the address for a unit step of each loop index.
+    lin: Vec<i64>,
+}
+
+/// A compiled loop tree, borrowing the closures of the tree.
+pub struct Program<'a> {
+    ops: Vec<Op<'a>>,
+    slots: Vec<Slot<'a>>,
+    /// References set up before the first operation.
+    top: Vec<usize>,
+    /// Longest iteration vector.
+    depth: usize,
+    /// Number of offset and subscript registers.
+    regs: usize,
+}
+
+struct Compiler<'a> {
+    ops: Vec<Op<'a>>,
+    slots: Vec<Slot<'a>>,
+    frames: Vec<(CallFrame<'a>, usize)>,
+    /// Affine references of the innermost loop or call being compiled.
+    scope: Vec<usize>,
+    depth: usize,
+    regs: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn start(&self) -> usize {
+        self.frames.last().map_or(0, |f| f.1)
+    }
+
+    /// Compile `node`, executed with `len` indices in the iteration vector.
+    fn node(&mut self, node: &'a Node, len: usize) {
+        match &node.stmt {
+            Stmt::Ref(ary_ref) => {
+                let slot = self.slot(ary_ref, len);
+                match &self.slots[slot].affine {
+                    Some(a) => self.ops.push(Op::Affine(AffineAccess {
+                        reg: a.first,
+                        dims: &ary_ref.dim,
+                        ref_id: self.slots[slot].ref_id,
+                        slot,
+                    })),
+                    None => self.ops.push(Op::Access(slot)),
+                }
+            }
+            Stmt::Loop(aloop) => {
+                self.depth = self.depth.max(len + 1);
+                let at = self.ops.len();
+                self.ops.push(Op::Loop {
+                    stmt: aloop,
+                    start: self.start(),
+                    pos: len,
+                    refs: vec![],
+                    end: 0,
+                });
+                let outer = std::mem::take(&mut self.scope);
+                aloop.body.iter().for_each(|n| self.node(n, len + 1));
+                let refs = std::mem::replace(&mut self.scope, outer);
+                let mut steps = vec![];
+                for &r in refs.iter() {
+                    let a = self.slots[r].affine.as_ref().unwrap();
+                    steps.push((a.first, a.lin[len]));
+                    for (d, c) in a.coeffs.iter().enumerate() {
+                        steps.push((a.first + 1 + d, c[len]));
+                    }
+                }
+                steps.retain(|&(_, stride)| stride != 0);
+                if self.ops[at + 1..]
+                    .iter()
+                    .all(|op| matches!(op, Op::Affine(_)))
+                {
+                    let body = self
+                        .ops
+                        .drain(at + 1..)
+                        .map(|op| match op {
+                            Op::Affine(access) => access,
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                    self.ops[at] = Op::Inner {
+                        stmt: aloop,
+                        start: self.start(),
+                        pos: len,
+                        refs,
+                        body,
+                    };
+                    return;
+                }
+                self.ops.push(Op::Next {
+                    stmt: aloop,
+                    pos: len,
+                    steps,
+                    body: at + 1,
+                });
+                let next = self.ops.len();
+                if let Op::Loop { refs: r, end, .. } = &mut self.ops[at] {
+                    *r = refs;
+                    *end = next;
+                }
+            }
+            Stmt::Block(blk) => blk.iter().for_each(|n| self.node(n, len)),
+            Stmt::Branch(stmt) => {
+                let at = self.ops.len();
+                self.ops.push(Op::Branch {
+                    cond: stmt.cond.as_ref(),
+                    start: self.start(),
+                    target: 0,
+                });
+                self.node(&stmt.then_body, len);
+                let mut jump = None;
+                if stmt.else_body.is_some() {
+                    jump = Some(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                }
+                let else_pc = self.ops.len();
+                if let Op::Branch { target, .. } = &mut self.ops[at] {
+                    *target = else_pc;
+                }
+                if let Some(else_body) = &stmt.else_body {
+                    self.node(else_body, len);
+                    let end = self.ops.len();
+                    self.ops[jump.unwrap()] = Op::Jump(end);
+                }
+            }
+            Stmt::Call(call) => {
+                let frame = CallFrame::new(call, self.frames.last().map(|f| &f.0));
+                let at = self.ops.len();
+                self.ops.push(Op::Call {
+                    scalars: &call.scalars,
+                    start: self.start(),
+                    refs: vec![],
+                });
+                self.frames.push((frame, len));
+                let outer = std::mem::take(&mut self.scope);
+                let callee_len = len + call.scalars.len();
+                self.depth = self.depth.max(callee_len);
+                self.node(&call.callee.body, callee_len);
+                let refs = std::mem::replace(&mut self.scope, outer);
+                self.frames.pop();
+                self.ops.push(Op::Return { start: len });
+                if let Op::Call { refs: r, .. } = &mut self.ops[at] {
+                    *r = refs;
+                }
+            }
+        }
+    }
+
+    fn slot(&mut self, ary_ref: &'a AryRef, len: usize) -> usize {
+        let start = self.start();
+        let base = match self.frames.last() {
+            Some((frame, _)) => frame.array(&ary_ref.name).0,
+            None => ary_ref
+                .base
+                .expect("array bases must be set by set_arybase"),
+        };
+        let affine = ary_ref.affine.as_ref().map(|sub| {
+            let dims: Vec<i64> = ary_ref.dim.iter().map(|&d| d as i64).collect();
+            // coefficients past the reference's own loops never see an index
+            let coeffs: Vec<Vec<i64>> = sub
+                .coeffs
+                .iter()
+                .map(|c| {
+                    let mut global = vec![0; len];
+                    for (k, &a) in c.iter().take(len - start).enumerate() {
+                        global[start + k] = a;
+                    }
+                    global
+                })
+                .collect();
+            let mut lin = vec![0; len];
+            let mut stride = 1;
+            for (d, c) in coeffs.iter().enumerate().rev() {
+                lin.iter_mut().zip(c).for_each(|(l, a)| *l += a * stride);
+                stride *= dims[d];
+            }
+            let first = self.regs;
+            self.regs += 1 + dims.len();
+            Strides {
+                first,
+                dims,
+                coeffs,
+                consts: sub.consts.clone(),
+                lin,
+            }
+        });
+        let slot = self.slots.len();
+        if affine.is_some() {
+            self.scope.push(slot);
+        }
+        self.slots.push(Slot {
+            ary_ref,
+            ref_id: ary_ref.ref_id.expect("ref ids must be set by set_arybase"),
+            base,
+            start,
+            affine,
+        });
+        slot
+    }
+}
+
+/// Compile a loop tree whose array bases and ref ids have been set with `set_arybase`.
+pub fn compile(root: &Arc<Node>) -> Program<'_> {
+    let mut c = Compiler {
+        ops: vec![],
+        slots: vec![],
+        frames: vec![],
+        scope: vec![],
+        depth: 0,
+        regs: 0,
+    };
+    c.node(root, 0);
+    Program {
+        ops: c.ops,
+        slots: c.slots,
+        top: c.scope,
+        depth: c.depth,
+        regs: c.regs,
+    }
+}
+
+impl Program<'_> {
+    /// Run the program, calling `emit(elem, ref_id)` for every access in trace order,
+    /// with the same element addresses as `TraceIter` gives.
+    pub fn run<F: FnMut(usize, usize)>(&self, mut emit: F) {
+        let mut ivec: Vec<i32> = Vec::with_capacity(self.depth);
+        let mut ubs = vec![0; self.depth];
+        let mut regs = vec![0; self.regs];
+        self.set_up(&self.top, &ivec, &mut regs);
+        let mut pc = 0;
+        while let Some(op) = self.ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Affine(access) => {
+                    let sub = &regs[access.reg + 1..access.reg + 1 + access.dims.len()];
+                    self.access(access, regs[access.reg], sub, &ivec, &mut emit);
+                }
+                Op::Inner {
+                    stmt,
+                    start,
+                    pos,
+                    refs,
+                    body,
+                } => {
+                    let lb = stmt.lb.eval(&ivec[*start..]);
+                    let ub = stmt.ub.eval(&ivec[*start..]);
+                    if (stmt.test)(lb, ub) {
+                        // the registers hold the accesses at index 0 of the loop
+                        ivec.push(0);
+                        self.set_up(refs, &ivec, &mut regs);
+                        self.inner(stmt, (lb, ub), *pos, body, &regs, &mut ivec, &mut emit);
+                        ivec.pop();
+                    }
+                }
+                Op::Access(r) => {
+                    let slot = &self.slots[*r];
+                    if let Some(index) = slot.ary_ref.offset(&ivec[slot.start..]) {
+                        emit(slot.base + index, slot.ref_id);
+                    }
+                }
+                Op::Loop {
+                    stmt,
+                    start,
+                    pos,
+                    refs,
+                    end,
+                } => {
+                    let lb = stmt.lb.eval(&ivec[*start..]);
+                    let ub = stmt.ub.eval(&ivec[*start..]);
+                    if (stmt.test)(lb, ub) {
+                        ivec.push(lb);
+                        ubs[*pos] = ub;
+                        self.set_up(refs, &ivec, &mut regs);
+                    } else {
+                        pc = *end;
+                    }
+                }
+                Op::Next {
+                    stmt,
+                    pos,
+                    steps,
+                    body,
+                } => {
+                    let old = ivec[*pos];
+                    let new = (stmt.step)(old);
+                    if (stmt.test)(new, ubs[*pos]) {
+                        ivec[*pos] = new;
+                        let delta = (new - old) as i64;
+                        for &(reg, stride) in steps.iter() {
+                            regs[reg] += delta * stride;
+                        }
+                        pc = *body;
+                    } else {
+                        ivec.pop();
+                    }
+                }
+                Op::Branch {
+                    cond,
+                    start,
+                    target,
+                } => {
+                    if !cond(&ivec[*start..]) {
+                        pc = *target;
+                    }
+                }
+                Op::Jump(target) => pc = *target,
+                Op::Call {
+                    scalars,
+                    start,
+                    refs,
+                } => {
+                    let values: Vec<i32> =
+                        scalars.iter().map(|s| s.eval(&ivec[*start..])).collect();
+                    ivec.extend(values);
+                    self.set_up(refs, &ivec, &mut regs);
+                }
+                Op::Return { start } => ivec.truncate(*start),
+            }
+        }
+    }
+
+    /// Run an innermost loop from `lb` to `ub`.  The indices are taken in chunks; an
+    /// access whose subscripts are within bounds at the least and greatest index of a
+    /// chunk is within bounds over the whole chunk, since its subscripts are affine in the
+    /// index, so its addresses are simply `address at 0 + index * stride`.
+    #[allow(clippy::too_many_arguments)]
+    fn inner<F: FnMut(usize, usize)>(
+        &self,
+        stmt: &LoopStmt,
+        (lb, ub): (i32, i32),
+        pos: usize,
+        body: &[AffineAccess],
+        regs: &[i64],
+        ivec: &mut [i32],
+        emit: &mut F,
+    ) {
+        const CHUNK: usize = 1024;
+        let mut indices = Vec::with_capacity(CHUNK);
+        let mut lanes = Vec::with_capacity(body.len());
+        let mut sub = vec![];
+        let mut i = lb;
+        let mut more = true;
+        while more {
+            indices.clear();
+            while more && indices.len() < CHUNK {
+                indices.push(i);
+                i = (stmt.step)(i);
+                more = (stmt.test)(i, ub);
+            }
+            let lo = *indices.iter().min().unwrap() as i64;
+            let hi = *indices.iter().max().unwrap() as i64;
+            lanes.clear();
+            for access in body {
+                let a = self.slots[access.slot].affine.as_ref().unwrap();
+                let safe = a
+                    .coeffs
+                    .iter()
+                    .zip(access.dims)
+                    .enumerate()
+                    .all(|(d, (c, &dim))| {
+                        let at = |i: i64| regs[access.reg + 1 + d] + c[pos] * i;
+                        (at(lo) as u64) < dim as u64 && (at(hi) as u64) < dim as u64
+                    });
+                lanes.push((regs[access.reg], a.lin[pos], access.ref_id, safe));
+            }
+            if lanes.iter().all(|lane| lane.3) {
+                for &i in indices.iter() {
+                    for &(addr, stride, ref_id, _) in lanes.iter() {
+                        emit((addr + stride * i as i64) as usize, ref_id);
+                    }
+                }
+                continue;
+            }
+            for &i in indices.iter() {
+                ivec[pos] = i;
+                for (access, &(addr, stride, ref_id, safe)) in body.iter().zip(lanes.iter()) {
+                    if safe {
+                        emit((addr + stride * i as i64) as usize, ref_id);
+                        continue;
+                    }
+                    let a = self.slots[access.slot].affine.as_ref().unwrap();
+                    sub.clear();
+                    sub.extend(
+                        a.coeffs
+                            .iter()
+                            .enumerate()
+                            .map(|(d, c)| regs[access.reg + 1 + d] + c[pos] * i as i64),
+                    );
+                    self.access(access, addr + stride * i as i64, &sub, ivec, emit);
+                }
+            }
+        }
+    }
+
+    /// An affine access with address `addr` and subscripts `sub`, which the boundary
+    /// policy takes care of when they are out of bounds.
+    #[inline(always)]
+    fn access<F: FnMut(usize, usize)>(
+        &self,
+        access: &AffineAccess,
+        addr: i64,
+        sub: &[i64],
+        ivec: &[i32],
+        emit: &mut F,
+    ) {
+        if sub
+            .iter()
+            .zip(access.dims)
+            .all(|(&i, &d)| (i as u64) < d as u64)
+        {
+            emit(addr as usize, access.ref_id);
+        } else {
+            let slot = &self.slots[access.slot];
+            if let Some(index) = slot.ary_ref.offset_of(sub, &ivec[slot.start..]) {
+                emit(slot.base + index, access.ref_id);
+            }
+        }
+    }
+
+    /// Compute the offsets and subscripts of `refs` in full.
+    fn set_up(&self, refs: &[usize], ivec: &[i32], regs: &mut [i64]) {
+        for &r in refs {
+            let a = self.slots[r].affine.as_ref().unwrap();
+            let mut off = 0;
+            for (d, c) in a.coeffs.iter().enumerate() {
+                let i = a.consts[d] + c.iter().zip(ivec).map(|(&a, &i)| a * i as i64).sum::<i64>();
+                regs[a.first + 1 + d] = i;
+                off = off * a.dims[d] + i;
+            }
+            regs[a.first] = self.slots[r].base as i64 + off;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arybase::set_arybase;
+    use crate::iter::TraceIter;
+
+    fn same_trace(tree: &Arc<Node>) {
+        let expected: Vec<_> = TraceIter::new(tree).map(|r| (r.elem, r.ref_id)).collect();
+        let mut trace = vec![];
+        compile(tree).run(|elem, ref_id| trace.push((elem, ref_id)));
+        assert_eq!(trace, expected);
+    }
+
+    #[test]
+    fn affine_nest() {
+        let n: usize = 12;
+        let mut tree = crate::dace! {
+            let A[n][n];
+            let B[n][n];
+            for i in 0..n {
+                for j in (i + 1)..=n {
+                    if (i + j) % 2 == 0 {
+                        A[i][j - 1] = B[j - 1][i];
+                    } else {
+                        A[j - 1][i] += B[i * j % 7][0];
+                    }
+                }
+                for j in (0..i).step_by(3) {
+                    B[i][n - 1 - j] = A[j][i] + A[n - 1 - i][j];
+                }
+            }
+        };
+        set_arybase(&mut tree);
+        same_trace(&tree);
+    }
+
+    #[test]
+    fn boundaries() {
+        // i = 0, 6 { a[i - 1] (clamp) a[i + 1] (wrap) a[2i - 3] (skip) }, and the same
+        // with b[i] after, which keeps the loop from running as an innermost loop
+        for with_b in [false, true] {
+            let mut refs = [
+                (Boundary::Clamp, 1, -1),
+                (Boundary::Wrap, 1, 1),
+                (Boundary::Skip, 2, -3),
+            ]
+            .map(|(boundary, a, b)| {
+                let sub = AffineSub {
+                    coeffs: vec![vec![a]],
+                    consts: vec![b],
+                };
+                let mut node = Node::new_affine_ref("A", vec![5], sub);
+                let fresh = Arc::get_mut(&mut node).unwrap();
+                *fresh.ref_only_mut_ref(|r| &mut r.boundary).unwrap() = boundary;
+                node
+            });
+            let mut iloop = Node::new_single_loop("i", 0, 6);
+            refs.iter_mut()
+                .for_each(|r| Node::extend_loop_body(&mut iloop, r));
+            if with_b {
+                let mut bref = Node::new_ref("B", vec![6], |i| vec![i[0] as i64]);
+                Node::extend_loop_body(&mut iloop, &mut bref);
+            }
+            set_arybase(&mut iloop);
+            same_trace(&iloop);
+        }
+    }
+
+    #[test]
+    fn long_loop() {
+        // i = 0, 3000, 7 { a[i - 2000] (clamp) } goes out of bounds in the middle of a chunk
+        let sub = AffineSub {
+            coeffs: vec![vec![1]],
+            consts: vec![-2000],
+        };
+        let mut aref = Node::new_affine_ref("A", vec![100], sub);
+        let fresh = Arc::get_mut(&mut aref).unwrap();
+        *fresh.ref_only_mut_ref(|r| &mut r.boundary).unwrap() = Boundary::Clamp;
+        let mut iloop = Node::new_single_loop_general(
+            "i",
+            LoopBound::Fixed(0),
+            LoopBound::Fixed(3000),
+            |i, ub| i < ub,
+            |i| i + 7,
+        );
+        Node::extend_loop_body(&mut iloop, &mut aref);
+        set_arybase(&mut iloop);
+        same_trace(&iloop);
+    }
+
+    #[test]
+    fn calls() {
+        // sub(X, s) { k = 0, s { X[k][s - k] } }
+        // i = 0, 4 { sub(A, i) sub(B, i + 1) }
+        let mut body = Node::new_single_loop_general(
+            "k",
+            LoopBound::Fixed(0),
+            LoopBound::Affine { a: vec![1], b: 0 },
+            |k, ub| k < ub,
+            |k| k + 1,
+        );
+        let mut x = Node::new_affine_ref(
+            "X",
+            vec![8, 8],
+            AffineSub {
+                coeffs: vec![vec![0, 1], vec![1, -1]],
+                consts: vec![0, 0],
+            },
+        );
+        Node::extend_loop_body(&mut body, &mut x);
+        let sub = Subprogram::new("sub", vec![("X", vec![8, 8])], 1, body);
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        let mut a = Node::new_call(&sub, &["A"], vec![LoopBound::Affine { a: vec![1], b: 0 }]);
+        let mut b = Node::new_call(&sub, &["B"], vec![LoopBound::Affine { a: vec![1], b: 1 }]);
+        Node::extend_loop_body(&mut iloop, &mut a);
+        Node::extend_loop_body(&mut iloop, &mut b);
+        set_arybase(&mut iloop);
+        same_trace(&iloop);
+    }
+}
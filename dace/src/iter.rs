@@ -1,14 +1,16 @@
 use crate::ast::*;
+use crate::record::{AccessRecord, CallFrame, Layout};
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct Walk {
     // usize is the current body statement index, if there is any
-    stack: Vec<(Rc<Node>, usize)>,
+    stack: Vec<(Arc<Node>, usize)>,
 }
 
 impl Walk {
-    pub fn new(root: &Rc<Node>) -> Self {
+    pub fn new(root: &Arc<Node>) -> Self {
         Walk {
             stack: vec![(root.clone(), 0)],
         }
@@ -16,7 +18,7 @@ impl Walk {
         //     if lp.body.borrow().len() > 0 { Some(0) } else { None } }))] }
     }
 
-    fn step(&mut self) -> Option<Rc<Node>> {
+    fn step(&mut self) -> Option<Arc<Node>> {
         match self.stack.last().cloned() {
             None => None, // stack already empty
             Some((node, visited)) => {
@@ -34,7 +36,8 @@ impl Walk {
                             self.stack.push((children.body[visited].clone(), 0));
                         }
                     }
-                    Stmt::Ref(_) => {
+                    // the body of a callee is a separate tree and is not walked
+                    Stmt::Ref(_) | Stmt::Call(_) => {
                         self.stack.pop();
                     }
                     Stmt::Block(children) => {
@@ -74,7 +77,7 @@ impl Walk {
 }
 
 impl Iterator for Walk {
-    type Item = Rc<Node>;
+    type Item = Arc<Node>;
     fn next(&mut self) -> Option<Self::Item> {
         while !self.stack.is_empty() {
             if let Some(x) = self.step() {
@@ -85,9 +88,314 @@ impl Iterator for Walk {
     }
 }
 
+/// Position of the traversal inside one statement.
+enum Cursor<'a> {
+    /// `owner` is the block or call node whose body is run, `None` for the root.
+    Block {
+        body: &'a [Arc<Node>],
+        pos: usize,
+        owner: Option<&'a Node>,
+    },
+    Loop {
+        node: &'a Node,
+        stmt: &'a LoopStmt,
+        ub: i32,
+        pos: usize,
+    },
+    /// End of a call: pops its frame.
+    Return,
+}
+
+/// Where a `TraceIter` is, with nodes named by their position in a pre-order walk of
+/// the tree that also goes into the body of every subprogram, once.  It holds no
+/// reference into the tree, so it can be written to a file and resumed by another run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceState {
+    pub cursors: Vec<SavedCursor>,
+    pub ivec: Vec<i32>,
+    /// The call nodes being executed, each with the position in `ivec` where the
+    /// callee's iteration vector starts.
+    pub calls: Vec<(usize, usize)>,
+    pub time: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavedCursor {
+    Block { owner: Option<usize>, pos: usize },
+    Loop { node: usize, ub: i32, pos: usize },
+    Return,
+}
+
+/// The nodes of `root` and of the subprograms it calls, in pre-order.
+fn number_nodes(root: &Arc<Node>) -> Vec<&Node> {
+    fn visit<'a>(node: &'a Node, seen: &mut HashSet<*const Node>, out: &mut Vec<&'a Node>) {
+        if !seen.insert(node) {
+            return;
+        }
+        out.push(node);
+        match &node.stmt {
+            Stmt::Ref(_) => {}
+            Stmt::Loop(aloop) => aloop.body.iter().for_each(|n| visit(n, seen, out)),
+            Stmt::Block(blk) => blk.iter().for_each(|n| visit(n, seen, out)),
+            Stmt::Branch(stmt) => {
+                visit(&stmt.then_body, seen, out);
+                if let Some(else_body) = &stmt.else_body {
+                    visit(else_body, seen, out);
+                }
+            }
+            Stmt::Call(call) => visit(&call.callee.body, seen, out),
+        }
+    }
+    let mut out = vec![];
+    visit(root, &mut HashSet::new(), &mut out);
+    out
+}
+
+/// Lazily enumerates the accesses of a loop tree, in execution order.
+///
+/// The loops are run by an explicit stack of cursors rather than by recursion, and nothing
+/// is stored per access, so traces of any length take constant memory.  Array bases must
+/// have been set with `set_arybase`.
+pub struct TraceIter<'a> {
+    root: &'a Arc<Node>,
+    stack: Vec<Cursor<'a>>,
+    ivec: Vec<i32>,
+    /// Frames of the calls being executed, each with the position in `ivec` where the
+    /// callee's iteration vector starts, and the call node.
+    calls: Vec<(CallFrame<'a>, usize, &'a Node)>,
+    time: usize,
+    layout: Layout,
+}
+
+impl<'a> TraceIter<'a> {
+    pub fn new(root: &'a Arc<Node>) -> Self {
+        TraceIter::with_layout(root, Layout::default())
+    }
+
+    pub fn with_layout(root: &'a Arc<Node>, layout: Layout) -> Self {
+        TraceIter {
+            root,
+            stack: vec![Cursor::Block {
+                body: std::slice::from_ref(root),
+                pos: 0,
+                owner: None,
+            }],
+            ivec: vec![],
+            calls: vec![],
+            time: 0,
+            layout,
+        }
+    }
+
+    /// The position of the traversal, to continue from with `resume`.
+    pub fn state(&self) -> TraceState {
+        let ids: HashMap<*const Node, usize> = number_nodes(self.root)
+            .into_iter()
+            .enumerate()
+            .map(|(id, node)| (node as *const Node, id))
+            .collect();
+        let id = |node: &Node| ids[&(node as *const Node)];
+        TraceState {
+            cursors: self
+                .stack
+                .iter()
+                .map(|cursor| match cursor {
+                    Cursor::Block { pos, owner, .. } => SavedCursor::Block {
+                        owner: owner.map(id),
+                        pos: *pos,
+                    },
+                    Cursor::Loop { node, ub, pos, .. } => SavedCursor::Loop {
+                        node: id(node),
+                        ub: *ub,
+                        pos: *pos,
+                    },
+                    Cursor::Return => SavedCursor::Return,
+                })
+                .collect(),
+            ivec: self.ivec.clone(),
+            calls: self.calls.iter().map(|c| (id(c.2), c.1)).collect(),
+            time: self.time,
+        }
+    }
+
+    /// Continue a traversal of `root` from `state`, which a `TraceIter` over the same tree
+    /// gave, possibly in another run.
+    pub fn resume(root: &'a Arc<Node>, layout: Layout, state: &TraceState) -> Self {
+        let nodes = number_nodes(root);
+        let node = |id: usize| -> &'a Node {
+            nodes
+                .get(id)
+                .copied()
+                .unwrap_or_else(|| panic!("node {} is not in the tree", id))
+        };
+        let stack = state
+            .cursors
+            .iter()
+            .map(|cursor| match *cursor {
+                SavedCursor::Block { owner: None, pos } => Cursor::Block {
+                    body: std::slice::from_ref(root),
+                    pos,
+                    owner: None,
+                },
+                SavedCursor::Block {
+                    owner: Some(id),
+                    pos,
+                } => {
+                    let owner = node(id);
+                    let body = match &owner.stmt {
+                        Stmt::Block(blk) => blk.as_slice(),
+                        Stmt::Call(call) => std::slice::from_ref(&call.callee.body),
+                        _ => panic!("node {} is not a block or a call", id),
+                    };
+                    Cursor::Block {
+                        body,
+                        pos,
+                        owner: Some(owner),
+                    }
+                }
+                SavedCursor::Loop { node: id, ub, pos } => {
+                    let Stmt::Loop(stmt) = &node(id).stmt else {
+                        panic!("node {} is not a loop", id);
+                    };
+                    Cursor::Loop {
+                        node: node(id),
+                        stmt,
+                        ub,
+                        pos,
+                    }
+                }
+                SavedCursor::Return => Cursor::Return,
+            })
+            .collect();
+        let mut calls: Vec<(CallFrame<'a>, usize, &'a Node)> = vec![];
+        for &(id, start) in state.calls.iter() {
+            let Stmt::Call(call) = &node(id).stmt else {
+                panic!("node {} is not a call", id);
+            };
+            let frame = CallFrame::new(call, calls.last().map(|f| &f.0));
+            calls.push((frame, start, node(id)));
+        }
+        TraceIter {
+            root,
+            stack,
+            ivec: state.ivec.clone(),
+            calls,
+            time: state.time,
+            layout,
+        }
+    }
+
+    /// The iteration vector of the statement being executed.
+    fn ivec(&self) -> &[i32] {
+        &self.ivec[self.calls.last().map_or(0, |f| f.1)..]
+    }
+
+    /// Start executing `node`.  Returns the record if `node` is an access.
+    fn enter(&mut self, node: &'a Node) -> Option<AccessRecord> {
+        match &node.stmt {
+            Stmt::Ref(ary_ref) => {
+                let frame = self.calls.last().map(|f| &f.0);
+                let rec = AccessRecord::new(ary_ref, self.ivec(), frame, self.time, &self.layout)?;
+                self.time += 1;
+                Some(rec)
+            }
+            Stmt::Loop(aloop) => {
+                let lb = aloop.lb.eval(self.ivec());
+                let ub = aloop.ub.eval(self.ivec());
+                if (aloop.test)(lb, ub) {
+                    self.ivec.push(lb);
+                    self.stack.push(Cursor::Loop {
+                        node,
+                        stmt: aloop,
+                        ub,
+                        pos: 0,
+                    });
+                }
+                None
+            }
+            Stmt::Block(blk) => {
+                self.stack.push(Cursor::Block {
+                    body: blk,
+                    pos: 0,
+                    owner: Some(node),
+                });
+                None
+            }
+            Stmt::Branch(stmt) => {
+                if (stmt.cond)(self.ivec()) {
+                    self.enter(&stmt.then_body)
+                } else if let Some(else_body) = &stmt.else_body {
+                    self.enter(else_body)
+                } else {
+                    None
+                }
+            }
+            Stmt::Call(call) => {
+                let frame = CallFrame::new(call, self.calls.last().map(|f| &f.0));
+                let scalars: Vec<i32> = call.scalars.iter().map(|s| s.eval(self.ivec())).collect();
+                let start = self.ivec.len();
+                self.ivec.extend(scalars);
+                self.calls.push((frame, start, node));
+                self.stack.push(Cursor::Return);
+                self.stack.push(Cursor::Block {
+                    body: std::slice::from_ref(&call.callee.body),
+                    pos: 0,
+                    owner: Some(node),
+                });
+                None
+            }
+        }
+    }
+}
+
+impl Iterator for TraceIter<'_> {
+    type Item = AccessRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next_node = match self.stack.last_mut()? {
+                Cursor::Block { body, pos, .. } if *pos < body.len() => {
+                    *pos += 1;
+                    Some(body[*pos - 1].as_ref())
+                }
+                Cursor::Block { .. } => {
+                    self.stack.pop();
+                    None
+                }
+                Cursor::Loop { stmt, pos, .. } if *pos < stmt.body.len() => {
+                    *pos += 1;
+                    Some(stmt.body[*pos - 1].as_ref())
+                }
+                Cursor::Loop { stmt, ub, pos, .. } => {
+                    // end of the body: step the index and run the body again, or leave the loop
+                    let i = self.ivec.last_mut().unwrap();
+                    *i = (stmt.step)(*i);
+                    if (stmt.test)(*i, *ub) {
+                        *pos = 0;
+                    } else {
+                        self.ivec.pop();
+                        self.stack.pop();
+                    }
+                    None
+                }
+                Cursor::Return => {
+                    let (_, start, _) = self.calls.pop().unwrap();
+                    self.ivec.truncate(start);
+                    self.stack.pop();
+                    None
+                }
+            };
+            if let Some(rec) = next_node.and_then(|node| self.enter(node)) {
+                return Some(rec);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::arybase::set_arybase;
 
     #[test]
     fn loop_a_0() {
@@ -113,4 +421,107 @@ mod test {
         let awalk = Walk::new(&iloop);
         assert_eq!(awalk.fold(0, |cnt, _stmt| cnt + 1), 4);
     }
+
+    #[test]
+    fn trace_loops() {
+        // i = 0, 3 { j = i, 3 { a[i][j] } b[i] if i == 1 { a[0][0] } }
+        let mut aref = Node::new_ref("A", vec![3, 3], |ij| vec![ij[0] as i64, ij[1] as i64]);
+        let mut jloop = Node::new_single_loop_general(
+            "j",
+            LoopBound::Affine { a: vec![1], b: 0 },
+            LoopBound::Fixed(3),
+            |j, ub| j < ub,
+            |j| j + 1,
+        );
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        let mut bref = Node::new_ref("B", vec![3], |i| vec![i[0] as i64]);
+        let mut branch = Node::new_node(Stmt::Branch(BranchStmt {
+            cond: Box::new(|i| i[0] == 1),
+            then_body: Node::new_ref("A", vec![3, 3], |_| vec![0, 0]),
+            else_body: None,
+        }));
+        let mut iloop = Node::new_single_loop("i", 0, 3);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        Node::extend_loop_body(&mut iloop, &mut bref);
+        Node::extend_loop_body(&mut iloop, &mut branch);
+        set_arybase(&mut iloop);
+
+        let trace: Vec<_> = TraceIter::new(&iloop).map(|rec| rec.elem).collect();
+        assert_eq!(trace, [0, 1, 2, 9, 4, 5, 10, 0, 8, 11]);
+    }
+
+    #[test]
+    fn trace_empty_loop() {
+        // i = 0, 0 { a[i] } b[0]
+        let mut aref = Node::new_ref("A", vec![1], |i| vec![i[0] as i64]);
+        let mut iloop = Node::new_single_loop("i", 0, 0);
+        Node::extend_loop_body(&mut iloop, &mut aref);
+        let mut code = Node::new_node(Stmt::Block(vec![
+            iloop,
+            Node::new_ref("B", vec![1], |_| vec![0]),
+        ]));
+        set_arybase(&mut code);
+        assert_eq!(
+            TraceIter::new(&code)
+                .map(|rec| rec.elem)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+    }
+
+    #[test]
+    fn trace_calls() {
+        // twice(x; o) = i = 0, 2 { x[o+i] }, called as twice(A; 2) twice(B; 0)
+        let mut body = Node::new_single_loop("i", 0, 2);
+        let mut xref = Node::new_ref("x", vec![4], |oi| vec![(oi[0] + oi[1]) as i64]);
+        Node::extend_loop_body(&mut body, &mut xref);
+        let twice = Subprogram::new("twice", vec![("x", vec![4])], 1, body);
+        let mut code = Node::new_node(Stmt::Block(vec![
+            Node::new_call(&twice, &["A"], vec![LoopBound::Fixed(2)]),
+            Node::new_call(&twice, &["B"], vec![LoopBound::Fixed(0)]),
+        ]));
+        set_arybase(&mut code);
+        let trace: Vec<_> = TraceIter::new(&code).collect();
+        let fields = |rec: &AccessRecord| (rec.time, rec.ary_id, rec.elem, rec.ivec.to_vec());
+        assert_eq!(
+            trace.iter().map(fields).collect::<Vec<_>>(),
+            [
+                (0, 0, 2, vec![2, 0]),
+                (1, 0, 3, vec![2, 1]),
+                (2, 1, 4, vec![0, 0]),
+                (3, 1, 5, vec![0, 1]),
+            ]
+        );
+        assert!(trace.iter().all(|rec| rec.ref_id == 0));
+    }
+
+    #[test]
+    fn resume() {
+        // twice(x; o) = i = 0, 2 { x[o+i] }
+        // j = 0, 3 { twice(A; j) if j == 1 { B[j] } else { twice(B; 0) } }
+        let mut body = Node::new_single_loop("i", 0, 2);
+        let mut xref = Node::new_ref("x", vec![8], |oi| vec![(oi[0] + oi[1]) as i64]);
+        Node::extend_loop_body(&mut body, &mut xref);
+        let twice = Subprogram::new("twice", vec![("x", vec![8])], 1, body);
+        let mut jloop = Node::new_single_loop("j", 0, 3);
+        let mut call = Node::new_call(&twice, &["A"], vec![LoopBound::Affine { a: vec![1], b: 0 }]);
+        let mut branch = Node::new_node(Stmt::Branch(BranchStmt {
+            cond: Box::new(|j| j[0] == 1),
+            then_body: Node::new_ref("B", vec![8], |j| vec![j[0] as i64]),
+            else_body: Some(Node::new_call(&twice, &["B"], vec![LoopBound::Fixed(0)])),
+        }));
+        Node::extend_loop_body(&mut jloop, &mut call);
+        Node::extend_loop_body(&mut jloop, &mut branch);
+        set_arybase(&mut jloop);
+
+        let trace: Vec<_> = TraceIter::new(&jloop).collect();
+        assert_eq!(trace.len(), 11);
+        for k in 0..=trace.len() {
+            let mut first = TraceIter::new(&jloop);
+            let mut resumed: Vec<_> = first.by_ref().take(k).collect();
+            let state = first.state();
+            resumed.extend(TraceIter::resume(&jloop, Layout::default(), &state));
+            assert_eq!(resumed, trace);
+        }
+    }
 }
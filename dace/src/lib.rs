@@ -1,6 +1,13 @@
 #![feature(get_mut_unchecked)]
 #![feature(core_intrinsics)]
 
+// lets the `::dace::...` paths emitted by `dace!` resolve inside this crate too
+extern crate self as dace;
+
 pub mod arybase;
 pub mod ast;
+pub mod compile;
 pub mod iter;
+pub mod record;
+
+pub use dace_macro::dace;
@@ -0,0 +1,151 @@
+use crate::ast::{AccessKind, AryRef, CallStmt, Subprogram};
+use smallvec::SmallVec;
+
+/// Sizes used to turn element addresses into byte and cache-line addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Bytes per array element.
+    pub elem_size: usize,
+    /// Bytes per cache line.
+    pub line_size: usize,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            elem_size: 8,
+            line_size: 64,
+        }
+    }
+}
+
+/// One access of a trace, as emitted by every tracer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessRecord {
+    /// Logical time: the position of the access in the trace, from 0.
+    pub time: usize,
+    pub ref_id: usize,
+    pub ary_id: usize,
+    /// Indices of the enclosing loops; inside a subprogram, those of the callee.  Nests of
+    /// up to 4 loops keep them inline, so a record does not allocate.
+    pub ivec: SmallVec<[i32; 4]>,
+    /// Row-major element index within the array.
+    pub index: usize,
+    /// Element address: the array base plus `index`, the unit of `set_arybase`.
+    pub elem: usize,
+    /// Byte address.
+    pub addr: usize,
+    /// Cache-line address, i.e. `addr / line_size`.
+    pub line: usize,
+    pub kind: AccessKind,
+}
+
+impl AccessRecord {
+    /// The record of the access `ary_ref` makes at iteration `ivec`, or `None` if its
+    /// boundary policy skips it.  Array bases, array ids and ref ids must have been set
+    /// by `set_arybase`.
+    pub fn new(
+        ary_ref: &AryRef,
+        ivec: &[i32],
+        frame: Option<&CallFrame>,
+        time: usize,
+        layout: &Layout,
+    ) -> Option<Self> {
+        let index = ary_ref.offset(ivec)?;
+        let (base, ary_id) = match frame {
+            Some(frame) => frame.array(&ary_ref.name),
+            None => (ary_ref.base.unwrap(), ary_ref.ary_id.unwrap()),
+        };
+        let elem = base + index;
+        let addr = elem * layout.elem_size;
+        Some(AccessRecord {
+            time,
+            ref_id: ary_ref.ref_id.unwrap(),
+            ary_id,
+            ivec: SmallVec::from_slice(ivec),
+            index,
+            elem,
+            addr,
+            line: addr / layout.line_size,
+            kind: ary_ref.kind,
+        })
+    }
+}
+
+/// The actual arrays seen inside the body of a called subprogram.
+pub struct CallFrame<'a> {
+    pub callee: &'a Subprogram,
+    pub bases: Vec<usize>,
+    pub ary_ids: Vec<usize>,
+}
+
+impl<'a> CallFrame<'a> {
+    /// Bind the arrays of `call`, made from the body of the subprogram of `caller`, or from
+    /// the top level if there is none.
+    pub fn new(call: &'a CallStmt, caller: Option<&CallFrame>) -> Self {
+        let (bases, ary_ids) = call
+            .arrays
+            .iter()
+            .enumerate()
+            .map(|(k, ary_nm)| match caller {
+                Some(frame) => frame.array(ary_nm),
+                None => (call.bases[k].unwrap(), call.ary_ids[k].unwrap()),
+            })
+            .unzip();
+        CallFrame {
+            callee: &call.callee,
+            bases,
+            ary_ids,
+        }
+    }
+
+    /// Base and id of the actual array bound to the formal array `ary_nm`.
+    pub fn array(&self, ary_nm: &str) -> (usize, usize) {
+        match self.callee.array_param(ary_nm) {
+            Some(k) => (self.bases[k], self.ary_ids[k]),
+            None => panic!("{} is not a parameter of {}", ary_nm, self.callee.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arybase::set_arybase;
+    use crate::ast::{Node, Stmt};
+
+    #[test]
+    fn record_fields() {
+        // i = 0, 4 { a[i] = b[i][i] }
+        let mut aref = Node::new_write_ref("A", vec![4], |i| vec![i[0] as i64]);
+        let mut bref = Node::new_ref("B", vec![4, 4], |i| vec![i[0] as i64, i[0] as i64]);
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        Node::extend_loop_body(&mut iloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut bref);
+        set_arybase(&mut iloop);
+
+        let Stmt::Ref(b) = &bref.stmt else {
+            panic!("expected a reference");
+        };
+        let layout = Layout {
+            elem_size: 4,
+            line_size: 32,
+        };
+        let rec = AccessRecord::new(b, &[3], None, 7, &layout).unwrap();
+        assert_eq!(
+            rec,
+            AccessRecord {
+                time: 7,
+                ref_id: 1,
+                ary_id: 1,
+                ivec: smallvec::smallvec![3],
+                index: 15,
+                elem: 19,
+                addr: 76,
+                line: 2,
+                kind: AccessKind::Read,
+            }
+        );
+        assert_eq!(aref.ref_only(|a| a.kind), Some(AccessKind::Write));
+    }
+}
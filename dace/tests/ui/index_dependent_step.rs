@@ -0,0 +1,11 @@
+fn main() {
+    let n: usize = 8;
+    let _tree = dace::dace! {
+        let A[n][n];
+        for i in 1..n {
+            for j in (0..n).step_by(i) {
+                A[i][j];
+            }
+        }
+    };
+}
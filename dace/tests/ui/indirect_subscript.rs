@@ -0,0 +1,10 @@
+fn main() {
+    let n: usize = 8;
+    let _tree = dace::dace! {
+        let A[n];
+        let B[n];
+        for i in 0..n {
+            A[B[i]];
+        }
+    };
+}
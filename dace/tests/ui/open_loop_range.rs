@@ -0,0 +1,9 @@
+fn main() {
+    let n: usize = 8;
+    let _tree = dace::dace! {
+        let A[n];
+        for i in 0.. {
+            A[i];
+        }
+    };
+}
@@ -0,0 +1,12 @@
+fn main() {
+    let n: usize = 8;
+    let _tree = dace::dace! {
+        let A[n][n];
+        for i in 0..n {
+            for j in 0..i {
+                A[i][j];
+                B[j];
+            }
+        }
+    };
+}
@@ -0,0 +1,167 @@
+use proc_macro2::{Ident, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{BinOp, Expr, UnOp};
+
+/// An affine form `a[0]*iv[0] + ... + a[n-1]*iv[n-1] + b` over the enclosing loop indices.
+/// `None` stands for a zero coefficient (or a zero constant), so that the generated code
+/// only contains the terms that were actually written.
+#[derive(Clone)]
+pub struct Affine {
+    pub coeffs: Vec<Option<TokenStream>>,
+    pub constant: Option<TokenStream>,
+}
+
+impl Affine {
+    fn zero(depth: usize) -> Self {
+        Affine {
+            coeffs: vec![None; depth],
+            constant: None,
+        }
+    }
+
+    fn constant(depth: usize, value: TokenStream) -> Self {
+        Affine {
+            coeffs: vec![None; depth],
+            constant: Some(value),
+        }
+    }
+
+    fn index(depth: usize, pos: usize) -> Self {
+        let mut form = Self::zero(depth);
+        form.coeffs[pos] = Some(quote!(1));
+        form
+    }
+
+    fn add(self, other: Self) -> Self {
+        let plus = |x: Option<TokenStream>, y: Option<TokenStream>| match (x, y) {
+            (None, y) => y,
+            (x, None) => x,
+            (Some(x), Some(y)) => Some(quote!((#x) + (#y))),
+        };
+        Affine {
+            coeffs: self
+                .coeffs
+                .into_iter()
+                .zip(other.coeffs)
+                .map(|(x, y)| plus(x, y))
+                .collect(),
+            constant: plus(self.constant, other.constant),
+        }
+    }
+
+    fn scale(self, factor: &TokenStream) -> Self {
+        let times = |x: Option<TokenStream>| x.map(|x| quote!((#x) * (#factor)));
+        Affine {
+            coeffs: self.coeffs.into_iter().map(times).collect(),
+            constant: times(self.constant),
+        }
+    }
+
+    fn neg(self) -> Self {
+        self.scale(&quote!(-1))
+    }
+
+    /// True if no loop index appears in the form.
+    pub fn is_constant(&self) -> bool {
+        self.coeffs.iter().all(Option::is_none)
+    }
+
+    /// The coefficient vector with zeros filled in, as `i32` expressions.
+    pub fn coeff_tokens(&self) -> Vec<TokenStream> {
+        self.coeffs
+            .iter()
+            .map(|c| c.clone().unwrap_or_else(|| quote!(0)))
+            .collect()
+    }
+
+    pub fn constant_tokens(&self) -> TokenStream {
+        self.constant.clone().unwrap_or_else(|| quote!(0))
+    }
+}
+
+/// Does the token stream mention any of the given loop indices?
+pub fn mentions(tokens: TokenStream, ivs: &[Ident]) -> bool {
+    tokens.into_iter().any(|tt| match tt {
+        TokenTree::Ident(id) => ivs.contains(&id),
+        TokenTree::Group(g) => mentions(g.stream(), ivs),
+        _ => false,
+    })
+}
+
+/// Try to read `expr` as an affine function of the loop indices `ivs`.
+/// Sub-expressions that do not mention any index are treated as constants and
+/// are evaluated (cast to `i32`) when the loop tree is built.
+/// Returns `None` if the expression is not affine, e.g. `i * j`.
+pub fn analyze(expr: &Expr, ivs: &[Ident]) -> Option<Affine> {
+    let depth = ivs.len();
+    if !mentions(expr.to_token_stream(), ivs) {
+        return Some(Affine::constant(depth, quote!((#expr) as i32)));
+    }
+    match expr {
+        Expr::Path(p) => {
+            let id = p.path.get_ident()?;
+            let pos = ivs.iter().rposition(|iv| iv == id)?;
+            Some(Affine::index(depth, pos))
+        }
+        Expr::Paren(e) => analyze(&e.expr, ivs),
+        Expr::Group(e) => analyze(&e.expr, ivs),
+        Expr::Cast(e) => analyze(&e.expr, ivs),
+        Expr::Unary(e) if matches!(e.op, UnOp::Neg(_)) => Some(analyze(&e.expr, ivs)?.neg()),
+        Expr::Binary(e) => {
+            let lhs = analyze(&e.left, ivs)?;
+            let rhs = analyze(&e.right, ivs)?;
+            match e.op {
+                BinOp::Add(_) => Some(lhs.add(rhs)),
+                BinOp::Sub(_) => Some(lhs.add(rhs.neg())),
+                BinOp::Mul(_) if lhs.is_constant() => Some(rhs.scale(&lhs.constant_tokens())),
+                BinOp::Mul(_) if rhs.is_constant() => Some(lhs.scale(&rhs.constant_tokens())),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proc_macro2::Span;
+
+    fn ivs(names: &[&str]) -> Vec<Ident> {
+        names
+            .iter()
+            .map(|n| Ident::new(n, Span::call_site()))
+            .collect()
+    }
+
+    #[test]
+    fn constant_bound() {
+        let expr: Expr = syn::parse_quote!(n - 1);
+        let form = analyze(&expr, &ivs(&["i"])).unwrap();
+        assert!(form.is_constant());
+    }
+
+    #[test]
+    fn affine_bound() {
+        let expr: Expr = syn::parse_quote!(2 * i - j + 1);
+        let form = analyze(&expr, &ivs(&["i", "j"])).unwrap();
+        assert!(form.coeffs.iter().all(Option::is_some));
+        assert!(form.constant.is_some());
+    }
+
+    #[test]
+    fn inner_index_shadows_outer() {
+        let expr: Expr = syn::parse_quote!(i);
+        let form = analyze(&expr, &ivs(&["i", "i"])).unwrap();
+        assert!(form.coeffs[0].is_none());
+        assert!(form.coeffs[1].is_some());
+    }
+
+    #[test]
+    fn not_affine() {
+        let expr: Expr = syn::parse_quote!(i * j);
+        assert!(analyze(&expr, &ivs(&["i", "j"])).is_none());
+        let expr: Expr = syn::parse_quote!(i / 2);
+        assert!(analyze(&expr, &ivs(&["i"])).is_none());
+    }
+}
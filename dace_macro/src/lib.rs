@@ -0,0 +1,564 @@
+//! The `dace!` procedural macro: write a loop nest in Rust syntax and get the
+//! corresponding `dace::ast::Node` tree.
+//!
+//! Every array must be declared with its dimensions before the loops, since they are not
+//! inferred from the loop bounds; a use of an undeclared array is a compile error.  The
+//! nest `for i in 0..N { for j in 0..i { A[i][j]; B[j]; } }` is written
+//!
+//! ```ignore
+//! let tree = dace! {
+//!     let A[N][N];
+//!     let B[N];
+//!     for i in 0..N {
+//!         for j in 0..i {
+//!             A[i][j];
+//!             B[j];
+//!         }
+//!     }
+//! };
+//! ```
+//!
+//! and the statement `A[i][j] = A[i][j] + B[j];` in its body would give a write
+//! reference to `A` and read references to `A` and `B`.
+//!
+//! Arrays are declared with `let NAME[dim]...;` and every indexed use of a declared
+//! array becomes one reference, in source order.  The target of `=` or of a compound
+//! assignment such as `+=` becomes a write reference.  Loop bounds that are affine in the
+//! enclosing loop indices become `LoopBound::Affine`, bounds without any loop index
+//! become `LoopBound::Fixed`, and everything else falls back to `LoopBound::Dynamic`.
+//! References whose subscripts are all affine also record them as an `AffineSub`.
+//!
+//! Loop indices are `i64` in conditions, dynamic bounds and non-affine subscripts, and
+//! the operands of arithmetic and comparisons that do not mention an index are cast to
+//! `i64`, so that `if i < n` works with `n: usize`.
+
+mod affine;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use std::collections::HashMap;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{braced, bracketed, BinOp, Error, Expr, ExprRange, RangeLimits, Result, Token};
+
+/// A whole `dace!` invocation.
+struct Program {
+    items: Vec<Item>,
+}
+
+enum Item {
+    /// `let A[N][M];`
+    Decl { name: Ident, dims: Vec<Expr> },
+    /// `for i in lo..hi { ... }`
+    Loop {
+        iv: Ident,
+        range: Expr,
+        body: Vec<Item>,
+    },
+    /// `if cond { ... } else { ... }`
+    Branch {
+        cond: Expr,
+        then_body: Vec<Item>,
+        else_body: Option<Vec<Item>>,
+        span: Span,
+    },
+    /// Any other expression statement, e.g. `A[i] = B[i];`
+    Stmt(Expr),
+}
+
+fn parse_block(input: ParseStream) -> Result<Vec<Item>> {
+    let content;
+    braced!(content in input);
+    parse_items(&content)
+}
+
+fn parse_items(input: ParseStream) -> Result<Vec<Item>> {
+    let mut items = Vec::new();
+    while !input.is_empty() {
+        items.push(input.parse()?);
+    }
+    Ok(items)
+}
+
+fn parse_branch(input: ParseStream) -> Result<Item> {
+    let if_token: Token![if] = input.parse()?;
+    let cond = Expr::parse_without_eager_brace(input)?;
+    let then_body = parse_block(input)?;
+    let else_body = if input.parse::<Option<Token![else]>>()?.is_some() {
+        if input.peek(Token![if]) {
+            Some(vec![parse_branch(input)?])
+        } else {
+            Some(parse_block(input)?)
+        }
+    } else {
+        None
+    };
+    Ok(Item::Branch {
+        cond,
+        then_body,
+        else_body,
+        span: if_token.span,
+    })
+}
+
+impl Parse for Item {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![let]) {
+            input.parse::<Token![let]>()?;
+            let name: Ident = input.parse()?;
+            let mut dims = Vec::new();
+            while input.peek(syn::token::Bracket) {
+                let content;
+                bracketed!(content in input);
+                dims.push(content.parse()?);
+            }
+            if dims.is_empty() {
+                return Err(Error::new(
+                    name.span(),
+                    "expected an array declaration such as `let A[N][M];`",
+                ));
+            }
+            input.parse::<Token![;]>()?;
+            Ok(Item::Decl { name, dims })
+        } else if input.peek(Token![for]) {
+            input.parse::<Token![for]>()?;
+            let iv: Ident = input.parse()?;
+            input.parse::<Token![in]>()?;
+            let range = Expr::parse_without_eager_brace(input)?;
+            let body = parse_block(input)?;
+            Ok(Item::Loop { iv, range, body })
+        } else if input.peek(Token![if]) {
+            parse_branch(input)
+        } else {
+            let expr: Expr = input.parse()?;
+            input.parse::<Token![;]>()?;
+            Ok(Item::Stmt(expr))
+        }
+    }
+}
+
+impl Parse for Program {
+    fn parse(input: ParseStream) -> Result<Self> {
+        Ok(Program {
+            items: parse_items(input)?,
+        })
+    }
+}
+
+/// Code generation state: declared arrays and the enclosing loop indices.
+struct Gen {
+    arrays: HashMap<String, (Ident, usize)>,
+    ivs: Vec<Ident>,
+}
+
+impl Gen {
+    /// `let i = ivec[0] as i64; ...` so that user expressions can name the indices.
+    fn bind_ivs(&self, ivec: &Ident) -> TokenStream2 {
+        let binds = self.ivs.iter().enumerate().map(|(pos, iv)| {
+            quote! {
+                #[allow(unused_variables)]
+                let #iv: i64 = #ivec[#pos] as i64;
+            }
+        });
+        quote!(#(#binds)*)
+    }
+
+    /// `expr` with the operands that do not mention a loop index cast to `i64`, see
+    /// [`widen`].
+    fn widen(&self, expr: &Expr) -> TokenStream2 {
+        widen(expr, &self.ivs)
+    }
+
+    fn closure_over_ivs(&self, body: TokenStream2) -> TokenStream2 {
+        let ivec = Ident::new("ivec", Span::mixed_site());
+        let binds = self.bind_ivs(&ivec);
+        quote! {
+            move |#ivec: &[i32]| {
+                #binds
+                #body
+            }
+        }
+    }
+
+    /// `inclusive` adds one to the bound, for `lo..=hi` ranges.
+    fn bound(&self, expr: &Expr, inclusive: bool) -> TokenStream2 {
+        let extra = if inclusive { quote!(+ 1) } else { quote!() };
+        match affine::analyze(expr, &self.ivs) {
+            Some(form) if form.is_constant() => {
+                let b = form.constant_tokens();
+                quote_spanned!(expr.span()=> ::dace::ast::LoopBound::Fixed(#b #extra))
+            }
+            Some(form) => {
+                let a = form.coeff_tokens();
+                let b = form.constant_tokens();
+                quote_spanned! {expr.span()=>
+                    ::dace::ast::LoopBound::Affine { a: vec![#(#a),*], b: #b #extra }
+                }
+            }
+            None => {
+                let expr = self.widen(expr);
+                let f = self.closure_over_ivs(quote!((#expr) as i32 #extra));
+                quote_spanned!(expr.span()=> ::dace::ast::LoopBound::Dynamic(Box::new(#f)))
+            }
+        }
+    }
+
+    /// Split a loop range into lower bound, upper bound and step.
+    fn range(&self, range: &Expr) -> Result<(TokenStream2, TokenStream2, TokenStream2)> {
+        let (range, step) = match range {
+            Expr::MethodCall(call) if call.method == "step_by" && call.args.len() == 1 => {
+                let step = &call.args[0];
+                if affine::mentions(step.to_token_stream(), &self.ivs) {
+                    return Err(Error::new_spanned(
+                        step,
+                        "the loop step cannot depend on loop indices",
+                    ));
+                }
+                (strip_parens(&call.receiver), quote!((#step) as i32))
+            }
+            _ => (range, quote!(1)),
+        };
+        let Expr::Range(ExprRange {
+            start: Some(start),
+            end: Some(end),
+            limits,
+            ..
+        }) = range
+        else {
+            return Err(Error::new_spanned(
+                range,
+                "expected a loop range `lo..hi`, `lo..=hi` or `(lo..hi).step_by(s)`",
+            ));
+        };
+        let inclusive = matches!(limits, RangeLimits::Closed(_));
+        let i = Ident::new("i", Span::mixed_site());
+        let step = quote!(move |#i: i32| #i + #step);
+        Ok((self.bound(start, false), self.bound(end, inclusive), step))
+    }
+
+    /// Generate a block node for several statements, or the statement itself if there is one.
+    fn body(&mut self, items: &[Item], span: Span) -> Result<TokenStream2> {
+        let mut nodes = Vec::new();
+        for item in items {
+            nodes.extend(self.item(item)?);
+        }
+        match nodes.len() {
+            0 => Err(Error::new(
+                span,
+                "empty body: expected at least one statement",
+            )),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(quote! {
+                ::dace::ast::Node::new_node(::dace::ast::Stmt::Block(vec![#(#nodes),*]))
+            }),
+        }
+    }
+
+    /// Generate the nodes of one item; an expression statement yields one node per reference.
+    fn item(&mut self, item: &Item) -> Result<Vec<TokenStream2>> {
+        match item {
+            Item::Decl { .. } => Ok(vec![]),
+            Item::Loop { iv, range, body } => {
+                let (lb, ub, step) = self.range(range)?;
+                self.ivs.push(iv.clone());
+                let children = body
+                    .iter()
+                    .map(|item| self.item(item))
+                    .collect::<Result<Vec<_>>>();
+                self.ivs.pop();
+                let children = children?.into_iter().flatten();
+                let node = Ident::new("node", Span::mixed_site());
+                let child = Ident::new("child", Span::mixed_site());
+                let name = iv.to_string();
+                Ok(vec![quote! {{
+                    let mut #node = ::dace::ast::Node::new_single_loop_general(
+                        #name, #lb, #ub, |i, ub| i < ub, #step,
+                    );
+                    #(
+                        let mut #child = #children;
+                        ::dace::ast::Node::extend_loop_body(&mut #node, &mut #child);
+                    )*
+                    #node
+                }}])
+            }
+            Item::Branch {
+                cond,
+                then_body,
+                else_body,
+                span,
+            } => {
+                let cond = self.widen(cond);
+                let cond = self.closure_over_ivs(quote!(#cond));
+                let then_body = self.body(then_body, *span)?;
+                let else_body = match else_body {
+                    Some(items) => {
+                        let node = self.body(items, *span)?;
+                        quote!(Some(#node))
+                    }
+                    None => quote!(None),
+                };
+                Ok(vec![quote! {
+                    ::dace::ast::Node::new_node(::dace::ast::Stmt::Branch(::dace::ast::BranchStmt {
+                        cond: Box::new(#cond),
+                        then_body: #then_body,
+                        else_body: #else_body,
+                    }))
+                }])
+            }
+            Item::Stmt(expr) => {
+                let mut refs = Vec::new();
+                self.collect_refs(expr, &mut refs)?;
+                Ok(refs)
+            }
+        }
+    }
+
+    /// Collect the array references of an expression statement in source order.
+    fn collect_refs(&self, expr: &Expr, refs: &mut Vec<TokenStream2>) -> Result<()> {
+        match expr {
+            Expr::Index(_) => refs.push(self.array_ref(expr, false)?),
+            Expr::Assign(e) => {
+                self.collect_target(&e.left, refs)?;
+                self.collect_refs(&e.right, refs)?;
+            }
+            Expr::Binary(e) if is_compound_assign(&e.op) => {
+                self.collect_target(&e.left, refs)?;
+                self.collect_refs(&e.right, refs)?;
+            }
+            Expr::Binary(e) => {
+                self.collect_refs(&e.left, refs)?;
+                self.collect_refs(&e.right, refs)?;
+            }
+            Expr::Unary(e) => self.collect_refs(&e.expr, refs)?,
+            Expr::Paren(e) => self.collect_refs(&e.expr, refs)?,
+            Expr::Group(e) => self.collect_refs(&e.expr, refs)?,
+            Expr::Cast(e) => self.collect_refs(&e.expr, refs)?,
+            Expr::Call(e) => {
+                for arg in e.args.iter() {
+                    self.collect_refs(arg, refs)?;
+                }
+            }
+            Expr::MethodCall(e) => {
+                self.collect_refs(&e.receiver, refs)?;
+                for arg in e.args.iter() {
+                    self.collect_refs(arg, refs)?;
+                }
+            }
+            Expr::Lit(_) | Expr::Path(_) => {}
+            _ => {
+                return Err(Error::new_spanned(
+                    expr,
+                    "unsupported expression in a dace statement",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The target of an assignment is a write reference.
+    fn collect_target(&self, expr: &Expr, refs: &mut Vec<TokenStream2>) -> Result<()> {
+        match strip_parens(expr) {
+            target @ Expr::Index(_) => refs.push(self.array_ref(target, true)?),
+            target => self.collect_refs(target, refs)?,
+        }
+        Ok(())
+    }
+
+    /// `A[i][j]` becomes `Node::new_ref("A", dims, |ivec| vec![i, j])`, or
+    /// `Node::new_write_ref` if the element is written.
+    fn array_ref(&self, expr: &Expr, write: bool) -> Result<TokenStream2> {
+        let mut subs = Vec::new();
+        let mut base = expr;
+        while let Expr::Index(e) = base {
+            subs.push(&*e.index);
+            base = &e.expr;
+        }
+        subs.reverse();
+        let name = match base {
+            Expr::Path(p) => p.path.get_ident(),
+            _ => None,
+        }
+        .ok_or_else(|| Error::new_spanned(base, "expected an array name"))?;
+        let Some((dims, rank)) = self.arrays.get(&name.to_string()) else {
+            return Err(Error::new_spanned(
+                name,
+                format!("array `{name}` is not declared; add `let {name}[..];`"),
+            ));
+        };
+        if subs.len() != *rank {
+            return Err(Error::new_spanned(
+                expr,
+                format!(
+                    "array `{name}` has {rank} dimension(s) but {} subscript(s) are given",
+                    subs.len()
+                ),
+            ));
+        }
+        for sub in subs.iter() {
+            let mut nested = Vec::new();
+            self.collect_refs(sub, &mut nested)?;
+            if !nested.is_empty() {
+                return Err(Error::new_spanned(
+                    sub,
+                    "subscripts must be expressions of loop indices and constants",
+                ));
+            }
+        }
+        let name_str = name.to_string();
+        let forms: Option<Vec<_>> = subs.iter().map(|s| affine::analyze(s, &self.ivs)).collect();
+        let new_ref = match forms {
+            // affine subscripts keep their form, for the compiled trace generator
+            Some(forms) => {
+                let coeffs = forms.iter().map(|f| {
+                    let a = f.coeff_tokens();
+                    quote!(vec![#((#a) as i64),*])
+                });
+                let consts = forms.iter().map(|f| f.constant_tokens());
+                quote_spanned! {expr.span()=> {
+                    // the forms are written as they came, e.g. `(1) as i64` or `(j) * (-1)`
+                    #[allow(clippy::unnecessary_cast, clippy::neg_multiply)]
+                    let sub = ::dace::ast::AffineSub {
+                        coeffs: vec![#(#coeffs),*],
+                        consts: vec![#((#consts) as i64),*],
+                    };
+                    ::dace::ast::Node::new_affine_ref(#name_str, #dims.clone(), sub)
+                }}
+            }
+            None => {
+                let subs = subs.iter().map(|s| self.widen(s));
+                let sub = self.closure_over_ivs(quote!(vec![#((#subs) as i64),*]));
+                quote_spanned! {expr.span()=>
+                    ::dace::ast::Node::new_ref(#name_str, #dims.clone(), #sub)
+                }
+            }
+        };
+        if write {
+            Ok(quote_spanned!(expr.span()=> ::dace::ast::Node::into_write(#new_ref)))
+        } else {
+            Ok(new_ref)
+        }
+    }
+}
+
+fn is_compound_assign(op: &BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::AddAssign(_)
+            | BinOp::SubAssign(_)
+            | BinOp::MulAssign(_)
+            | BinOp::DivAssign(_)
+            | BinOp::RemAssign(_)
+            | BinOp::BitXorAssign(_)
+            | BinOp::BitAndAssign(_)
+            | BinOp::BitOrAssign(_)
+            | BinOp::ShlAssign(_)
+            | BinOp::ShrAssign(_)
+    )
+}
+
+/// Rewrite a user expression over the loop indices, which are bound as `i64`, so that
+/// the other operands of arithmetic and comparisons are cast to `i64`, e.g. `i < n`
+/// becomes `i < ((n) as i64)` whatever the integer type of `n`.  Literals are left to
+/// inference, and an operation with an explicit cast on either side is left as written.
+fn widen(expr: &Expr, ivs: &[Ident]) -> TokenStream2 {
+    match expr {
+        Expr::Binary(e) if matches!(e.op, BinOp::And(_) | BinOp::Or(_)) => {
+            let (left, op, right) = (widen(&e.left, ivs), &e.op, widen(&e.right, ivs));
+            quote!(#left #op #right)
+        }
+        Expr::Binary(e)
+            if !matches!(strip_parens(&e.left), Expr::Cast(_))
+                && !matches!(strip_parens(&e.right), Expr::Cast(_)) =>
+        {
+            let operand = |e: &Expr| match strip_parens(e) {
+                Expr::Lit(_) => e.to_token_stream(),
+                _ if !affine::mentions(e.to_token_stream(), ivs) => quote!(((#e) as i64)),
+                _ => widen(e, ivs),
+            };
+            let (left, op, right) = (operand(&e.left), &e.op, operand(&e.right));
+            quote!(#left #op #right)
+        }
+        Expr::Unary(e) => {
+            let (op, inner) = (&e.op, widen(&e.expr, ivs));
+            quote!(#op #inner)
+        }
+        Expr::Paren(e) => {
+            let inner = widen(&e.expr, ivs);
+            quote!((#inner))
+        }
+        _ => expr.to_token_stream(),
+    }
+}
+
+fn strip_parens(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Paren(e) => strip_parens(&e.expr),
+        Expr::Group(e) => strip_parens(&e.expr),
+        _ => expr,
+    }
+}
+
+fn collect_decls(
+    items: &[Item],
+    decls: &mut Vec<(Ident, Vec<Expr>)>,
+    seen: &mut HashMap<String, Ident>,
+) -> Result<()> {
+    for item in items {
+        match item {
+            Item::Decl { name, dims } => {
+                if seen.insert(name.to_string(), name.clone()).is_some() {
+                    return Err(Error::new(
+                        name.span(),
+                        format!("array `{name}` is declared twice"),
+                    ));
+                }
+                decls.push((name.clone(), dims.clone()));
+            }
+            Item::Loop { body, .. } => collect_decls(body, decls, seen)?,
+            Item::Branch {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_decls(then_body, decls, seen)?;
+                if let Some(else_body) = else_body {
+                    collect_decls(else_body, decls, seen)?;
+                }
+            }
+            Item::Stmt(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn expand(program: Program) -> Result<TokenStream2> {
+    let mut decls = Vec::new();
+    collect_decls(&program.items, &mut decls, &mut HashMap::new())?;
+
+    let mut gen = Gen {
+        arrays: HashMap::new(),
+        ivs: Vec::new(),
+    };
+    let mut dim_vars = Vec::new();
+    for (name, dims) in decls.iter() {
+        let var = format_ident!("dim_{}", name, span = Span::mixed_site());
+        dim_vars.push(quote!(let #var: Vec<usize> = vec![#((#dims) as usize),*];));
+        gen.arrays.insert(name.to_string(), (var, dims.len()));
+    }
+    let root = gen.body(&program.items, Span::call_site())?;
+    Ok(quote! {{
+        #(#dim_vars)*
+        #root
+    }})
+}
+
+/// Build a `Arc<dace::ast::Node>` loop tree from Rust-like loop syntax.
+/// See the crate documentation for the accepted syntax.
+#[proc_macro]
+pub fn dace(input: TokenStream) -> TokenStream {
+    let program = syn::parse_macro_input!(input as Program);
+    expand(program)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
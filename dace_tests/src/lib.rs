@@ -1,22 +1,17 @@
 #![allow(dead_code)]
-use dace::ast::Node;
-use std::rc::Rc;
+use dace::ast::{IndexArray, LoopBound, Node, Subprogram};
+use dace::loop_node;
+use std::sync::Arc;
 
 pub mod polybench;
 
-pub fn matmul(n: usize) -> Rc<Node> {
+pub fn matmul(n: usize) -> Arc<Node> {
     // n: usize is array dim
     let ubound = n as i32; // loop bound
                            // creating C[i,j] += A[i,k] * B[k,j]
-    let mut s_ref_c = Node::new_ref("C", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b = Node::new_ref("B", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_c = Node::new_ref("C", vec![n, n], |ijk| vec![ijk[0] as i64, ijk[1] as i64]);
+    let mut s_ref_a = Node::new_ref("A", vec![n, n], |ijk| vec![ijk[0] as i64, ijk[2] as i64]);
+    let mut s_ref_b = Node::new_ref("B", vec![n, n], |ijk| vec![ijk[2] as i64, ijk[1] as i64]);
 
     // creating loop k = 0, n { s_ref }
     let mut k_loop_ref = Node::new_single_loop("k", 0, ubound);
@@ -33,6 +28,96 @@ pub fn matmul(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
+/// One `tile` x `tile` x `tile` block of C[i,j] += A[i,k] * B[k,j], at the offsets
+/// given by the scalar parameters (ii, jj, kk).
+pub fn gemm_tile(n: usize, tile: usize) -> Arc<Subprogram> {
+    // the iteration vector is [ii, jj, kk, i, j, k]
+    let mut s_ref_c = Node::new_ref("C", vec![n, n], |v| {
+        vec![(v[0] + v[3]) as i64, (v[1] + v[4]) as i64]
+    });
+    let mut s_ref_a = Node::new_ref("A", vec![n, n], |v| {
+        vec![(v[0] + v[3]) as i64, (v[2] + v[5]) as i64]
+    });
+    let mut s_ref_b = Node::new_ref("B", vec![n, n], |v| {
+        vec![(v[2] + v[5]) as i64, (v[1] + v[4]) as i64]
+    });
+
+    let mut k_loop_ref = Node::new_single_loop("k", 0, tile as i32);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_c);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_a);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_b);
+    let mut j_loop_ref = Node::new_single_loop("j", 0, tile as i32);
+    Node::extend_loop_body(&mut j_loop_ref, &mut k_loop_ref);
+    let mut i_loop_ref = Node::new_single_loop("i", 0, tile as i32);
+    Node::extend_loop_body(&mut i_loop_ref, &mut j_loop_ref);
+
+    let dims = || vec![n, n];
+    Subprogram::new(
+        "gemm_tile",
+        vec![("C", dims()), ("A", dims()), ("B", dims())],
+        3,
+        i_loop_ref,
+    )
+}
+
+/// Matrix multiply as a loop over tiles, each a call of `gemm_tile`.  `tile` must divide `n`.
+pub fn matmul_tiled(n: usize, tile: usize) -> Arc<Node> {
+    let tile_ref = gemm_tile(n, tile);
+    let step = tile as i32;
+    let mut s_call = Node::new_call(
+        &tile_ref,
+        &["C", "A", "B"],
+        vec![
+            LoopBound::from((vec![1], 0)),
+            LoopBound::from((vec![0, 1], 0)),
+            LoopBound::from((vec![0, 0, 1], 0)),
+        ],
+    );
+
+    let mut kk_loop_ref = loop_node!("kk", 0 => n as i32, step: move |x| x + step);
+    Node::extend_loop_body(&mut kk_loop_ref, &mut s_call);
+    let mut jj_loop_ref = loop_node!("jj", 0 => n as i32, step: move |x| x + step);
+    Node::extend_loop_body(&mut jj_loop_ref, &mut kk_loop_ref);
+    let mut ii_loop_ref = loop_node!("ii", 0 => n as i32, step: move |x| x + step);
+    Node::extend_loop_body(&mut ii_loop_ref, &mut jj_loop_ref);
+
+    ii_loop_ref
+}
+
+/// Sparse matrix-vector product y = A x with A in CSR form.  The sparsity pattern
+/// comes from `rowptr` (n + 1 row offsets) and `col` (column of each nonzero).
+pub fn spmv_csr(rowptr: &IndexArray, col: &IndexArray, ncols: usize) -> Arc<Node> {
+    let n = rowptr.len() - 1;
+    let nnz = col.len();
+
+    // the row bounds rowptr[i] and rowptr[i + 1] are read before the row
+    let mut s_ref_lo = Node::new_ref(&rowptr.name, vec![n + 1], |i| vec![i[0] as i64]);
+    let mut s_ref_hi = Node::new_ref(&rowptr.name, vec![n + 1], |i| vec![i[0] as i64 + 1]);
+
+    // y[i] = y[i] + val[k] * x[col[k]]
+    let mut s_ref_y = Node::new_ref("y", vec![n], |ik| vec![ik[0] as i64]);
+    let mut s_ref_val = Node::new_ref("val", vec![nnz], |ik| vec![ik[1] as i64]);
+    let mut s_ref_x =
+        Node::new_indirect_ref("x", vec![ncols], col, |ik| ik[1] as i64, |_, c| vec![c]);
+
+    let (lo, hi) = (rowptr.clone(), rowptr.clone());
+    let mut k_loop_ref = loop_node!(
+        "k",
+        move |i: &[i32]| lo.get(i[0] as i64) as i32 => move |i: &[i32]| hi.get(i[0] as i64 + 1) as i32
+    );
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_y);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_val);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_x);
+    Node::extend_loop_body(&mut k_loop_ref, &mut s_ref_y);
+
+    let mut i_loop_ref = Node::new_single_loop("i", 0, n as i32);
+    Node::extend_loop_body(&mut i_loop_ref, &mut s_ref_lo);
+    Node::extend_loop_body(&mut i_loop_ref, &mut s_ref_hi);
+    Node::extend_loop_body(&mut i_loop_ref, &mut k_loop_ref);
+
+    i_loop_ref
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,4 +127,91 @@ mod tests {
         let mm = matmul(100);
         assert_eq!(mm.node_count(), 6);
     }
+
+    #[test]
+    fn matmul_tiled_test() {
+        let n = 8;
+        let mut whole = matmul_tiled(n, n);
+        let mut tiled = matmul_tiled(n, 4);
+        let mut mm = matmul(n);
+        let trace = |code: &mut Arc<Node>| {
+            static_rd::trace::trace(code, static_rd::LRUStack::new())
+                .2
+                .get_vec()
+                .clone()
+        };
+        // a single tile is the plain loop nest; smaller tiles reorder the same accesses
+        let expected = trace(&mut mm);
+        assert_eq!(trace(&mut whole), expected);
+        let mut reordered = trace(&mut tiled);
+        assert_ne!(reordered, expected);
+        reordered.sort();
+        let mut expected = expected;
+        expected.sort();
+        assert_eq!(reordered, expected);
+    }
+
+    #[test]
+    fn spmv_tridiagonal() {
+        // 4x4 tridiagonal matrix
+        let rowptr = IndexArray::new("rowptr", vec![0, 2, 5, 8, 10]);
+        let col = IndexArray::new("col", vec![0, 1, 0, 1, 2, 1, 2, 3, 2, 3]);
+        let mut spmv = spmv_csr(&rowptr, &col, 4);
+        assert_eq!(spmv.node_count(), 10);
+
+        let (_, _, trace) = static_rd::trace::trace(&mut spmv, static_rd::LRUSplay::new());
+        let trace = trace.get_vec();
+        assert_eq!(trace.len(), 2 * 4 + 5 * 10);
+        // the first row reads x[0] and x[1], which come right after y, val and col
+        let x_base = 5 + 4 + 10 + 10;
+        assert_eq!(trace[2..7], [5, 9, 19, x_base, 5]);
+        assert_eq!(trace[10], x_base + 1);
+    }
+
+    #[test]
+    fn matmul_dace_macro() {
+        let n: usize = 16;
+        let mut by_hand = matmul(n);
+        let mut by_macro = dace::dace! {
+            let C[n][n];
+            let A[n][n];
+            let B[n][n];
+            for i in 0..n {
+                for j in 0..n {
+                    for k in 0..n {
+                        C[i][j];
+                        A[i][k];
+                        B[k][j];
+                    }
+                }
+            }
+        };
+        let expected = static_rd::trace::trace(&mut by_hand, static_rd::LRUSplay::new());
+        let actual = static_rd::trace::trace(&mut by_macro, static_rd::LRUSplay::new());
+        assert_eq!(expected.2.get_vec(), actual.2.get_vec());
+        assert_eq!(expected.0.to_vec(), actual.0.to_vec());
+    }
+
+    #[test]
+    fn matmul_compiled() {
+        let n: usize = 64;
+        let mut mm = dace::dace! {
+            let C[n][n];
+            let A[n][n];
+            let B[n][n];
+            for i in 0..n {
+                for j in 0..n {
+                    for k in 0..n {
+                        C[i][j] += A[i][k] * B[k][j];
+                    }
+                }
+            }
+        };
+        dace::arybase::set_arybase(&mut mm);
+        let mut interpreted = static_rd::sink::AccessList::new();
+        static_rd::trace::trace_to(&mm, &mut [&mut interpreted]);
+        let mut compiled = Vec::new();
+        dace::compile::compile(&mm).run(|elem, _| compiled.push(elem));
+        assert_eq!(*interpreted.accesses.get_vec(), compiled);
+    }
 }
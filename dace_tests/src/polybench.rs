@@ -1,24 +1,46 @@
 #![allow(dead_code, non_snake_case)]
-use dace::ast::Node;
 use dace::ast::Stmt;
+use dace::ast::{AffineSub, Boundary, Node};
 use dace::branch_node;
 use dace::loop_node;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub fn lu(n: usize) -> Rc<Node> {
+/// A reference whose subscript d is loop index `indices[d]`.  The subscripts are kept as
+/// an `AffineSub`, so a compiled trace steps the address instead of calling a closure.
+fn affine_ref(name: &str, dims: Vec<usize>, indices: &[usize]) -> Arc<Node> {
+    let subs: Vec<_> = indices.iter().map(|&k| (k, 0)).collect();
+    shifted_ref(name, dims, Boundary::Error, &subs)
+}
+
+/// A reference whose subscript d is loop index `subs[d].0` plus `subs[d].1`, with
+/// `boundary` for the subscripts that fall out of range.
+fn shifted_ref(
+    name: &str,
+    dims: Vec<usize>,
+    boundary: Boundary,
+    subs: &[(usize, i64)],
+) -> Arc<Node> {
+    let coeffs = subs
+        .iter()
+        .map(|&(k, _)| {
+            let mut c = vec![0; k + 1];
+            c[k] = 1;
+            c
+        })
+        .collect();
+    let consts = subs.iter().map(|&(_, b)| b).collect();
+    let mut node = Node::new_affine_ref(name, dims, AffineSub { coeffs, consts });
+    let fresh = Arc::get_mut(&mut node).unwrap();
+    *fresh.ref_only_mut_ref(|r| &mut r.boundary).unwrap() = boundary;
+    node
+}
+
+pub fn lu(n: usize) -> Arc<Node> {
     let ubound = n as i32;
-    let mut ref_a_ij = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut ref_a_ik = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut ref_a_kj = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut ref_a_jj = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[1] as usize, ijk[1] as usize]
-    });
+    let mut ref_a_ij = affine_ref("A", vec![n, n], &[0, 1]);
+    let mut ref_a_ik = affine_ref("A", vec![n, n], &[0, 2]);
+    let mut ref_a_kj = affine_ref("A", vec![n, n], &[2, 1]);
+    let mut ref_a_jj = affine_ref("A", vec![n, n], &[1, 1]);
 
     let mut k_loop_ref_j = loop_node!("k", 0 => move |ijk:&[i32]| ijk[1]);
     Node::extend_loop_body(&mut k_loop_ref_j, &mut ref_a_ik);
@@ -48,20 +70,12 @@ pub fn lu(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn lu_affine(n: usize) -> Rc<Node> {
+pub fn lu_affine(n: usize) -> Arc<Node> {
     let ubound = n as i32;
-    let mut ref_a_ij = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut ref_a_ik = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut ref_a_kj = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut ref_a_jj = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[1] as usize, ijk[1] as usize]
-    });
+    let mut ref_a_ij = affine_ref("A", vec![n, n], &[0, 1]);
+    let mut ref_a_ik = affine_ref("A", vec![n, n], &[0, 2]);
+    let mut ref_a_kj = affine_ref("A", vec![n, n], &[2, 1]);
+    let mut ref_a_jj = affine_ref("A", vec![n, n], &[1, 1]);
 
     let mut k_loop_ref_j = loop_node!("k", 0 => (vec![0, 1, 0], 0));
     Node::extend_loop_body(&mut k_loop_ref_j, &mut ref_a_ik);
@@ -91,25 +105,17 @@ pub fn lu_affine(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn trmm_trace(M: usize, N: usize) -> Rc<Node> {
+pub fn trmm_trace(M: usize, N: usize) -> Arc<Node> {
     let mut i_loop_ref = Node::new_single_loop("i", 0, M as i32);
     let mut j_loop_ref = Node::new_single_loop("j", 0, N as i32);
     let mut k_loop_ref =
         Node::new_single_loop("k", Node::get_lb(&i_loop_ref).unwrap() + 1, M as i32);
 
     // B[i * N + j] += A[k * M + i] * B[k * N + j];
-    let mut a_ref = Node::new_ref("A", vec![N, M], |ijk| {
-        vec![ijk[2] as usize, ijk[0] as usize]
-    });
-    let mut b1_ref = Node::new_ref("B", vec![M, N], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut b2_ref = Node::new_ref("B", vec![M, N], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut b3_ref = Node::new_ref("B", vec![M, N], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut a_ref = affine_ref("A", vec![N, M], &[2, 0]);
+    let mut b1_ref = affine_ref("B", vec![M, N], &[2, 1]);
+    let mut b2_ref = affine_ref("B", vec![M, N], &[0, 1]);
+    let mut b3_ref = affine_ref("B", vec![M, N], &[0, 1]);
 
     Node::extend_loop_body(&mut k_loop_ref, &mut a_ref);
     Node::extend_loop_body(&mut k_loop_ref, &mut b1_ref);
@@ -117,9 +123,7 @@ pub fn trmm_trace(M: usize, N: usize) -> Rc<Node> {
     Node::extend_loop_body(&mut k_loop_ref, &mut b3_ref);
 
     // B[i * N + j] = alpha * B[i * N + j];
-    let mut b3_ref = Node::new_ref("B", vec![M, N], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut b3_ref = affine_ref("B", vec![M, N], &[0, 1]);
     Node::extend_loop_body(&mut j_loop_ref, &mut b3_ref);
     Node::extend_loop_body(&mut j_loop_ref, &mut k_loop_ref);
 
@@ -128,14 +132,14 @@ pub fn trmm_trace(M: usize, N: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn mvt(n: usize) -> Rc<Node> {
+pub fn mvt(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating x1[i] = x1[i] + a[i][j] * y1[j];
-    let mut s_ref_x1: Rc<Node> = Node::new_ref("x1", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_y1 = Node::new_ref("y1", vec![n], |ij| vec![ij[1] as usize]);
+    let mut s_ref_x1: Arc<Node> = affine_ref("x1", vec![n], &[0]);
+    let mut s_ref_a1 = affine_ref("a1", vec![n, n], &[0, 1]);
+    let mut s_ref_y1 = affine_ref("y1", vec![n], &[1]);
 
     // creating loop j = 0, n { s_ref }
     let mut j_loop_ref = Node::new_single_loop("j", 0, ubound);
@@ -149,9 +153,9 @@ pub fn mvt(n: usize) -> Rc<Node> {
     Node::extend_loop_body(&mut i_loop_ref, &mut j_loop_ref);
 
     //x2[i] = x2[i] + a[j][i] * y2[j];
-    let mut s_ref_x2: Rc<Node> = Node::new_ref("x2", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |ij| vec![ij[1] as usize, ij[0] as usize]);
-    let mut s_ref_y2 = Node::new_ref("y2", vec![n], |ij| vec![ij[1] as usize]);
+    let mut s_ref_x2: Arc<Node> = affine_ref("x2", vec![n], &[0]);
+    let mut s_ref_a2 = affine_ref("a2", vec![n, n], &[1, 0]);
+    let mut s_ref_y2 = affine_ref("y2", vec![n], &[1]);
 
     // creating loop k = 0, n { s_ref }
     let mut k_loop_ref = Node::new_single_loop("k", 0, ubound);
@@ -168,21 +172,21 @@ pub fn mvt(n: usize) -> Rc<Node> {
     Node::new_node(Stmt::Block(vec![i_loop_ref, m_loop_ref]))
 }
 
-pub fn trisolv(n: usize) -> Rc<Node> {
+pub fn trisolv(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating x[i] = b[i];
-    let mut s_ref_x1 = Node::new_ref("x", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_b = Node::new_ref("b", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_x1 = affine_ref("x", vec![n], &[0]);
+    let mut s_ref_b = affine_ref("b", vec![n], &[0]);
 
     // creating x[i] -= L[i][j] * x[j];
-    let mut s_ref_L1 = Node::new_ref("L", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_x2 = Node::new_ref("x", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_x3 = Node::new_ref("x", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_L1 = affine_ref("L", vec![n, n], &[0, 1]);
+    let mut s_ref_x2 = affine_ref("x", vec![n], &[1]);
+    let mut s_ref_x3 = affine_ref("x", vec![n], &[0]);
 
     // creating x[i] = x[i] / L[i][i]
-    let mut s_ref_L2 = Node::new_ref("L", vec![n, n], |ij| vec![ij[0] as usize, ij[0] as usize]);
+    let mut s_ref_L2 = affine_ref("L", vec![n, n], &[0, 0]);
     // s_ref_x1
 
     let mut j_loop_ref = Node::new_single_loop_dyn_ub("j", 0, move |i| i[0]);
@@ -202,26 +206,18 @@ pub fn trisolv(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn syrk(n: usize, m: usize) -> Rc<Node> {
+pub fn syrk(n: usize, m: usize) -> Arc<Node> {
     // n,m are array dimensions
     let ubound1 = n as i32;
     let ubound2 = m as i32;
 
     //creating C[i][j] = C[i][j] * beta
-    let mut s_ref_c1 = Node::new_ref("c", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_c1 = affine_ref("c", vec![n, n], &[0, 1]);
 
     // creating C[i][j] = C[i][j] + alpha * A[i][k] * A[j][k]
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n, m], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, m], |ijk| {
-        vec![ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_c2 = Node::new_ref("c", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a1 = affine_ref("a1", vec![n, m], &[0, 2]);
+    let mut s_ref_a2 = affine_ref("a2", vec![n, m], &[1, 2]);
+    let mut s_ref_c2 = affine_ref("c", vec![n, n], &[0, 1]);
 
     let mut j_loop_ref = Node::new_single_loop("j", 0, ubound1);
     Node::extend_loop_body(&mut j_loop_ref, &mut s_ref_c1);
@@ -246,32 +242,32 @@ pub fn syrk(n: usize, m: usize) -> Rc<Node> {
     Node::new_node(Stmt::Block(vec![i_loop_ref, k_loop_ref]))
 }
 
-pub fn syr2d(n: usize, m: usize) -> Rc<Node> {
+pub fn syr2d(n: usize, m: usize) -> Arc<Node> {
     // n,m are array dimensions
     let ubound1 = n as i32;
     let ubound2 = m as i32;
 
     // creating C[i][j] *= beta;
-    let mut s_ref_c = Node::new_ref("c", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
+    let mut s_ref_c = Node::new_ref("c", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
 
     // creating C[i][j] += A[j][k]*alpha*B[i][k] + B[j][k]*alpha*A[i][k];
     let mut s_ref_a1 = Node::new_ref("a1", vec![n, m], |ijkl| {
-        vec![ijkl[3] as usize, ijkl[2] as usize]
+        vec![ijkl[3] as i64, ijkl[2] as i64]
     });
     let mut s_ref_b1 = Node::new_ref("b1", vec![n, m], |ijkl| {
-        vec![ijkl[0] as usize, ijkl[2] as usize]
+        vec![ijkl[0] as i64, ijkl[2] as i64]
     });
     let mut s_ref_b2 = Node::new_ref("b2", vec![n, m], |ijkl| {
-        vec![ijkl[3] as usize, ijkl[2] as usize]
+        vec![ijkl[3] as i64, ijkl[2] as i64]
     });
     let mut s_ref_a2 = Node::new_ref("a2", vec![n, m], |ijkl| {
-        vec![ijkl[0] as usize, ijkl[2] as usize]
+        vec![ijkl[0] as i64, ijkl[2] as i64]
     });
     let mut s_ref_c1 = Node::new_ref("c1", vec![n, n], |ijkl| {
-        vec![ijkl[0] as usize, ijkl[3] as usize]
+        vec![ijkl[0] as i64, ijkl[3] as i64]
     });
     let mut s_ref_c2 = Node::new_ref("c2", vec![n, n], |ijkl| {
-        vec![ijkl[0] as usize, ijkl[3] as usize]
+        vec![ijkl[0] as i64, ijkl[3] as i64]
     });
 
     let mut l_loop_ref = loop_node!("l", 0 => |i : &[i32]| i[0]);
@@ -296,18 +292,12 @@ pub fn syr2d(n: usize, m: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn gemm(n: usize) -> Rc<Node> {
+pub fn gemm(n: usize) -> Arc<Node> {
     let ubound = n as i32;
 
-    let mut A0 = Node::new_ref("A", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut B0 = Node::new_ref("B", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut C0 = Node::new_ref("C", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut A0 = affine_ref("A", vec![n, n], &[0, 2]);
+    let mut B0 = affine_ref("B", vec![n, n], &[2, 1]);
+    let mut C0 = affine_ref("C", vec![n, n], &[0, 1]);
 
     let mut k_loop_ref = loop_node!("k", 0 => ubound);
     Node::extend_loop_body(&mut k_loop_ref, &mut A0);
@@ -326,34 +316,16 @@ pub fn gemm(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn _3mm(NI: usize, NJ: usize, NK: usize, NL: usize, NM: usize) -> Rc<Node> {
-    let mut s_ref_e = Node::new_ref("e", vec![NI, NJ], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a = Node::new_ref("a", vec![NI, NK], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b = Node::new_ref("b", vec![NK, NJ], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_f = Node::new_ref("f", vec![NJ, NL], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_c = Node::new_ref("c", vec![NJ, NM], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_d = Node::new_ref("d", vec![NM, NL], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_g = Node::new_ref("g", vec![NI, NL], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_e_2 = Node::new_ref("e", vec![NI, NJ], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_f_2 = Node::new_ref("f", vec![NJ, NL], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
+pub fn _3mm(NI: usize, NJ: usize, NK: usize, NL: usize, NM: usize) -> Arc<Node> {
+    let mut s_ref_e = affine_ref("e", vec![NI, NJ], &[0, 1]);
+    let mut s_ref_a = affine_ref("a", vec![NI, NK], &[0, 2]);
+    let mut s_ref_b = affine_ref("b", vec![NK, NJ], &[2, 1]);
+    let mut s_ref_f = affine_ref("f", vec![NJ, NL], &[0, 1]);
+    let mut s_ref_c = affine_ref("c", vec![NJ, NM], &[0, 2]);
+    let mut s_ref_d = affine_ref("d", vec![NM, NL], &[2, 1]);
+    let mut s_ref_g = affine_ref("g", vec![NI, NL], &[0, 1]);
+    let mut s_ref_e_2 = affine_ref("e", vec![NI, NJ], &[0, 2]);
+    let mut s_ref_f_2 = affine_ref("f", vec![NJ, NL], &[2, 1]);
 
     let mut knk_loop_ref = Node::new_single_loop("k", 0, NK as i32);
     Node::extend_loop_body(&mut knk_loop_ref, &mut s_ref_a);
@@ -398,22 +370,14 @@ pub fn _3mm(NI: usize, NJ: usize, NK: usize, NL: usize, NM: usize) -> Rc<Node> {
     ]))
 }
 
-pub fn _2mm(NI: usize, NJ: usize, NK: usize, NL: usize) -> Rc<Node> {
+pub fn _2mm(NI: usize, NJ: usize, NK: usize, NL: usize) -> Arc<Node> {
     let mut s_ref_tmp = Node::new_ref("tmp", vec![NI, NJ], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a = Node::new_ref("a", vec![NI, NK], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b = Node::new_ref("b", vec![NK, NJ], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_c = Node::new_ref("c", vec![NL, NJ], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_d = Node::new_ref("d", vec![NI, NL], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
+        vec![ijk[0] as i64, ijk[1] as i64]
     });
+    let mut s_ref_a = Node::new_ref("a", vec![NI, NK], |ijk| vec![ijk[0] as i64, ijk[2] as i64]);
+    let mut s_ref_b = Node::new_ref("b", vec![NK, NJ], |ijk| vec![ijk[2] as i64, ijk[1] as i64]);
+    let mut s_ref_c = Node::new_ref("c", vec![NL, NJ], |ijk| vec![ijk[2] as i64, ijk[1] as i64]);
+    let mut s_ref_d = Node::new_ref("d", vec![NI, NL], |ijk| vec![ijk[0] as i64, ijk[2] as i64]);
 
     let mut knk_loop_ref = Node::new_single_loop("k", 0, NK as i32);
     let mut knk_loop_ref_clone = knk_loop_ref.clone();
@@ -444,36 +408,27 @@ pub fn _2mm(NI: usize, NJ: usize, NK: usize, NL: usize) -> Rc<Node> {
 }
 
 //created by: Dylan McKellips
-pub fn cholesky(n: usize) -> Rc<Node> {
+pub fn cholesky(n: usize) -> Arc<Node> {
     let ubound = n as i32;
 
     //create A[i * N + j] -= A[i * N + k] * A[j * N + k];
-    let mut s_ref_aij1 = Node::new_ref("a", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_aik1 = Node::new_ref("a", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_ajk = Node::new_ref("a", vec![n, n], |ijk| {
-        vec![ijk[1] as usize, ijk[2] as usize]
-    });
+    let mut s_ref_aij1 = affine_ref("a", vec![n, n], &[0, 1]);
+    let mut s_ref_aik1 = affine_ref("a", vec![n, n], &[0, 2]);
+    let mut s_ref_ajk = affine_ref("a", vec![n, n], &[1, 2]);
 
     // create A[i * N + j] /= A[j * N + j];
 
-    let mut s_ref_aij2 = Node::new_ref("a", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_ajj = Node::new_ref("a", vec![n], |ijk| vec![ijk[1] as usize]);
+    let mut s_ref_aij2 = affine_ref("a", vec![n, n], &[0, 1]);
+    let mut s_ref_ajj = affine_ref("a", vec![n], &[1]);
 
     //create A[i * N + i] -= A[i * N + k] * A[i * N + k];
-    let mut s_ref_aii1 = Node::new_ref("a", vec![n], |ijk| vec![ijk[0] as usize]);
-    let mut s_ref_aik2 = Node::new_ref("a", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
+    let mut s_ref_aii1 = affine_ref("a", vec![n], &[0]);
+    // the k2 loop is directly in the i loop, so k is the second index here
+    let mut s_ref_aik2 = affine_ref("a", vec![n, n], &[0, 1]);
 
     //create A[i * N + i] = sqrt(A[i * N + i]);
 
-    let mut s_ref_aii2 = Node::new_ref("a", vec![n], |ijk| vec![ijk[0] as usize]);
+    let mut s_ref_aii2 = affine_ref("a", vec![n], &[0]);
 
     let mut k1_loop_ref = Node::new_single_loop_dyn_ub("k", 0, move |j| j[0]);
     Node::extend_loop_body(&mut k1_loop_ref, &mut s_ref_aik1);
@@ -503,43 +458,32 @@ pub fn cholesky(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn gramschmidt_trace(n: usize, m: usize) -> Rc<Node> {
+pub fn gramschmidt_trace(n: usize, m: usize) -> Arc<Node> {
+    // the arrays are flattened: A and Q hold m * n elements and R holds n * n.  They were
+    // declared with n elements, which the subscripts overran and so overlapped the next
+    // array's addresses; checked subscripts would now reject that.
+    let flat = |coeffs: &[usize]| AffineSub {
+        coeffs: vec![coeffs.iter().map(|&c| c as i64).collect()],
+        consts: vec![0],
+    };
     //nrm += A[i * N + k] * A[i * N + k];
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n], move |ki| {
-        vec![ki[1] as usize * n + ki[0] as usize]
-    });
+    let mut s_ref_a1 = Node::new_affine_ref("a1", vec![m * n], flat(&[1, n]));
 
     //R[k * N + k] = sqrt(nrm);
-    let mut s_ref_r1 = Node::new_ref("r1", vec![n], move |k| {
-        vec![k[0] as usize * n + k[0] as usize]
-    });
+    let mut s_ref_r1 = Node::new_affine_ref("r1", vec![n * n], flat(&[n + 1]));
 
     //Q[i * N + k] = A[i * N + k] / R[k * N + k];
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n], move |ki| {
-        vec![ki[1] as usize * n + ki[0] as usize]
-    });
-    let mut s_ref_r1_copy = Node::new_ref("r1", vec![n], move |ki| {
-        vec![ki[0] as usize * n + ki[0] as usize]
-    });
-    let mut s_ref_q1 = Node::new_ref("q1", vec![n], move |ki| {
-        vec![ki[1] as usize * n + ki[0] as usize]
-    });
+    let mut s_ref_a2 = Node::new_affine_ref("a2", vec![m * n], flat(&[1, n]));
+    let mut s_ref_r1_copy = Node::new_affine_ref("r1", vec![n * n], flat(&[n + 1]));
+    let mut s_ref_q1 = Node::new_affine_ref("q1", vec![m * n], flat(&[1, n]));
 
     //R[k * N + j] = 0.0;
-    let mut s_ref_r2 = Node::new_ref("r2", vec![n], move |kj| {
-        vec![kj[0] as usize * n + kj[1] as usize]
-    });
+    let mut s_ref_r2 = Node::new_affine_ref("r2", vec![n * n], flat(&[n, 1]));
 
     //R[k * N + j] += Q[i * N + k] * A[i * N + j];
-    let mut s_ref_q2 = Node::new_ref("q2", vec![n], move |kji| {
-        vec![kji[2] as usize * n + kji[0] as usize]
-    });
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n], move |kji| {
-        vec![kji[2] as usize * n + kji[1] as usize]
-    });
-    let mut s_ref_r3 = Node::new_ref("r3", vec![n], move |kji| {
-        vec![kji[0] as usize * n + kji[1] as usize]
-    });
+    let mut s_ref_q2 = Node::new_affine_ref("q2", vec![m * n], flat(&[1, 0, n]));
+    let mut s_ref_a3 = Node::new_affine_ref("a3", vec![m * n], flat(&[0, 1, n]));
+    let mut s_ref_r3 = Node::new_affine_ref("r3", vec![n * n], flat(&[n, 1]));
     //insert r3 clone here in this order
 
     //A[i * N + j] = A[i * N + j] - Q[i * N + k] * R[k * N + j];
@@ -578,7 +522,7 @@ pub fn gramschmidt_trace(n: usize, m: usize) -> Rc<Node> {
     k_loop_ref
 }
 
-pub fn heat_3d(m: usize, n: usize) -> Rc<Node> {
+pub fn heat_3d(m: usize, n: usize) -> Arc<Node> {
     // n: usize is array dim
     let ubound = n as i32; // loop bound
     let tsteps = m as i32; // steps bound
@@ -588,40 +532,49 @@ pub fn heat_3d(m: usize, n: usize) -> Rc<Node> {
     let mut j_loop_ref_1 = Node::new_single_loop("j_1", 0, ubound);
     let mut k_loop_ref_1 = Node::new_single_loop("k_1", 0, ubound);
 
-    let mut s_ref_a_1 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize + 1, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a_2 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a_3 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize - 1, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a_4 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize + 1, ijk[2] as usize]
-    });
-    let mut s_ref_a_5 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a_6 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize - 1, ijk[2] as usize]
-    });
-    let mut s_ref_a_7 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize + 1]
-    });
-    let mut s_ref_a_8 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a_9 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize - 1]
-    });
-    let mut s_ref_a_10 = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-
-    let mut s_ref_b = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
+    // the +1/-1 neighbours fall outside the arrays on the faces and are not accessed there
+    let mut s_ref_a_1 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 1), (1, 0), (2, 0)],
+    );
+    let mut s_ref_a_2 = affine_ref("A", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_a_3 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, -1), (1, 0), (2, 0)],
+    );
+    let mut s_ref_a_4 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 1), (2, 0)],
+    );
+    let mut s_ref_a_5 = affine_ref("A", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_a_6 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, -1), (2, 0)],
+    );
+    let mut s_ref_a_7 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 0), (2, 1)],
+    );
+    let mut s_ref_a_8 = affine_ref("A", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_a_9 = shifted_ref(
+        "A",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 0), (2, -1)],
+    );
+    let mut s_ref_a_10 = affine_ref("A", vec![n, n, n], &[0, 1, 2]);
+
+    let mut s_ref_b = affine_ref("B", vec![n, n, n], &[0, 1, 2]);
 
     Node::extend_loop_body(&mut k_loop_ref_1, &mut s_ref_a_1);
     Node::extend_loop_body(&mut k_loop_ref_1, &mut s_ref_a_2);
@@ -642,39 +595,47 @@ pub fn heat_3d(m: usize, n: usize) -> Rc<Node> {
     let mut j_loop_ref_2 = Node::new_single_loop("j_2", 0, ubound);
     let mut k_loop_ref_2 = Node::new_single_loop("k_2", 0, ubound);
 
-    let mut s_ref_b_1 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize + 1, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b_2 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b_3 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize - 1, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b_4 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize + 1, ijk[2] as usize]
-    });
-    let mut s_ref_b_5 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b_6 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize - 1, ijk[2] as usize]
-    });
-    let mut s_ref_b_7 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize + 1]
-    });
-    let mut s_ref_b_8 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_b_9 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize - 1]
-    });
-    let mut s_ref_b_10 = Node::new_ref("B", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a = Node::new_ref("A", vec![n, n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize, ijk[2] as usize]
-    });
+    let mut s_ref_b_1 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 1), (1, 0), (2, 0)],
+    );
+    let mut s_ref_b_2 = affine_ref("B", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_b_3 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, -1), (1, 0), (2, 0)],
+    );
+    let mut s_ref_b_4 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 1), (2, 0)],
+    );
+    let mut s_ref_b_5 = affine_ref("B", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_b_6 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, -1), (2, 0)],
+    );
+    let mut s_ref_b_7 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 0), (2, 1)],
+    );
+    let mut s_ref_b_8 = affine_ref("B", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_b_9 = shifted_ref(
+        "B",
+        vec![n, n, n],
+        Boundary::Skip,
+        &[(0, 0), (1, 0), (2, -1)],
+    );
+    let mut s_ref_b_10 = affine_ref("B", vec![n, n, n], &[0, 1, 2]);
+    let mut s_ref_a = affine_ref("A", vec![n, n, n], &[0, 1, 2]);
 
     Node::extend_loop_body(&mut k_loop_ref_2, &mut s_ref_b_1);
     Node::extend_loop_body(&mut k_loop_ref_2, &mut s_ref_b_2);
@@ -697,10 +658,10 @@ pub fn heat_3d(m: usize, n: usize) -> Rc<Node> {
     t_loop_ref
 }
 
-pub fn convolution_2d(ni: usize, nj: usize) -> Rc<Node> {
-    let mut mat_a_ref = Node::new_ref("A", vec![ni, nj], |ij| vec![ij[0] as usize, ij[1] as usize]);
+pub fn convolution_2d(ni: usize, nj: usize) -> Arc<Node> {
+    let mut mat_a_ref = affine_ref("A", vec![ni, nj], &[0, 1]);
 
-    let mut mat_b_ref = Node::new_ref("B", vec![ni, nj], |ij| vec![ij[0] as usize, ij[1] as usize]);
+    let mut mat_b_ref = affine_ref("B", vec![ni, nj], &[0, 1]);
 
     let mut i_ni_loop_ref = Node::new_single_loop("i", 1, (ni - 1) as i32);
     let mut j_nj_loop_ref = Node::new_single_loop("j", 1, (nj - 1) as i32);
@@ -712,46 +673,26 @@ pub fn convolution_2d(ni: usize, nj: usize) -> Rc<Node> {
     Node::new_node(Stmt::Block(vec![i_ni_loop_ref]))
 }
 
-pub fn symm(n: usize, m: usize) -> Rc<Node> {
+pub fn symm(n: usize, m: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound1 = n as i32;
     let ubound2 = m as i32;
 
     // creating c[k][j] += alpha * b[i][j] * a[i][k]
-    let mut s_ref_b1: Rc<Node> = Node::new_ref("b1", vec![m, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a1 = Node::new_ref("a1", vec![m, m], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_c1 = Node::new_ref("c1", vec![m, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_c2 = Node::new_ref("c2", vec![m, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_b1: Arc<Node> = affine_ref("b1", vec![m, n], &[0, 1]);
+    let mut s_ref_a1 = affine_ref("a1", vec![m, m], &[0, 2]);
+    let mut s_ref_c1 = affine_ref("c1", vec![m, n], &[2, 1]);
+    let mut s_ref_c2 = affine_ref("c2", vec![m, n], &[2, 1]);
 
     // creating tempt2 += b[k][j] * a[i][k]
-    let mut s_ref_b2 = Node::new_ref("b2", vec![m, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a2 = Node::new_ref("a2", vec![m, m], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
+    let mut s_ref_b2 = affine_ref("b2", vec![m, n], &[2, 1]);
+    let mut s_ref_a2 = affine_ref("a2", vec![m, m], &[0, 2]);
 
     // creating c[i][j] = beta * c[i][j] + alpha* b[i][j] * a[i][i] + alpha * temp2
-    let mut s_ref_c3 = Node::new_ref("c3", vec![m, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_b3 = Node::new_ref("b3", vec![m, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a3 = Node::new_ref("a3", vec![m, m], |ijk| {
-        vec![ijk[0] as usize, ijk[0] as usize]
-    });
-    let mut s_ref_c4 = Node::new_ref("c4", vec![m, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_c3 = affine_ref("c3", vec![m, n], &[0, 1]);
+    let mut s_ref_b3 = affine_ref("b3", vec![m, n], &[0, 1]);
+    let mut s_ref_a3 = affine_ref("a3", vec![m, m], &[0, 0]);
+    let mut s_ref_c4 = affine_ref("c4", vec![m, n], &[0, 1]);
 
     // creating loops
     let mut k_loop_ref = loop_node!("k", 0 => move |i : &[i32]| i[0]);
@@ -775,18 +716,19 @@ pub fn symm(n: usize, m: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn stencil(n: usize) -> Rc<Node> {
+pub fn stencil(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating b[i][j] =  a[i][j] + a[i][j] + a[i][j] + a[i][j] + a[i][j]
-    let mut s_ref_a1: Rc<Node> =
-        Node::new_ref("a1", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_a5 = Node::new_ref("a5", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_b = Node::new_ref("b", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
+    // the loops run to n inclusive, so the last row and column are clamped to the edge
+    let at_ij = &[(0, 0), (1, 0)];
+    let mut s_ref_a1: Arc<Node> = shifted_ref("a1", vec![n, n], Boundary::Clamp, at_ij);
+    let mut s_ref_a2 = shifted_ref("a2", vec![n, n], Boundary::Clamp, at_ij);
+    let mut s_ref_a3 = shifted_ref("a3", vec![n, n], Boundary::Clamp, at_ij);
+    let mut s_ref_a4 = shifted_ref("a4", vec![n, n], Boundary::Clamp, at_ij);
+    let mut s_ref_a5 = shifted_ref("a5", vec![n, n], Boundary::Clamp, at_ij);
+    let mut s_ref_b = shifted_ref("b", vec![n, n], Boundary::Clamp, at_ij);
 
     // creating loops
     let mut j_loop_ref = Node::new_single_loop("j", 0, ubound + 1);
@@ -803,41 +745,21 @@ pub fn stencil(n: usize) -> Rc<Node> {
     i_loop_ref
 }
 
-pub fn seidel_2d(m: usize, n: usize) -> Rc<Node> {
+pub fn seidel_2d(m: usize, n: usize) -> Arc<Node> {
     let ubound = n as i32;
     let tsteps = m as i32;
 
     // creating A[i][j] = A[i][j] + A[i][j] + A[i][j] + A[i][j] + A[i][j] + A[i][j] + A[i][j] + A[i][j] + A[i][j]
-    let mut s_ref_a1: Rc<Node> = Node::new_ref("a1", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a5 = Node::new_ref("a5", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a6 = Node::new_ref("a6", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a7 = Node::new_ref("a7", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a8 = Node::new_ref("a8", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a9 = Node::new_ref("a9", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a0 = Node::new_ref("a0", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
+    let mut s_ref_a1: Arc<Node> = affine_ref("a1", vec![n, n], &[1, 2]);
+    let mut s_ref_a2 = affine_ref("a2", vec![n, n], &[1, 2]);
+    let mut s_ref_a3 = affine_ref("a3", vec![n, n], &[1, 2]);
+    let mut s_ref_a4 = affine_ref("a4", vec![n, n], &[1, 2]);
+    let mut s_ref_a5 = affine_ref("a5", vec![n, n], &[1, 2]);
+    let mut s_ref_a6 = affine_ref("a6", vec![n, n], &[1, 2]);
+    let mut s_ref_a7 = affine_ref("a7", vec![n, n], &[1, 2]);
+    let mut s_ref_a8 = affine_ref("a8", vec![n, n], &[1, 2]);
+    let mut s_ref_a9 = affine_ref("a9", vec![n, n], &[1, 2]);
+    let mut s_ref_a0 = affine_ref("a0", vec![n, n], &[1, 2]);
 
     // creating loops
     let mut j_loop_ref = Node::new_single_loop("j", 1, ubound - 2);
@@ -861,75 +783,51 @@ pub fn seidel_2d(m: usize, n: usize) -> Rc<Node> {
     t_loop_ref
 }
 
-pub fn ludcmp(n: usize) -> Rc<Node> {
+pub fn ludcmp(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating w = A[i][j]
-    let mut s_ref_a1: Rc<Node> = Node::new_ref("a1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a1: Arc<Node> = affine_ref("a1", vec![n, n], &[0, 1]);
 
     // creating w -= A[i][k] * A[k][j]
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a2 = affine_ref("a2", vec![n, n], &[0, 2]);
+    let mut s_ref_a3 = affine_ref("a3", vec![n, n], &[2, 1]);
 
     // creating A[i][j] = w / A[j][j]
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |ijk| {
-        vec![ijk[1] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_a5 = Node::new_ref("a5", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a4 = affine_ref("a4", vec![n, n], &[1, 1]);
+    let mut s_ref_a5 = affine_ref("a5", vec![n, n], &[0, 1]);
 
     // creating w = A[i][j]
-    let mut s_ref_a6 = Node::new_ref("a6", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a6 = affine_ref("a6", vec![n, n], &[0, 1]);
 
     // creating w -= A[i][k] * A[k][j]
-    let mut s_ref_a7 = Node::new_ref("a7", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_a8 = Node::new_ref("a8", vec![n, n], |ijk| {
-        vec![ijk[2] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a7 = affine_ref("a7", vec![n, n], &[0, 2]);
+    let mut s_ref_a8 = affine_ref("a8", vec![n, n], &[2, 1]);
 
     // creating A[i][j] = w
-    let mut s_ref_a9 = Node::new_ref("a9", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_a9 = affine_ref("a9", vec![n, n], &[0, 1]);
 
     // creating w = b[i]
-    let mut s_ref_b1 = Node::new_ref("b1", vec![n], |ijk| vec![ijk[0] as usize]);
+    let mut s_ref_b1 = affine_ref("b1", vec![n], &[0]);
 
     // creating w -= A[i][j] * y[j]
-    let mut s_ref_a10 = Node::new_ref("a10", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_y1 = Node::new_ref("y1", vec![n], |ijk| vec![ijk[1] as usize]);
+    let mut s_ref_a10 = affine_ref("a10", vec![n, n], &[0, 1]);
+    let mut s_ref_y1 = affine_ref("y1", vec![n], &[1]);
 
     // creating y[i] = w
-    let mut s_ref_y2 = Node::new_ref("y2", vec![n], |ijk| vec![ijk[0] as usize]);
+    let mut s_ref_y2 = affine_ref("y2", vec![n], &[0]);
 
     // creating w = y[i]
-    let mut s_ref_y3 = Node::new_ref("y3", vec![n], |ijk| vec![ijk[0] as usize]);
+    let mut s_ref_y3 = affine_ref("y3", vec![n], &[0]);
 
     // creating w -= A[i][j] * x[j]
-    let mut s_ref_a11 = Node::new_ref("a11", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_x1 = Node::new_ref("x1", vec![n], |ijk| vec![ijk[1] as usize]);
+    let mut s_ref_a11 = affine_ref("a11", vec![n, n], &[0, 1]);
+    let mut s_ref_x1 = affine_ref("x1", vec![n], &[1]);
 
     // creating x[i] = w / A[i][i]
-    let mut s_ref_a12 = Node::new_ref("a12", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[0] as usize]
-    });
-    let mut s_ref_x2 = Node::new_ref("x2", vec![n], |ijk| vec![ijk[0] as usize]);
+    let mut s_ref_a12 = affine_ref("a12", vec![n, n], &[0, 0]);
+    let mut s_ref_x2 = affine_ref("x2", vec![n], &[0]);
 
     // creating loop
     let mut k_loop_upper = loop_node!("k", 0 => move |j : &[i32]| j[0]);
@@ -978,69 +876,37 @@ pub fn ludcmp(n: usize) -> Rc<Node> {
     Node::new_node(Stmt::Block(vec![i_loop_upper, i_loop_middle, i_loop_lower]))
 }
 
-pub fn nussinov(n: usize) -> Rc<Node> {
+pub fn nussinov(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating table[i][j] = max_score(table[i][j], table[i][j-1])
-    let s_ref_if1_t1 = Node::new_ref("if1_t1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let s_ref_if1_t2 = Node::new_ref("if1_t2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize - 1]
-    });
-    let s_ref_if1_t3 = Node::new_ref("if1_t3", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let s_ref_if1_t1 = affine_ref("if1_t1", vec![n, n], &[0, 1]);
+    let s_ref_if1_t2 = shifted_ref("if1_t2", vec![n, n], Boundary::Error, &[(0, 0), (1, -1)]);
+    let s_ref_if1_t3 = affine_ref("if1_t3", vec![n, n], &[0, 1]);
 
     // creating table[i][j] = max_score(table[i][j], table[i+1][j])
-    let s_ref_if2_t1 = Node::new_ref("if2_t1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let s_ref_if2_t2 = Node::new_ref("if2_t2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize + 1, ijk[1] as usize]
-    });
-    let s_ref_if2_t3 = Node::new_ref("if2_t3", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let s_ref_if2_t1 = affine_ref("if2_t1", vec![n, n], &[0, 1]);
+    let s_ref_if2_t2 = shifted_ref("if2_t2", vec![n, n], Boundary::Error, &[(0, 1), (1, 0)]);
+    let s_ref_if2_t3 = affine_ref("if2_t3", vec![n, n], &[0, 1]);
 
     // creating table[i][j] = max_score(table[i][j], table[i+1][j-1]+match(seq[i], seq[j]))
-    let s_ref_if3_t1 = Node::new_ref("if3_t1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let s_ref_if3_t2 = Node::new_ref("if3_t2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize + 1, ijk[1] as usize - 1]
-    });
-    let s_ref_if3_s1 = Node::new_ref("if3_s1", vec![n], |ijk| vec![ijk[0] as usize]);
-    let s_ref_if3_s2 = Node::new_ref("if3_s2", vec![n], |ijk| vec![ijk[1] as usize]);
-    let s_ref_if3_t3 = Node::new_ref("if3_t3", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let s_ref_if3_t1 = affine_ref("if3_t1", vec![n, n], &[0, 1]);
+    let s_ref_if3_t2 = shifted_ref("if3_t2", vec![n, n], Boundary::Error, &[(0, 1), (1, -1)]);
+    let s_ref_if3_s1 = affine_ref("if3_s1", vec![n], &[0]);
+    let s_ref_if3_s2 = affine_ref("if3_s2", vec![n], &[1]);
+    let s_ref_if3_t3 = affine_ref("if3_t3", vec![n, n], &[0, 1]);
 
     // creating table[i][j] = max_score(table[i][j], table[i+1][j-1])
-    let s_ref_else3_t1 = Node::new_ref("else3_t1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let s_ref_else3_t2 = Node::new_ref("else3_t2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize + 1, ijk[1] as usize - 1]
-    });
-    let s_ref_else3_t3 = Node::new_ref("else3_t3", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let s_ref_else3_t1 = affine_ref("else3_t1", vec![n, n], &[0, 1]);
+    let s_ref_else3_t2 = shifted_ref("else3_t2", vec![n, n], Boundary::Error, &[(0, 1), (1, -1)]);
+    let s_ref_else3_t3 = affine_ref("else3_t3", vec![n, n], &[0, 1]);
 
     // creating table[i][j] = max_score(table[i][j], table[i][k] + table[k+1][j])
-    let mut s_ref_t1 = Node::new_ref("t1", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
-    let mut s_ref_t2 = Node::new_ref("t2", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[2] as usize]
-    });
-    let mut s_ref_t3 = Node::new_ref("t3", vec![n, n], |ijk| {
-        vec![ijk[2] as usize + 1, ijk[1] as usize]
-    });
-    let mut s_ref_t4 = Node::new_ref("t4", vec![n, n], |ijk| {
-        vec![ijk[0] as usize, ijk[1] as usize]
-    });
+    let mut s_ref_t1 = affine_ref("t1", vec![n, n], &[0, 1]);
+    let mut s_ref_t2 = affine_ref("t2", vec![n, n], &[0, 2]);
+    let mut s_ref_t3 = shifted_ref("t3", vec![n, n], Boundary::Error, &[(2, 1), (1, 0)]);
+    let mut s_ref_t4 = affine_ref("t4", vec![n, n], &[0, 1]);
 
     // creating if else branches
     let q1 = Node::new_node(Stmt::Block(vec![s_ref_if1_t1, s_ref_if1_t2, s_ref_if1_t3]));
@@ -1106,21 +972,21 @@ pub fn nussinov(n: usize) -> Rc<Node> {
     i_loop
 }
 
-pub fn jacobi_1d(m: usize, n: usize) -> Rc<Node> {
+pub fn jacobi_1d(m: usize, n: usize) -> Arc<Node> {
     let ubound = n as i32;
     let tsteps = m as i32;
 
     // creating B[i] = 0.33333 * (A[i-1] + A[i] + A[i + 1]);
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n], |ti| vec![ti[1] as usize - 1]);
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n], |ti| vec![ti[1] as usize]);
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n], |ti| vec![ti[1] as usize + 1]);
-    let mut s_ref_b1 = Node::new_ref("b1", vec![n], |ti| vec![ti[1] as usize]);
+    let mut s_ref_a1 = shifted_ref("a1", vec![n], Boundary::Error, &[(1, -1)]);
+    let mut s_ref_a2 = affine_ref("a2", vec![n], &[1]);
+    let mut s_ref_a3 = shifted_ref("a3", vec![n], Boundary::Error, &[(1, 1)]);
+    let mut s_ref_b1 = affine_ref("b1", vec![n], &[1]);
 
     // creating A[i] = 0.33333 * (B[i-1] + B[i] + B[i + 1]);
-    let mut s_ref_b2 = Node::new_ref("b2", vec![n], |ti| vec![ti[1] as usize - 1]);
-    let mut s_ref_b3 = Node::new_ref("b3", vec![n], |ti| vec![ti[1] as usize]);
-    let mut s_ref_b4 = Node::new_ref("b4", vec![n], |ti| vec![ti[1] as usize + 1]);
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n], |ti| vec![ti[1] as usize]);
+    let mut s_ref_b2 = shifted_ref("b2", vec![n], Boundary::Error, &[(1, -1)]);
+    let mut s_ref_b3 = affine_ref("b3", vec![n], &[1]);
+    let mut s_ref_b4 = shifted_ref("b4", vec![n], Boundary::Error, &[(1, 1)]);
+    let mut s_ref_a4 = affine_ref("a4", vec![n], &[1]);
 
     // creating loops
     let mut i_loop1 = Node::new_single_loop("i", 1, ubound - 1);
@@ -1142,49 +1008,25 @@ pub fn jacobi_1d(m: usize, n: usize) -> Rc<Node> {
     t_loop
 }
 
-pub fn jacobi_2d(m: usize, n: usize) -> Rc<Node> {
+pub fn jacobi_2d(m: usize, n: usize) -> Arc<Node> {
     let ubound = n as i32;
     let tsteps = m as i32;
 
     // creating B[i][j] = 0.2 * (A[i][j] + A[i][j-1] + A[i][1+j] + A[(1+i)][j] + A[(i-1)][j]);
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize - 1]
-    });
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize + 1]
-    });
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |tij| {
-        vec![tij[1] as usize + 1, tij[2] as usize]
-    });
-    let mut s_ref_a5 = Node::new_ref("a5", vec![n, n], |tij| {
-        vec![tij[1] as usize - 1, tij[2] as usize]
-    });
-    let mut s_ref_b1 = Node::new_ref("b1", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
+    let mut s_ref_a1 = affine_ref("a1", vec![n, n], &[1, 2]);
+    let mut s_ref_a2 = shifted_ref("a2", vec![n, n], Boundary::Error, &[(1, 0), (2, -1)]);
+    let mut s_ref_a3 = shifted_ref("a3", vec![n, n], Boundary::Error, &[(1, 0), (2, 1)]);
+    let mut s_ref_a4 = shifted_ref("a4", vec![n, n], Boundary::Error, &[(1, 1), (2, 0)]);
+    let mut s_ref_a5 = shifted_ref("a5", vec![n, n], Boundary::Error, &[(1, -1), (2, 0)]);
+    let mut s_ref_b1 = affine_ref("b1", vec![n, n], &[1, 2]);
 
     // creating A[i][j] = 0.2 * (B[i][j] + B[i][j-1] + B[i][1+j] + B[(1+i)][j] + B[(i-1)][j]);
-    let mut s_ref_b2 = Node::new_ref("b2", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
-    let mut s_ref_b3 = Node::new_ref("b3", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize - 1]
-    });
-    let mut s_ref_b4 = Node::new_ref("b4", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize + 1]
-    });
-    let mut s_ref_b5 = Node::new_ref("b5", vec![n, n], |tij| {
-        vec![tij[1] as usize + 1, tij[2] as usize]
-    });
-    let mut s_ref_b6 = Node::new_ref("b6", vec![n, n], |tij| {
-        vec![tij[1] as usize - 1, tij[2] as usize]
-    });
-    let mut s_ref_a6 = Node::new_ref("a6", vec![n, n], |tij| {
-        vec![tij[1] as usize, tij[2] as usize]
-    });
+    let mut s_ref_b2 = affine_ref("b2", vec![n, n], &[1, 2]);
+    let mut s_ref_b3 = shifted_ref("b3", vec![n, n], Boundary::Error, &[(1, 0), (2, -1)]);
+    let mut s_ref_b4 = shifted_ref("b4", vec![n, n], Boundary::Error, &[(1, 0), (2, 1)]);
+    let mut s_ref_b5 = shifted_ref("b5", vec![n, n], Boundary::Error, &[(1, 1), (2, 0)]);
+    let mut s_ref_b6 = shifted_ref("b6", vec![n, n], Boundary::Error, &[(1, -1), (2, 0)]);
+    let mut s_ref_a6 = affine_ref("a6", vec![n, n], &[1, 2]);
 
     // creating loops
     let mut j_loop1 = Node::new_single_loop("j", 1, ubound - 1);
@@ -1216,29 +1058,29 @@ pub fn jacobi_2d(m: usize, n: usize) -> Rc<Node> {
     t_loop
 }
 
-pub fn gesummv(n: usize) -> Rc<Node> {
+pub fn gesummv(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating tmp[i] = 0 and y[i] = 0;
-    let mut s_ref_tmp1 = Node::new_ref("tmp1", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_y1 = Node::new_ref("y1", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_tmp1 = affine_ref("tmp1", vec![n], &[0]);
+    let mut s_ref_y1 = affine_ref("y1", vec![n], &[0]);
 
     // creating tmp[i] = A[i][j] * x[j] + tmp[i];
     // creating y[i] = B[i][j] * x[j] + y[i];
-    let mut s_ref_a = Node::new_ref("a", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_x1 = Node::new_ref("x1", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_tmp2 = Node::new_ref("tmp2", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_tmp3 = Node::new_ref("tmp3", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_b = Node::new_ref("b", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_x2: Rc<Node> = Node::new_ref("x2", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_y2 = Node::new_ref("y2", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_y3 = Node::new_ref("y3", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_a = affine_ref("a", vec![n, n], &[0, 1]);
+    let mut s_ref_x1 = affine_ref("x1", vec![n], &[1]);
+    let mut s_ref_tmp2 = affine_ref("tmp2", vec![n], &[0]);
+    let mut s_ref_tmp3 = affine_ref("tmp3", vec![n], &[0]);
+    let mut s_ref_b = affine_ref("b", vec![n, n], &[0, 1]);
+    let mut s_ref_x2: Arc<Node> = affine_ref("x2", vec![n], &[1]);
+    let mut s_ref_y2 = affine_ref("y2", vec![n], &[0]);
+    let mut s_ref_y3 = affine_ref("y3", vec![n], &[0]);
 
     // creating y[i] = alpha * tmp[i] + beta * y[i];
-    let mut s_ref_tmp4 = Node::new_ref("tmp4", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_y4 = Node::new_ref("y4", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_y5 = Node::new_ref("y5", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_tmp4 = affine_ref("tmp4", vec![n], &[0]);
+    let mut s_ref_y4 = affine_ref("y4", vec![n], &[0]);
+    let mut s_ref_y5 = affine_ref("y5", vec![n], &[0]);
 
     // creating loops
     let mut j_loop = Node::new_single_loop("j", 0, ubound);
@@ -1262,34 +1104,34 @@ pub fn gesummv(n: usize) -> Rc<Node> {
     i_loop
 }
 
-pub fn gemver(n: usize) -> Rc<Node> {
+pub fn gemver(n: usize) -> Arc<Node> {
     // n : usize is size of array
     let ubound = n as i32;
 
     // creating A[i][j] = A[i][j] + u1[i] * v1[j] + u2[i] * v2[j]
-    let mut s_ref_a1 = Node::new_ref("a1", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_u1 = Node::new_ref("u1", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_v1 = Node::new_ref("v1", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_u2 = Node::new_ref("u2", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_v2 = Node::new_ref("v2", vec![n, n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
+    let mut s_ref_a1 = Node::new_ref("a1", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
+    let mut s_ref_u1 = Node::new_ref("u1", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_v1 = Node::new_ref("v1", vec![n], |ij| vec![ij[1] as i64]);
+    let mut s_ref_u2 = Node::new_ref("u2", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_v2 = Node::new_ref("v2", vec![n, n], |ij| vec![ij[1] as i64]);
+    let mut s_ref_a2 = Node::new_ref("a2", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
 
     // creating x[i] = x[i] + beta * A[j][i] * y[j]
-    let mut s_ref_x1 = Node::new_ref("x1", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |ij| vec![ij[1] as usize, ij[0] as usize]);
-    let mut s_ref_y = Node::new_ref("y", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_x2 = Node::new_ref("x2", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_x1 = Node::new_ref("x1", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_a3 = Node::new_ref("a3", vec![n, n], |ij| vec![ij[1] as i64, ij[0] as i64]);
+    let mut s_ref_y = Node::new_ref("y", vec![n], |ij| vec![ij[1] as i64]);
+    let mut s_ref_x2 = Node::new_ref("x2", vec![n], |ij| vec![ij[0] as i64]);
 
     // creating x[i] = x[i] + z[i]
-    let mut s_ref_x3 = Node::new_ref("x3", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_z = Node::new_ref("z", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_x4 = Node::new_ref("x4", vec![n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_x3 = Node::new_ref("x3", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_z = Node::new_ref("z", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_x4 = Node::new_ref("x4", vec![n], |ij| vec![ij[0] as i64]);
 
     // creating w[i] = w[i] + alpha * A[i][j] * x[j]
-    let mut s_ref_w1 = Node::new_ref("w1", vec![n], |ij| vec![ij[0] as usize]);
-    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |ij| vec![ij[0] as usize, ij[1] as usize]);
-    let mut s_ref_x5 = Node::new_ref("x5", vec![n], |ij| vec![ij[1] as usize]);
-    let mut s_ref_w2 = Node::new_ref("w2", vec![n, n], |ij| vec![ij[0] as usize]);
+    let mut s_ref_w1 = Node::new_ref("w1", vec![n], |ij| vec![ij[0] as i64]);
+    let mut s_ref_a4 = Node::new_ref("a4", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
+    let mut s_ref_x5 = Node::new_ref("x5", vec![n], |ij| vec![ij[1] as i64]);
+    let mut s_ref_w2 = Node::new_ref("w2", vec![n, n], |ij| vec![ij[0] as i64]);
 
     // creating loops
     let mut j_loop1 = Node::new_single_loop("j", 0, ubound);
@@ -1333,6 +1175,31 @@ pub fn gemver(n: usize) -> Rc<Node> {
 mod tests {
 
     use super::*;
+
+    #[test]
+    fn compiled_kernels() {
+        // the affine references step their addresses, and give the interpreted trace
+        let kernels = [
+            lu(9),
+            mvt(9),
+            trisolv(9),
+            syrk(9, 7),
+            gemm(9),
+            cholesky(9),
+            gramschmidt_trace(9, 7),
+            heat_3d(3, 9),
+            stencil(9),
+            jacobi_2d(3, 9),
+        ];
+        for mut code in kernels {
+            dace::arybase::set_arybase(&mut code);
+            let mut interpreted = static_rd::sink::AccessList::new();
+            static_rd::trace::trace_to(&code, &mut [&mut interpreted]);
+            let mut compiled = Vec::new();
+            dace::compile::compile(&code).run(|elem, _| compiled.push(elem));
+            assert_eq!(*interpreted.accesses.get_vec(), compiled);
+        }
+    }
     #[test]
     fn trmm_trace_test() {
         assert_eq!(trmm_trace(1024, 1024).node_count(), 8);
@@ -1371,6 +1238,23 @@ mod tests {
         println!("hist: {}", hist);
     }
 
+    #[test]
+    fn test_gemm_opt_vs_lru() {
+        let mut trace = gemm(16);
+        dace::arybase::set_arybase(&mut trace);
+        let lru = static_rd::trace::trace_hist(&trace, static_rd::LRUSplay::new());
+        let opt = static_rd::trace::trace_hist_opt(&trace);
+        for size in [16, 64, 256] {
+            println!(
+                "gemm {} lines: LRU {} misses, OPT {} misses",
+                size,
+                lru.misses(size),
+                opt.misses(size)
+            );
+            assert!(opt.misses(size) <= lru.misses(size));
+        }
+    }
+
     #[test]
     fn test_gemm_rd_scale_tree() {
         use std::time::Instant;
@@ -1417,7 +1301,14 @@ mod tests {
 
     #[test]
     fn test_stencil() {
-        assert_eq!(stencil(1024).node_count(), 8)
+        let tree = stencil(1024);
+        assert_eq!(tree.node_count(), 8);
+        let edge = tree
+            .loop_only(|i| i.body[0].loop_only(|j| j.body[0].ref_only(|r| r.offset(&[1024, 1024]))))
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(edge, Some(1024 * 1024 - 1));
     }
 
     #[test]
@@ -24,6 +24,28 @@ impl Hist {
             .or_insert(1);
     }
 
+    /// Record `count` accesses with reuse distance `d` at once.
+    pub fn add_dists(&mut self, d: Option<usize>, count: usize) {
+        *self.hist.entry(d).or_insert(0) += count;
+    }
+
+    /// Add the counts of `other`, e.g. a histogram of another part of the trace.
+    pub fn merge(&mut self, other: &Hist) {
+        for (&d, &cnt) in other.hist.iter() {
+            *self.hist.entry(d).or_insert(0) += cnt;
+        }
+    }
+
+    /// The misses of a fully associative cache of `size` lines: the accesses with a
+    /// distance over `size` and the first accesses.
+    pub fn misses(&self, size: usize) -> usize {
+        self.hist
+            .iter()
+            .filter(|(d, _)| !d.is_some_and(|d| d <= size))
+            .map(|(_, cnt)| cnt)
+            .sum()
+    }
+
     pub fn to_vec(&self) -> Vec<(Option<usize>, usize)> {
         let mut h2 = self.hist.clone();
         let inf_rds = h2.remove(&None);
@@ -91,5 +113,17 @@ value, count
 
         // use cargo test -- --show-output to see the result
         println!("{}", h);
+
+        let mut h2 = Hist::new();
+        h2.add_dist(Some(1));
+        h2.add_dist(Some(7));
+        h2.merge(&h);
+        assert_eq!(
+            h2.to_vec(),
+            [(Some(1), 3), (Some(7), 1), (Some(100), 1), (None, 1)]
+        );
+        assert_eq!(h2.misses(1), 3);
+        assert_eq!(h2.misses(7), 2);
+        assert_eq!(h2.misses(100), 1);
     }
 }
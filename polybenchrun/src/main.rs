@@ -3,12 +3,18 @@ use dace_tests::polybench::{
     _2mm, _3mm, cholesky, gemm, gramschmidt_trace, lu, mvt, syr2d, syrk, trisolv, trmm_trace,
 };
 
+use dace::arybase::set_arybase;
+use static_rd::checkpoint::trace_resumable;
 use static_rd::trace::trace;
 use static_rd::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{env, time::Duration, time::Instant};
 
+/// Accesses between two checkpoints of a resumable run.
+const CHECKPOINT_EVERY: usize = 1 << 26;
+
 fn duration_to_string(duration: Duration) -> String {
     let total_seconds = duration.as_secs();
     let hours = total_seconds / 3600;
@@ -20,9 +26,11 @@ fn duration_to_string(duration: Duration) -> String {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    // `--resume` continues from the checkpoint of an interrupted run with the same arguments
+    let resume = env::args().any(|arg| arg == "--resume");
+    let args: Vec<String> = env::args().filter(|arg| arg != "--resume").collect();
     if args.len() < 6 {
-        println!("Format:   exe   lru_mode   test_mode   data1,data2,data3,data4,...");
+        println!("Format:   exe   lru_mode   test_mode   data1,data2,data3,data4,...   [--resume]");
         return Ok(());
     }
 
@@ -95,10 +103,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let start = Instant::now();
     let split: Vec<&str> = lru_mode.split(',').collect();
+    let ckpt = PathBuf::from(format!("{}_{}_{}.ckpt", *t_mode, *lru_mode, *argdata));
+    let params = [
+        ("test_mode".to_string(), t_mode.clone()),
+        ("lru_mode".to_string(), lru_mode.clone()),
+        ("data".to_string(), argdata.clone()),
+    ];
+    set_arybase(&mut loop_code);
     let result = match split[0] {
-        "Olken" => trace(&mut loop_code, LRUSplay::<usize>::new()),
-        "Stack" => trace(&mut loop_code, LRUStack::<usize>::new()),
-        "Vec" => trace(&mut loop_code, LRUVec::<usize>::new()),
+        "Fenwick" => trace_resumable::<LRUFenwick<usize>>(
+            &loop_code,
+            &ckpt,
+            CHECKPOINT_EVERY,
+            resume,
+            &params,
+        )?,
+        "Stack" => trace_resumable::<LRUStack<usize>>(
+            &loop_code,
+            &ckpt,
+            CHECKPOINT_EVERY,
+            resume,
+            &params,
+        )?,
+        "Vec" => {
+            trace_resumable::<LRUVec<usize>>(&loop_code, &ckpt, CHECKPOINT_EVERY, resume, &params)?
+        }
+        // the scale tree merges nodes and cannot be rebuilt from a stack, so it has no checkpoints
         "Scale" => trace(
             &mut loop_code,
             LRUScaleTree::<usize>::new(
@@ -106,7 +136,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 split[2].parse::<usize>().unwrap(),
             ),
         ),
-        _ => trace(&mut loop_code, LRUSplay::<usize>::new()),
+        _ => trace_resumable::<LRUSplay<usize>>(
+            &loop_code,
+            &ckpt,
+            CHECKPOINT_EVERY,
+            resume,
+            &params,
+        )?,
     };
 
     let time_elapsed = start.elapsed();
@@ -0,0 +1,187 @@
+//! LRU stacks cut at a maximum depth.
+//!
+//! Distances are usually only needed up to the largest cache size of interest.  Keeping
+//! only that many keys in the stack bounds memory by the cache size rather than by the
+//! footprint, at the price of telling first accesses from long reuses, unless the keys
+//! ever accessed are kept as well.
+
+use crate::{Reuse, LRU};
+use fxhash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+/// An LRU stack of at most `capacity` keys, the least recently used leaving `L` when
+/// there are more.  A distance past `capacity` is not known: an access to a key not in
+/// the stack gives `Some(capacity + 1)`, which misses in every cache of at most
+/// `capacity` lines, whether it is a first access or a long reuse.
+pub struct Bounded<T, L> {
+    lru: L,
+    capacity: usize,
+    /// The time of the last access of every key in the stack.
+    last: FxHashMap<T, usize>,
+    /// The keys in the stack by the time of their last access.
+    by_time: BTreeMap<usize, T>,
+    /// Every key accessed and not invalidated, to give first accesses `None`.
+    seen: Option<FxHashSet<T>>,
+    time: usize,
+}
+
+impl<T: Eq + Hash + Clone, L: LRU<T>> Bounded<T, L> {
+    /// Bound `lru`, which should be empty, to `capacity` keys.
+    pub fn new(lru: L, capacity: usize) -> Self {
+        assert!(capacity > 0, "a bounded stack needs a key");
+        Bounded {
+            lru,
+            capacity,
+            last: FxHashMap::default(),
+            by_time: BTreeMap::new(),
+            seen: None,
+            time: 0,
+        }
+    }
+
+    /// `new`, but a first access gives `None` apart from the long reuses.  This keeps
+    /// every key ever accessed, so memory grows with the footprint again, though by a
+    /// set of keys rather than a stack.
+    pub fn with_cold_misses(lru: L, capacity: usize) -> Self {
+        Bounded {
+            seen: Some(FxHashSet::default()),
+            ..Bounded::new(lru, capacity)
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<T: Eq + Hash + Clone, L: LRU<T>> LRU<T> for Bounded<T, L> {
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        let mut reuse = self.lru.rec_reuse(val.clone());
+        match self.last.insert(val.clone(), self.time) {
+            Some(t) => {
+                self.by_time.remove(&t);
+            }
+            None => {
+                let first = match &mut self.seen {
+                    Some(seen) => seen.insert(val.clone()),
+                    None => false,
+                };
+                if !first {
+                    reuse.distance = Some(self.capacity + 1);
+                }
+            }
+        }
+        self.by_time.insert(self.time, val);
+        self.time += 1;
+        if self.last.len() > self.capacity {
+            let (_, oldest) = self.by_time.pop_first().unwrap();
+            self.last.remove(&oldest);
+            self.lru.invalidate(&oldest);
+        }
+        reuse
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        self.lru.depth(val)
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        if let Some(seen) = &mut self.seen {
+            seen.remove(val);
+        }
+        let Some(t) = self.last.remove(val) else {
+            return false;
+        };
+        self.by_time.remove(&t);
+        self.lru.invalidate(val)
+    }
+
+    fn reset(&mut self) {
+        for key in std::mem::take(&mut self.by_time).into_values() {
+            self.lru.invalidate(&key);
+        }
+        self.last.clear();
+        if let Some(seen) = &mut self.seen {
+            seen.clear();
+        }
+        self.time = 0;
+    }
+
+    fn distinct(&self) -> usize {
+        self.last.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fenwick::LRUFenwick;
+    use crate::olken::LRUSplay;
+    use crate::stack::LRUStack;
+
+    fn test_trace() -> Vec<u64> {
+        (0..30_000u64)
+            .map(|i| (i * 7 + i / 11 + i * i % 13) % 2000 + (i / 5000) * 1000)
+            .collect()
+    }
+
+    fn check<L: LRU<u64>>(mut bounded: Bounded<u64, L>) {
+        let mut exact = LRUSplay::new();
+        let (cold, far) = (bounded.seen.is_some(), bounded.capacity() + 1);
+        for x in test_trace() {
+            let d = match exact.rec_access(x) {
+                None if cold => None,
+                d => Some(d.map_or(far, |d| d.min(far))),
+            };
+            assert_eq!(bounded.rec_access(x), d);
+            assert!(bounded.distinct() <= bounded.capacity());
+        }
+    }
+
+    #[test]
+    fn cut_at_capacity() {
+        let mut bounded = Bounded::new(LRUStack::new(), 2);
+        let dists: Vec<_> = "abcacbb".chars().map(|c| bounded.rec_access(c)).collect();
+        let far = Some(3);
+        assert_eq!(dists, [far, far, far, far, Some(2), far, Some(1)]);
+        assert_eq!(bounded.lru.stack.len(), 2);
+        assert_eq!((bounded.depth(&'a'), bounded.depth(&'c')), (None, Some(2)));
+        assert_eq!(bounded.last.len(), 2);
+        // an access past the capacity has no reuse interval
+        assert_eq!(bounded.rec_reuse('a').interval, None);
+    }
+
+    #[test]
+    fn cold_misses_apart_from_long_reuses() {
+        let trace: Vec<u64> = (0..10).chain(0..10).collect();
+        let mut merged = Bounded::new(LRUSplay::new(), 3);
+        let dists: Vec<_> = trace.iter().map(|&x| merged.rec_access(x)).collect();
+        assert_eq!(dists, [Some(4); 20]);
+
+        let mut bounded = Bounded::with_cold_misses(LRUSplay::new(), 3);
+        let dists: Vec<_> = trace.iter().map(|&x| bounded.rec_access(x)).collect();
+        assert_eq!(dists[..10], [None; 10]);
+        assert_eq!(dists[10..], [Some(4); 10]);
+        assert_eq!((bounded.distinct(), bounded.last.len()), (3, 3));
+        // an invalidated key is accessed anew
+        assert!(!bounded.invalidate(&0) && bounded.invalidate(&9));
+        assert_eq!((bounded.rec_access(0), bounded.rec_access(9)), (None, None));
+        assert_eq!(bounded.rec_access(5), Some(4));
+        bounded.reset();
+        assert_eq!(bounded.rec_access(5), None);
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut Bounded::with_cold_misses(LRUStack::new(), 3));
+    }
+
+    #[test]
+    fn same_as_unbounded_up_to_capacity() {
+        check(Bounded::new(LRUSplay::new(), 500));
+        check(Bounded::new(LRUFenwick::new(), 1500));
+        check(Bounded::new(LRUStack::new(), 100));
+        check(Bounded::with_cold_misses(LRUSplay::new(), 300));
+    }
+}
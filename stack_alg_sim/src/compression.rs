@@ -1 +1,559 @@
-use std::{ptr::NonNull, rc::Rc};
+//! Lossless compression of address traces.
+//!
+//! A trace is stored as a list of segments.  A `Run` is a strided sequence of addresses,
+//! and a `Repeat` is a repeated pattern of segments where every copy moves each strided
+//! run of the pattern by its own constant shift.  An affine loop nest therefore folds into
+//! one `Repeat` per loop, whatever the number of iterations: the innermost loop is a
+//! repeat over the references of the body, with a shift per reference, the next loop a
+//! repeat of that repeat, and so on.
+//!
+//! Compression streams: every level of folding holds a bounded window of segments and
+//! the repeat it is extending, and passes a segment on to the next level once it is
+//! closed, so a trace can be compressed as it is generated.
+
+use std::mem;
+
+/// Longest pattern, in segments, that `compress` looks for.
+const MAX_WINDOW: usize = 16;
+/// Fewest copies that make a repeat.  Any two patterns of the same shape are related by
+/// some shifts, so only a third copy shows that the shifts are regular.
+const MIN_COUNT: usize = 3;
+/// Segments a level holds before it decides how to fold the first one: enough to see
+/// `MIN_COUNT` copies of the longest pattern.
+const LOOKAHEAD: usize = MAX_WINDOW * MIN_COUNT;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// `len` addresses `start, start + stride, ...`
+    Run { start: i64, stride: i64, len: usize },
+    /// `count` copies of `body`.  In copy k the r-th run of `body`, in depth-first order,
+    /// starts `k * shifts[r]` after its start in copy 0.
+    Repeat {
+        body: Vec<Segment>,
+        count: usize,
+        shifts: Vec<i64>,
+    },
+}
+
+impl Segment {
+    /// Number of addresses.
+    pub fn len(&self) -> usize {
+        match self {
+            Segment::Run { len, .. } => *len,
+            Segment::Repeat { body, count, .. } => {
+                count * body.iter().map(Segment::len).sum::<usize>()
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of runs in the segment, counting those in a repeated body once.
+    fn runs(&self) -> usize {
+        match self {
+            Segment::Run { .. } => 1,
+            Segment::Repeat { body, .. } => body.iter().map(Segment::runs).sum(),
+        }
+    }
+
+    /// Number of integers it takes to store the segment.
+    fn words(&self) -> usize {
+        match self {
+            Segment::Run { .. } => 3,
+            Segment::Repeat { body, shifts, .. } => {
+                1 + shifts.len() + body.iter().map(Segment::words).sum::<usize>()
+            }
+        }
+    }
+
+    /// Two segments have the same shape if they differ only in where their runs start.
+    fn same_shape(&self, other: &Segment) -> bool {
+        match (self, other) {
+            (
+                Segment::Run { stride, len, .. },
+                Segment::Run {
+                    stride: stride2,
+                    len: len2,
+                    ..
+                },
+            ) => stride == stride2 && len == len2,
+            (
+                Segment::Repeat {
+                    body,
+                    count,
+                    shifts,
+                },
+                Segment::Repeat {
+                    body: body2,
+                    count: count2,
+                    shifts: shifts2,
+                },
+            ) => count == count2 && shifts == shifts2 && same_shapes(body, body2),
+            _ => false,
+        }
+    }
+
+    /// Push the start of every run, in depth-first order.
+    fn starts(&self, out: &mut Vec<i64>) {
+        match self {
+            Segment::Run { start, .. } => out.push(*start),
+            Segment::Repeat { body, .. } => body.iter().for_each(|s| s.starts(out)),
+        }
+    }
+
+    /// Append the addresses, with the r-th run moved by `offsets[r]`.
+    fn expand(&self, offsets: &[i64], out: &mut Vec<usize>) {
+        match self {
+            Segment::Run { start, stride, len } => {
+                let start = start + offsets[0];
+                out.extend((0..*len as i64).map(|i| (start + i * stride) as usize));
+            }
+            Segment::Repeat {
+                body,
+                count,
+                shifts,
+            } => {
+                let mut moved = offsets.to_vec();
+                for _ in 0..*count {
+                    let mut rest = moved.as_slice();
+                    for seg in body.iter() {
+                        let (mine, others) = rest.split_at(seg.runs());
+                        seg.expand(mine, out);
+                        rest = others;
+                    }
+                    moved.iter_mut().zip(shifts).for_each(|(o, s)| *o += s);
+                }
+            }
+        }
+    }
+}
+
+fn same_shapes(a: &[Segment], b: &[Segment]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.same_shape(y))
+}
+
+fn starts(segs: &[Segment]) -> Vec<i64> {
+    let mut out = Vec::new();
+    segs.iter().for_each(|s| s.starts(&mut out));
+    out
+}
+
+/// A repeat of a single run that continues the run is a longer run.
+fn simplify(seg: Segment) -> Segment {
+    if let Segment::Repeat {
+        body,
+        count,
+        shifts,
+    } = &seg
+    {
+        if let [Segment::Run { start, stride, len }] = body.as_slice() {
+            if *len == 1 || shifts[0] == stride * *len as i64 {
+                let stride = if *len == 1 { shifts[0] } else { *stride };
+                return Segment::Run {
+                    start: *start,
+                    stride,
+                    len: len * count,
+                };
+            }
+        }
+    }
+    seg
+}
+
+/// The longest repeat of `segs[..w]` at the front of `segs`, as (count, shifts).
+fn repeat_at(segs: &[Segment], w: usize) -> Option<(usize, Vec<i64>)> {
+    let (first, second) = (segs.get(..w)?, segs.get(w..2 * w)?);
+    if !same_shapes(first, second) {
+        return None;
+    }
+    let base = starts(first);
+    let shifts: Vec<i64> = starts(second)
+        .iter()
+        .zip(&base)
+        .map(|(b, a)| b - a)
+        .collect();
+    let mut count = 2;
+    while let Some(next) = segs.get(count * w..(count + 1) * w) {
+        let expected = base.iter().zip(&shifts).map(|(a, s)| a + count as i64 * s);
+        if !same_shapes(first, next) || !expected.eq(starts(next)) {
+            break;
+        }
+        count += 1;
+    }
+    (count >= MIN_COUNT).then_some((count, shifts))
+}
+
+/// The repeat at the front of `segs` that covers the most segments, as (window, count,
+/// shifts), preferring the shorter pattern.
+fn best_repeat(segs: &[Segment]) -> Option<(usize, usize, Vec<i64>)> {
+    (1..=MAX_WINDOW)
+        .filter_map(|w| repeat_at(segs, w).map(|(count, shifts)| (w, count, shifts)))
+        .max_by_key(|(w, count, _)| (w * count, std::cmp::Reverse(*w)))
+}
+
+/// A repeat that may go on with the segments still to come.
+struct Open {
+    body: Vec<Segment>,
+    count: usize,
+    shifts: Vec<i64>,
+    /// The run starts of the next copy.
+    expected: Vec<i64>,
+    /// The segments of the next copy so far, and the number of runs in them.
+    next: Vec<Segment>,
+    next_runs: usize,
+}
+
+impl Open {
+    fn new(body: Vec<Segment>, count: usize, shifts: Vec<i64>) -> Self {
+        let expected = starts(&body)
+            .iter()
+            .zip(&shifts)
+            .map(|(a, s)| a + count as i64 * s)
+            .collect();
+        Open {
+            body,
+            count,
+            shifts,
+            expected,
+            next: Vec::new(),
+            next_runs: 0,
+        }
+    }
+
+    /// Add `seg` to the next copy.  Returns false, without adding it, if the copy breaks.
+    fn extend(&mut self, seg: &Segment) -> bool {
+        let runs = seg.runs();
+        if self.next.len() >= self.body.len()
+            || !seg.same_shape(&self.body[self.next.len()])
+            || self.next_runs + runs > self.expected.len()
+        {
+            return false;
+        }
+        let expected = &self.expected[self.next_runs..self.next_runs + runs];
+        if starts(std::slice::from_ref(seg)) != expected {
+            return false;
+        }
+        self.next.push(seg.clone());
+        self.next_runs += runs;
+        if self.next.len() == self.body.len() {
+            self.count += 1;
+            self.expected
+                .iter_mut()
+                .zip(&self.shifts)
+                .for_each(|(e, s)| *e += s);
+            self.next.clear();
+            self.next_runs = 0;
+        }
+        true
+    }
+
+    /// The repeat so far, and the segments of the unfinished copy.
+    fn close(self) -> (Segment, Vec<Segment>) {
+        let repeat = simplify(Segment::Repeat {
+            body: self.body,
+            count: self.count,
+            shifts: self.shifts,
+        });
+        (repeat, self.next)
+    }
+}
+
+/// One pass of folding repeated patterns over a stream of segments.  At each position
+/// it folds the pattern that covers the most segments, looking `LOOKAHEAD` segments
+/// ahead, or extending a repeat for as long as it goes on.
+#[derive(Default)]
+struct Level {
+    window: Vec<Segment>,
+    open: Option<Open>,
+    /// Whether the level has folded anything, so that another pass may fold more.
+    folded: bool,
+}
+
+impl Level {
+    fn push(&mut self, seg: Segment, out: &mut Vec<Segment>) {
+        if let Some(open) = &mut self.open {
+            if open.extend(&seg) {
+                return;
+            }
+            let (repeat, rest) = self.open.take().unwrap().close();
+            out.push(repeat);
+            self.folded = true;
+            self.window.extend(rest);
+        }
+        self.window.push(seg);
+        self.fold(out, false);
+    }
+
+    /// Fold the front of the window while enough of it is known, or all of it at the end.
+    fn fold(&mut self, out: &mut Vec<Segment>, end: bool) {
+        while self.open.is_none()
+            && !self.window.is_empty()
+            && (end || self.window.len() >= LOOKAHEAD)
+        {
+            match best_repeat(&self.window) {
+                // the next copy is not all there yet, so the repeat may go on
+                Some((w, count, shifts)) if !end && w * (count + 1) > self.window.len() => {
+                    let rest = self.window.split_off(w * count);
+                    let mut body = mem::take(&mut self.window);
+                    body.truncate(w);
+                    self.open = Some(Open::new(body, count, shifts));
+                    rest.into_iter().for_each(|seg| self.push(seg, out));
+                }
+                Some((w, count, shifts)) => {
+                    out.push(simplify(Segment::Repeat {
+                        body: self.window[..w].to_vec(),
+                        count,
+                        shifts,
+                    }));
+                    self.window.drain(..w * count);
+                    self.folded = true;
+                }
+                None => out.push(self.window.remove(0)),
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<Segment>) {
+        if let Some(open) = self.open.take() {
+            let (repeat, rest) = open.close();
+            out.push(repeat);
+            self.folded = true;
+            self.window.extend(rest);
+        }
+        self.fold(out, true);
+    }
+}
+
+/// Compresses a trace address by address.  Each level of folding passes the segments it
+/// has closed to the next, which is added once the level below has folded something.
+#[derive(Default)]
+pub struct Compressor {
+    levels: Vec<Level>,
+    segments: Vec<Segment>,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, addr: usize) {
+        let seg = Segment::Run {
+            start: addr as i64,
+            stride: 0,
+            len: 1,
+        };
+        self.push_at(0, seg);
+    }
+
+    fn push_at(&mut self, level: usize, seg: Segment) {
+        if level == self.levels.len() {
+            self.levels.push(Level::default());
+        }
+        let mut out = Vec::new();
+        self.levels[level].push(seg, &mut out);
+        self.pass_on(level, out);
+    }
+
+    fn pass_on(&mut self, level: usize, out: Vec<Segment>) {
+        for seg in out {
+            if level + 1 < self.levels.len() || self.levels[level].folded {
+                self.push_at(level + 1, seg);
+            } else {
+                self.segments.push(seg);
+            }
+        }
+    }
+
+    /// Fold what is left at every level and return the compressed trace.
+    pub fn finish(mut self) -> Compressed {
+        let mut level = 0;
+        while level < self.levels.len() {
+            let mut out = Vec::new();
+            self.levels[level].finish(&mut out);
+            self.pass_on(level, out);
+            level += 1;
+        }
+        Compressed {
+            segments: self.segments,
+        }
+    }
+}
+
+/// A compressed trace.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Compressed {
+    pub segments: Vec<Segment>,
+}
+
+impl Compressed {
+    /// Number of addresses in the trace.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(Segment::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of integers it takes to store the compressed trace.
+    pub fn words(&self) -> usize {
+        self.segments.iter().map(Segment::words).sum()
+    }
+
+    /// The exact trace that was compressed.
+    pub fn decompress(&self) -> Vec<usize> {
+        let mut out = Vec::with_capacity(self.len());
+        for seg in self.segments.iter() {
+            seg.expand(&vec![0; seg.runs()], &mut out);
+        }
+        out
+    }
+}
+
+/// Compress a trace by folding strided runs and repeated patterns, level by level,
+/// until nothing more folds.  The trace is read once, e.g. straight from a trace
+/// iterator, and only a window of it is held at each level.
+pub fn compress(trace: impl IntoIterator<Item = usize>) -> Compressed {
+    let mut compressor = Compressor::new();
+    trace.into_iter().for_each(|addr| compressor.push(addr));
+    compressor.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strided() {
+        let trace: Vec<usize> = (0..1000).map(|i| 100 + 8 * i).collect();
+        let c = compress(trace.iter().copied());
+        assert_eq!(
+            c.segments,
+            [Segment::Run {
+                start: 100,
+                stride: 8,
+                len: 1000
+            }]
+        );
+        assert_eq!(c.decompress(), trace);
+    }
+
+    #[test]
+    fn matmul() {
+        // i, j, k { C[i][j] A[i][k] B[k][j] }
+        let n = 24;
+        let (c, a, b) = (0, n * n, 2 * n * n);
+        let mut trace = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    trace.extend([c + i * n + j, a + i * n + k, b + k * n + j]);
+                }
+            }
+        }
+        let compressed = compress(trace.iter().copied());
+        assert_eq!(compressed.segments.len(), 1);
+        assert!(compressed.words() < 30);
+        assert_eq!(compressed.len(), trace.len());
+        assert_eq!(compressed.decompress(), trace);
+    }
+
+    #[test]
+    fn streams_in_bounded_space() {
+        // t, i { A[i] } with a long inner loop: no level holds more than its window
+        let mut compressor = Compressor::new();
+        let mut trace = Vec::new();
+        for t in 0..50 {
+            for i in 0..2000 {
+                compressor.push(1000 + i + t % 2);
+                trace.push(1000 + i + t % 2);
+                assert!(compressor
+                    .levels
+                    .iter()
+                    .all(|l| l.window.len() <= LOOKAHEAD));
+            }
+        }
+        let c = compressor.finish();
+        assert!(c.words() < 20, "{:?}", c.segments);
+        assert_eq!(c.decompress(), trace);
+    }
+
+    #[test]
+    fn irregular() {
+        // a pseudo-random trace with a few regular stretches still decompresses exactly
+        let mut x: u64 = 12345;
+        let mut trace = Vec::new();
+        for i in 0..2000 {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            if i % 100 < 40 {
+                trace.push(i % 7);
+            } else {
+                trace.push((x >> 33) as usize % 64);
+            }
+        }
+        assert_eq!(compress(trace.iter().copied()).decompress(), trace);
+    }
+
+    /// for a { for b { for c { refs } } X[a] }, with a reference `base + a * s[0] + b * s[1]
+    /// + c * s[2]` for every `(base, s)` in `refs`.
+    fn nest(dims: [usize; 3], refs: &[(usize, [usize; 3])], trace: &mut Vec<usize>) {
+        for a in 0..dims[0] {
+            for b in 0..dims[1] {
+                for c in 0..dims[2] {
+                    for (base, s) in refs.iter() {
+                        trace.push(base + a * s[0] + b * s[1] + c * s[2]);
+                    }
+                }
+            }
+            trace.push(5000 + a);
+        }
+    }
+
+    #[test]
+    fn random_nests() {
+        // a repeat of several runs arriving while a copy of one-run segments is partly
+        // filled must break the copy, not index past it
+        let mut trace = Vec::new();
+        let refs = [(909, [0, 4, 2]), (194, [174, 7, 1]), (95, [328, 8, 1])];
+        nest([21, 6, 9], &refs, &mut trace);
+        assert_eq!(compress(trace.iter().copied()).decompress(), trace);
+
+        // random nests with noise between them round-trip
+        let mut x: u64 = 987654321;
+        let mut rand = |n: u64| {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (x >> 33) as usize % n as usize
+        };
+        for _ in 0..300 {
+            let mut trace = Vec::new();
+            for _ in 0..3 {
+                let dims = [1 + rand(12), 1 + rand(12), 1 + rand(12)];
+                let refs: Vec<_> = (0..1 + rand(4))
+                    .map(|_| {
+                        (
+                            rand(1000),
+                            [rand(3) * rand(300), rand(3) * rand(30), rand(3)],
+                        )
+                    })
+                    .collect();
+                nest(dims, &refs, &mut trace);
+                (0..rand(5)).for_each(|_| trace.push(rand(100)));
+            }
+            assert_eq!(compress(trace.iter().copied()).decompress(), trace);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let c = compress([]);
+        assert!(c.is_empty());
+        assert_eq!(c.decompress(), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,204 @@
+//! Reuse distance after Bennett and Kruskal: the time of the last access of every key,
+//! and a Fenwick tree over logical time marking those last accesses.  The distance of a
+//! reuse is the number of marks after the previous access of its key.
+//!
+//! Time is counted in slots, one per access.  When they run out, the live keys are
+//! renumbered in the order of their last accesses, so the tree stays within a small
+//! multiple of the number of distinct keys however long the trace is.
+
+use crate::Reuse;
+use fxhash::FxHashMap;
+use std::hash::Hash;
+
+/// Slots of the smallest tree, so that short traces do not compact all the time.
+const MIN_SLOTS: usize = 1 << 10;
+
+/// Counts over positions `0..n`, with prefix sums in O(log n).
+pub(crate) struct Fenwick(Vec<i64>);
+
+impl Fenwick {
+    pub(crate) fn new(n: usize) -> Self {
+        Fenwick(vec![0; n + 1])
+    }
+
+    pub(crate) fn add(&mut self, pos: usize, v: i64) {
+        let mut i = pos + 1;
+        while i < self.0.len() {
+            self.0[i] += v;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Sum over `0..pos`.
+    pub(crate) fn prefix(&self, pos: usize) -> i64 {
+        let mut i = pos;
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+}
+
+/// An LRU stack simulator with O(log n) accesses, for keys that hash cheaply such as
+/// `u64` addresses.
+pub struct LRUFenwick<T> {
+    /// The slot and the logical time of the last access of every key.
+    last: FxHashMap<T, (usize, usize)>,
+    marks: Fenwick,
+    /// The slot of the next access.
+    next: usize,
+    slots: usize,
+    time: usize,
+}
+
+impl<T: Eq + Hash + Clone> Default for LRUFenwick<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Eq + Hash + Clone> LRUFenwick<T> {
+    pub fn new() -> Self {
+        LRUFenwick {
+            last: FxHashMap::default(),
+            marks: Fenwick::new(MIN_SLOTS),
+            next: 0,
+            slots: MIN_SLOTS,
+            time: 0,
+        }
+    }
+
+    /// Renumber the last accesses `0..n` in order and make room for as many accesses
+    /// again.
+    fn compact(&mut self) {
+        let mut slots: Vec<&mut usize> = self.last.values_mut().map(|last| &mut last.0).collect();
+        slots.sort_unstable_by_key(|slot| **slot);
+        let live = slots.len();
+        self.slots = (2 * live).max(MIN_SLOTS);
+        self.marks = Fenwick::new(self.slots);
+        for (i, slot) in slots.into_iter().enumerate() {
+            *slot = i;
+            self.marks.add(i, 1);
+        }
+        self.next = live;
+    }
+}
+
+impl<T: Eq + Hash + Clone> crate::LRU<T> for LRUFenwick<T> {
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        if self.next == self.slots {
+            self.compact();
+        }
+        let slot = self.next;
+        self.next += 1;
+        let time = self.time;
+        self.time += 1;
+        self.marks.add(slot, 1);
+        let Some((prev, prev_time)) = self.last.insert(val, (slot, time)) else {
+            return Reuse::default();
+        };
+        // the marks after the previous access: the keys accessed since and the new one,
+        // out of one per key and the old one of this key
+        let after = self.last.len() as i64 + 1 - self.marks.prefix(prev + 1);
+        self.marks.add(prev, -1);
+        Reuse {
+            distance: Some(after as usize),
+            interval: Some(time - prev_time),
+        }
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        let &(slot, _) = self.last.get(val)?;
+        Some(self.last.len() + 1 - self.marks.prefix(slot + 1) as usize)
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        let Some((slot, _)) = self.last.remove(val) else {
+            return false;
+        };
+        self.marks.add(slot, -1);
+        true
+    }
+
+    fn reset(&mut self) {
+        *self = LRUFenwick::new();
+    }
+
+    fn distinct(&self) -> usize {
+        self.last.len()
+    }
+}
+
+impl<T: Eq + Hash + Clone> crate::Snapshot<T> for LRUFenwick<T> {
+    fn snapshot(&self) -> Vec<T> {
+        let mut keys: Vec<(&T, usize)> = self.last.iter().map(|(k, &(s, _))| (k, s)).collect();
+        keys.sort_unstable_by_key(|&(_, slot)| std::cmp::Reverse(slot));
+        keys.into_iter().map(|(k, _)| k.clone()).collect()
+    }
+
+    fn restore(stack: Vec<T>) -> Self {
+        let mut lru = LRUFenwick::new();
+        stack.into_iter().rev().for_each(|key| {
+            crate::LRU::rec_access(&mut lru, key);
+        });
+        lru
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::olken::LRUSplay;
+    use crate::LRU;
+
+    #[test]
+    fn cyclic() {
+        let mut analyzer = LRUFenwick::new();
+        let dists: Vec<_> = "abcabc".chars().map(|c| analyzer.rec_access(c)).collect();
+        assert_eq!(dists, [None, None, None, Some(3), Some(3), Some(3)]);
+    }
+
+    #[test]
+    fn same_as_olken_across_compactions() {
+        let mut fenwick = LRUFenwick::new();
+        let mut splay = LRUSplay::new();
+        for i in 0..50_000u64 {
+            let key = (i * 7919 + i / 13 + (i * i) % 101) % 3000;
+            assert_eq!(
+                fenwick.rec_access(key),
+                splay.rec_access(key),
+                "access {}",
+                i
+            );
+        }
+        // 3000 keys live, so the tree has 6000 slots after compactions
+        assert_eq!(fenwick.slots, 6000);
+    }
+
+    #[test]
+    fn snapshot() {
+        crate::tests::check_snapshot::<LRUFenwick<char>>();
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut LRUFenwick::new());
+    }
+
+    #[test]
+    fn invalidate_same_as_olken() {
+        let mut fenwick = LRUFenwick::new();
+        let mut splay = LRUSplay::new();
+        for i in 0..20_000u64 {
+            let key = (i * 31 + i / 7) % 1500;
+            if i % 5 == 0 {
+                assert_eq!(fenwick.invalidate(&key), splay.invalidate(&key));
+                assert_eq!(fenwick.depth(&(key + 1)), splay.depth(&(key + 1)));
+            } else {
+                assert_eq!(fenwick.rec_access(key), splay.rec_access(key));
+            }
+        }
+    }
+}
@@ -1,10 +1,149 @@
 #![feature(linked_list_remove)]
 #![feature(let_chains)]
+pub mod bounded;
+pub mod compression;
+pub mod fenwick;
 pub mod olken;
+pub mod opt;
+pub mod parallel;
+pub mod priority;
 pub mod scale_tree;
+pub mod shards;
 pub mod stack;
 pub mod vec;
 
+/// What an access tells about the last access of the same data.  Both are `None` for a
+/// first access.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reuse {
+    /// The distinct data accessed since, counting this data, i.e. the stack depth.
+    pub distance: Option<usize>,
+    /// The accesses since, counting this one, so 1 for an immediate repeat.
+    pub interval: Option<usize>,
+}
+
 pub trait LRU<T> {
-    fn rec_access(&mut self, val: T) -> Option<usize>;
+    /// Access `val` and return its reuse distance and reuse interval.  A simulator that
+    /// only finds distances gives no interval.
+    fn rec_reuse(&mut self, val: T) -> Reuse;
+
+    /// Access `val` and return its reuse distance.
+    fn rec_access(&mut self, val: T) -> Option<usize> {
+        self.rec_reuse(val).distance
+    }
+
+    /// The depth of `val` in the stack, the distance an access would have now, without
+    /// accessing it.
+    fn depth(&self, val: &T) -> Option<usize>;
+
+    /// Remove `val` from the stack, e.g. for a flush or a free, as if it had never been
+    /// accessed, and tell whether it was there.
+    fn invalidate(&mut self, val: &T) -> bool;
+
+    /// Forget every access, as a new simulator.
+    fn reset(&mut self);
+
+    /// The number of distinct data in the stack: all those accessed, unless invalidated
+    /// or dropped by a bounded stack.
+    fn distinct(&self) -> usize;
+}
+
+/// An LRU simulator whose whole state is its stack, so that it can be saved, e.g. in a
+/// checkpoint, and restored to give the same distances from then on.  Reuse intervals
+/// are not kept: the restored stack is as if its data had just been accessed in order.
+pub trait Snapshot<T>: LRU<T> {
+    /// The stack, most recently used first.
+    fn snapshot(&self) -> Vec<T>;
+
+    /// A simulator whose stack is `stack`, most recently used first.
+    fn restore(stack: Vec<T>) -> Self;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the methods of `LRU` on an empty simulator.
+    pub(crate) fn check_trait<L: LRU<char>>(lru: &mut L) {
+        let reuses: Vec<_> = "abcacb".chars().map(|c| lru.rec_reuse(c)).collect();
+        let reuse = |d, i| Reuse {
+            distance: Some(d),
+            interval: Some(i),
+        };
+        assert_eq!(&reuses[..3], [Reuse::default(); 3]);
+        assert_eq!(&reuses[3..], [reuse(3, 3), reuse(2, 2), reuse(3, 4)]);
+
+        // the stack is b c a
+        let depths: Vec<_> = "abcz".chars().map(|c| lru.depth(&c)).collect();
+        assert_eq!(depths, [Some(3), Some(1), Some(2), None]);
+        assert_eq!(lru.distinct(), 3);
+
+        assert!(lru.invalidate(&'c') && !lru.invalidate(&'c'));
+        assert_eq!((lru.distinct(), lru.depth(&'a')), (2, Some(2)));
+        assert_eq!(lru.rec_reuse('c'), Reuse::default());
+        assert_eq!(lru.rec_reuse('a'), reuse(3, 4));
+
+        lru.reset();
+        assert_eq!((lru.distinct(), lru.depth(&'a')), (0, None));
+        assert_eq!(lru.rec_access('a'), None);
+        assert_eq!(lru.rec_reuse('a'), reuse(1, 1));
+    }
+
+    #[test]
+    fn provided_methods() {
+        // a simulator that only finds distances, by the position in a vector
+        #[derive(Default)]
+        struct Distances(Vec<char>);
+        impl LRU<char> for Distances {
+            fn rec_reuse(&mut self, val: char) -> Reuse {
+                let pos = self.0.iter().rposition(|&x| x == val);
+                pos.map(|p| self.0.remove(p));
+                self.0.push(val);
+                Reuse {
+                    distance: pos.map(|p| self.0.len() - p),
+                    interval: None,
+                }
+            }
+            fn depth(&self, val: &char) -> Option<usize> {
+                self.0.iter().rev().position(|x| x == val).map(|p| p + 1)
+            }
+            fn invalidate(&mut self, val: &char) -> bool {
+                let pos = self.0.iter().position(|x| x == val);
+                pos.map(|p| self.0.remove(p)).is_some()
+            }
+            fn reset(&mut self) {
+                self.0.clear();
+            }
+            fn distinct(&self) -> usize {
+                self.0.len()
+            }
+        }
+        let mut lru = Distances::default();
+        let reuses: Vec<_> = "abab".chars().map(|c| lru.rec_reuse(c)).collect();
+        let distance = |d| Reuse {
+            distance: Some(d),
+            interval: None,
+        };
+        assert_eq!(
+            reuses,
+            [Reuse::default(), Reuse::default(), distance(2), distance(2)]
+        );
+        assert_eq!(lru.rec_access('a'), Some(2));
+        lru.reset();
+        assert_eq!((lru.distinct(), lru.rec_access('a')), (0, None));
+    }
+
+    /// Check that a restored snapshot gives the same distances as the simulator it was
+    /// taken of.
+    pub(crate) fn check_snapshot<L: Snapshot<char>>() {
+        let mut lru = L::restore(vec![]);
+        for c in "abcadbe".chars() {
+            lru.rec_access(c);
+        }
+        assert_eq!(lru.snapshot(), ['e', 'b', 'd', 'a', 'c']);
+        let mut restored = L::restore(lru.snapshot());
+        for c in "cabfe".chars() {
+            assert_eq!(restored.rec_access(c), lru.rec_access(c));
+        }
+    }
 }
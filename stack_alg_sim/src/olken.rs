@@ -1,18 +1,26 @@
+use crate::Reuse;
 use fxhash::FxHashMap;
+use hist::Hist;
 use std::{hash::Hash, ptr::NonNull};
 
 struct SplayNode {
     parent: Option<NonNull<Self>>,
     children: [Option<NonNull<Self>>; 2],
+    /// The weight of the subtree: the number of keys, or their bytes in a `WeightedSplay`.
     count: usize,
+    weight: usize,
+    /// The logical time of the last access.
+    last: usize,
 }
 
 impl SplayNode {
-    fn empty() -> Self {
+    fn empty(time: usize, weight: usize) -> Self {
         Self {
             parent: None,
             children: [None, None],
-            count: 1,
+            count: weight,
+            weight,
+            last: time,
         }
     }
     #[inline(always)]
@@ -29,7 +37,8 @@ impl SplayNode {
     }
     #[inline(always)]
     unsafe fn maintain(mut node: NonNull<Self>) {
-        node.as_mut().count = 1 + Self::left_count(node) + Self::right_count(node);
+        node.as_mut().count =
+            node.as_ref().weight + Self::left_count(node) + Self::right_count(node);
     }
     #[inline(always)]
     unsafe fn get_child(node: NonNull<Self>, dir: usize) -> Option<NonNull<Self>> {
@@ -113,6 +122,18 @@ impl SplayNode {
             left
         }
     }
+    /// The weight of the nodes up to `node` in the in-order walk of its tree, `node`
+    /// included.
+    unsafe fn rank(mut node: NonNull<Self>) -> usize {
+        let mut rank = Self::left_count(node) + node.as_ref().weight;
+        while let Some(parent) = node.as_ref().parent {
+            if Self::is_right_child(node) {
+                rank += Self::left_count(parent) + parent.as_ref().weight;
+            }
+            node = parent;
+        }
+        rank
+    }
     unsafe fn insert_front(node: NonNull<Self>, root: Option<NonNull<Self>>) -> NonNull<Self> {
         Self::set_child(node, 1, root);
         Self::maintain(node);
@@ -126,6 +147,7 @@ impl SplayNode {
 pub struct LRUSplay<A> {
     root: Option<NonNull<SplayNode>>,
     handles: FxHashMap<A, NonNull<SplayNode>>,
+    time: usize,
 }
 
 impl<A> Default for LRUSplay<A>
@@ -145,42 +167,194 @@ where
         Self {
             root: None,
             handles: FxHashMap::default(),
+            time: 0,
         }
     }
     pub fn access(&mut self, key: A) -> Option<usize> {
+        self.reuse(key).distance
+    }
+    pub fn reuse(&mut self, key: A) -> Reuse {
+        self.reuse_weighted(key, 1)
+    }
+    /// Access `key` of weight `weight`.  The distance is the weight of the keys accessed
+    /// since its last access, its own new weight included.
+    fn reuse_weighted(&mut self, key: A, weight: usize) -> Reuse {
+        let time = self.time;
+        self.time += 1;
         unsafe {
             if let Some(node) = self.handles.get(&key) {
                 SplayNode::splay(*node);
                 let distance = SplayNode::left_count(*node);
                 let new_root = SplayNode::remove_root(*node);
                 let mut node = *node;
-                node.as_mut().count = 1;
+                node.as_mut().weight = weight;
+                node.as_mut().count = weight;
                 node.as_mut().children = [None, None];
+                let interval = time - std::mem::replace(&mut node.as_mut().last, time);
                 self.root = Some(SplayNode::insert_front(node, new_root));
-                Some(distance + 1)
+                Reuse {
+                    distance: Some(distance + weight),
+                    interval: Some(interval),
+                }
             } else {
-                let node = NonNull::new_unchecked(Box::leak(Box::new(SplayNode::empty())));
+                let node =
+                    NonNull::new_unchecked(Box::leak(Box::new(SplayNode::empty(time, weight))));
                 self.handles.insert(key.clone(), node);
                 self.root = Some(SplayNode::insert_front(node, self.root));
-                None
+                Reuse::default()
             }
         }
     }
 }
 
 impl<T: Eq + Hash + Clone> crate::LRU<T> for LRUSplay<T> {
-    fn rec_access(&mut self, val: T) -> Option<usize> {
-        self.access(val)
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        self.reuse(val)
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        let node = *self.handles.get(val)?;
+        // walk up rather than splay, so that the tree is not changed
+        Some(unsafe { SplayNode::rank(node) })
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        let Some(node) = self.handles.remove(val) else {
+            return false;
+        };
+        unsafe {
+            SplayNode::splay(node);
+            self.root = SplayNode::remove_root(node);
+            let _ = Box::from_raw(node.as_ptr());
+        }
+        true
+    }
+
+    fn reset(&mut self) {
+        self.free_nodes();
+        self.time = 0;
+    }
+
+    fn distinct(&self) -> usize {
+        self.handles.len()
     }
 }
 
-impl<A> Drop for LRUSplay<A> {
-    fn drop(&mut self) {
+impl<T: Eq + Hash + Clone> crate::Snapshot<T> for LRUSplay<T> {
+    fn snapshot(&self) -> Vec<T> {
+        let keys: FxHashMap<NonNull<SplayNode>, &T> =
+            self.handles.iter().map(|(k, &node)| (node, k)).collect();
+        // the in-order walk of the tree is the stack, as each access goes to the front
+        let mut stack = Vec::with_capacity(keys.len());
+        let mut path = vec![];
+        let mut next = self.root;
+        unsafe {
+            while next.is_some() || !path.is_empty() {
+                while let Some(node) = next {
+                    path.push(node);
+                    next = SplayNode::get_child(node, 0);
+                }
+                let node = path.pop().unwrap();
+                stack.push(keys[&node].clone());
+                next = SplayNode::get_child(node, 1);
+            }
+        }
+        stack
+    }
+
+    fn restore(stack: Vec<T>) -> Self {
+        let mut lru = LRUSplay::new();
+        stack.into_iter().rev().for_each(|key| {
+            lru.access(key);
+        });
+        lru
+    }
+}
+
+/// An LRU stack of objects of different sizes, whose distances are in bytes: the total
+/// size of the distinct objects accessed since the last access of an object, its own
+/// size included.  The size of an object may change from one access to the next.
+pub struct WeightedSplay<A> {
+    lru: LRUSplay<A>,
+}
+
+impl<A: Eq + Hash + Clone> Default for WeightedSplay<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Eq + Hash + Clone> WeightedSplay<A> {
+    pub fn new() -> Self {
+        WeightedSplay {
+            lru: LRUSplay::new(),
+        }
+    }
+
+    /// Access `key`, now of `size` bytes, and return its reuse distance in bytes.
+    pub fn access(&mut self, key: A, size: usize) -> Option<usize> {
+        self.reuse(key, size).distance
+    }
+
+    /// Like `access`, with the reuse interval in accesses.
+    pub fn reuse(&mut self, key: A, size: usize) -> Reuse {
+        assert!(size > 0, "an object needs a size");
+        self.lru.reuse_weighted(key, size)
+    }
+
+    /// The bytes of `key` and the objects above it, without accessing it.
+    pub fn depth(&self, key: &A) -> Option<usize> {
+        crate::LRU::depth(&self.lru, key)
+    }
+
+    pub fn invalidate(&mut self, key: &A) -> bool {
+        crate::LRU::invalidate(&mut self.lru, key)
+    }
+
+    /// The number of distinct objects.
+    pub fn distinct(&self) -> usize {
+        self.lru.handles.len()
+    }
+
+    /// The total size of the distinct objects.
+    pub fn bytes(&self) -> usize {
+        self.lru
+            .root
+            .map_or(0, |root| unsafe { root.as_ref().count })
+    }
+}
+
+/// The histogram of the byte reuse distances of `trace`, given as objects and their
+/// sizes.  Distances are rounded up to a multiple of `bucket` bytes, which keeps the miss
+/// ratio of every cache whose size is such a multiple exact.
+pub fn byte_hist<A: Eq + Hash + Clone>(
+    trace: impl IntoIterator<Item = (A, usize)>,
+    bucket: usize,
+) -> Hist {
+    assert!(bucket > 0, "byte buckets need a size");
+    let mut lru = WeightedSplay::new();
+    let mut hist = Hist::new();
+    for (key, size) in trace {
+        let d = lru.access(key, size);
+        hist.add_dist(d.map(|d| d.div_ceil(bucket) * bucket));
+    }
+    hist
+}
+
+impl<A> LRUSplay<A> {
+    fn free_nodes(&mut self) {
         for (_, node) in self.handles.drain() {
             unsafe {
                 let _ = Box::from_raw(node.as_ptr());
             }
         }
+        self.root = None;
+    }
+}
+
+impl<A> Drop for LRUSplay<A> {
+    fn drop(&mut self) {
+        self.free_nodes();
     }
 }
 
@@ -261,4 +435,97 @@ mod tests {
             .collect();
         assert_eq!(&dists, &access);
     }
+
+    #[test]
+    fn snapshot() {
+        crate::tests::check_snapshot::<LRUSplay<char>>();
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut LRUSplay::new());
+        // depths found by walking up agree with the distances found by splaying
+        let mut analyzer = LRUSplay::new();
+        for i in 0..5000u64 {
+            let key = (i * 37 + i / 3) % 700;
+            let depth = analyzer.depth(&key);
+            assert_eq!(depth, analyzer.rec_access(key));
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        let mut analyzer = WeightedSplay::new();
+        let sizes = [
+            ('a', 10),
+            ('b', 20),
+            ('c', 30),
+            ('a', 10),
+            ('b', 20),
+            ('b', 20),
+        ];
+        let dists: Vec<_> = sizes.iter().map(|&(k, s)| analyzer.access(k, s)).collect();
+        assert_eq!(dists, [None, None, None, Some(60), Some(60), Some(20)]);
+        // a grows to 15 bytes
+        assert_eq!(analyzer.access('a', 15), Some(35));
+        assert_eq!((analyzer.depth(&'c'), analyzer.bytes()), (Some(65), 65));
+        assert!(analyzer.invalidate(&'b'));
+        assert_eq!((analyzer.depth(&'c'), analyzer.distinct()), (Some(45), 2));
+    }
+
+    #[test]
+    fn weighted_unit_sizes() {
+        let mut weighted = WeightedSplay::new();
+        let mut analyzer = LRUSplay::new();
+        for i in 0..5000u64 {
+            let key = (i * 37 + i / 3) % 700;
+            assert_eq!(weighted.access(key, 1), analyzer.access(key));
+        }
+    }
+
+    #[test]
+    fn byte_buckets() {
+        // an LRU cache of `capacity` bytes holding the most recent objects that fit
+        let misses = |trace: &[(u64, usize)], capacity: usize| {
+            let mut cache: Vec<(u64, usize)> = vec![];
+            let mut misses = 0;
+            for &(key, size) in trace {
+                match cache.iter().position(|&(k, _)| k == key) {
+                    Some(pos) => {
+                        cache.remove(pos);
+                    }
+                    None => misses += 1,
+                }
+                cache.insert(0, (key, size));
+                let mut total = 0;
+                let fit = cache.iter().take_while(|&&(_, s)| {
+                    total += s;
+                    total <= capacity
+                });
+                let fit = fit.count();
+                if fit == 0 {
+                    // the object is bigger than the cache and hits nowhere
+                    cache.clear();
+                } else {
+                    cache.truncate(fit);
+                }
+            }
+            misses
+        };
+        let trace: Vec<(u64, usize)> = (0..4000u64)
+            .map(|i| {
+                let key = (i * 13 + i / 7 + i * i % 5) % 300;
+                (key, 64 * (1 + (key % 4) as usize))
+            })
+            .collect();
+        let hist = byte_hist(trace.iter().copied(), 1024).to_vec();
+        for capacity in [1024, 4096, 16384, 65536] {
+            let stack_misses: usize = hist
+                .iter()
+                .filter(|(d, _)| !d.is_some_and(|d| d <= capacity))
+                .map(|c| c.1)
+                .sum();
+            assert_eq!(stack_misses, misses(&trace, capacity), "{} bytes", capacity);
+        }
+    }
 }
@@ -0,0 +1,157 @@
+//! Stack distances of Belady's OPT (MIN) replacement.
+//!
+//! OPT evicts the line whose next use is farthest in the future, so it needs the whole
+//! trace: a backward pass finds the next use of every access, and a forward pass runs
+//! the `StackAlgorithm` with the next use as priority.  An access at depth `d` hits in
+//! every OPT cache of `d` lines or more, so one pass gives the misses of all sizes.
+//!
+//! The stack is a vector searched linearly, so an access costs its stack distance, or
+//! the footprint for a first access: O(trace length × footprint) in all.  The trace and
+//! its next uses are held in memory, 16 bytes per access for `usize` data.  In practice
+//! this limits OPT to traces of a few million accesses over some ten thousand lines;
+//! polybench gemm with n = 64, 1M accesses over 12K elements, takes under a second in a
+//! release build, and every doubling of n costs about 30 times as much.
+
+use crate::priority::{Priority, StackAlgorithm};
+use crate::LRU;
+use fxhash::FxHashMap;
+use hist::Hist;
+use std::cmp::Reverse;
+use std::hash::Hash;
+
+/// Logical time of an access that is never followed by another to the same data.
+pub const NEVER: usize = usize::MAX;
+
+/// For every access of `trace`, the time of the next access to the same data, or `NEVER`.
+pub fn next_uses<T: Eq + Hash>(trace: &[T]) -> Vec<usize> {
+    let mut next = vec![NEVER; trace.len()];
+    let mut seen: FxHashMap<&T, usize> = FxHashMap::default();
+    for (t, x) in trace.iter().enumerate().rev() {
+        if let Some(later) = seen.insert(x, t) {
+            next[t] = later;
+        }
+    }
+    next
+}
+
+/// Data used again the soonest have the highest priority: Belady's OPT.  The next uses
+/// come from a first pass over the trace, whose accesses must then be given in order.
+pub struct NextUse {
+    next_uses: Vec<usize>,
+}
+
+impl NextUse {
+    pub fn new(next_uses: Vec<usize>) -> Self {
+        NextUse { next_uses }
+    }
+}
+
+impl<T> Priority<T> for NextUse {
+    type Key = Reverse<usize>;
+
+    fn on_access(&mut self, _val: &T, time: usize) -> Reverse<usize> {
+        Reverse(
+            *self
+                .next_uses
+                .get(time)
+                .expect("more accesses than in the trace of the next uses"),
+        )
+    }
+}
+
+/// The OPT stack of a trace, which must be given the accesses of that trace in order.
+pub type OptStack<T> = StackAlgorithm<T, NextUse>;
+
+/// The OPT stack distance of every access of `trace`.
+pub fn opt_distances<T: Eq + Hash + Clone>(trace: &[T]) -> Vec<Option<usize>> {
+    let mut opt = OptStack::new(NextUse::new(next_uses(trace)));
+    trace.iter().map(|x| opt.rec_access(x.clone())).collect()
+}
+
+/// The histogram of the OPT stack distances of `trace`.
+pub fn opt_hist<T: Eq + Hash + Clone>(trace: &[T]) -> Hist {
+    let mut hist = Hist::new();
+    opt_distances(trace)
+        .into_iter()
+        .for_each(|d| hist.add_dist(d));
+    hist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::olken::LRUSplay;
+
+    /// Misses of Belady's MIN with `size` lines, simulated directly.  Like every stack
+    /// algorithm it always caches the accessed line.
+    fn min_misses(trace: &[u32], size: usize) -> usize {
+        let next = next_uses(trace);
+        let mut cache: FxHashMap<u32, usize> = FxHashMap::default();
+        let mut misses = 0;
+        for (t, &x) in trace.iter().enumerate() {
+            if cache.insert(x, next[t]).is_none() {
+                misses += 1;
+                if cache.len() > size {
+                    let (&victim, _) = cache
+                        .iter()
+                        .filter(|(&y, _)| y != x)
+                        .max_by_key(|(_, &n)| n)
+                        .unwrap();
+                    cache.remove(&victim);
+                }
+            }
+        }
+        misses
+    }
+
+    #[test]
+    fn cyclic() {
+        // LRU misses every access of a cycle longer than the cache, OPT with 2 lines
+        // hits every other reuse
+        let trace: Vec<char> = "abcabcabc".chars().collect();
+        assert_eq!(
+            opt_distances(&trace),
+            [
+                None,
+                None,
+                None,
+                Some(2),
+                Some(3),
+                Some(2),
+                Some(3),
+                Some(2),
+                Some(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn same_misses_as_min() {
+        let trace: Vec<u32> = (0..3000u32)
+            .map(|i| (i * 17 + i / 7 + i * i % 5) % 53)
+            .collect();
+        let dists = opt_distances(&trace);
+        for size in [1, 2, 5, 10, 30, 52, 53] {
+            let misses = dists
+                .iter()
+                .filter(|d| !d.is_some_and(|d| d <= size))
+                .count();
+            assert_eq!(misses, min_misses(&trace, size), "{} lines", size);
+        }
+    }
+
+    #[test]
+    fn lower_bound_of_lru() {
+        let trace: Vec<u32> = (0..2000u32).map(|i| (i * i / 3 + i % 11) % 97).collect();
+        let opt = opt_distances(&trace);
+        let mut lru = LRUSplay::new();
+        let lru: Vec<_> = trace.iter().map(|&x| lru.rec_access(x)).collect();
+        for size in 1..=97 {
+            let hits =
+                |d: &[Option<usize>]| d.iter().filter(|d| d.is_some_and(|d| d <= size)).count();
+            assert!(hits(&opt) >= hits(&lru));
+        }
+        // the first accesses are the same
+        assert_eq!(opt_hist(&trace).to_vec().last(), Some(&(None, 97)));
+    }
+}
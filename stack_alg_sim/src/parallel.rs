@@ -0,0 +1,147 @@
+//! Reuse distances of a whole trace computed on several threads.
+//!
+//! The trace is split into one chunk per thread.  Each chunk is run through its own
+//! Olken splay tree, which gives the exact distance of every reuse within the chunk.
+//! What is left are the first accesses of each chunk.  Say the k-th address first
+//! accessed in a chunk, counting from 1, was at depth `d` of the LRU stack at the start
+//! of the chunk.  Its distance is `d` plus the `k - 1` addresses accessed before it in
+//! the chunk, less those of them that were already above it in the stack.  The merge
+//! phase goes through the chunks in order and keeps that stack as the positions of the
+//! last accesses, in a Fenwick tree over logical time.
+
+use crate::fenwick::Fenwick;
+use crate::olken::LRUSplay;
+use crate::LRU;
+use fxhash::FxHashMap;
+use hist::Hist;
+use std::hash::Hash;
+
+/// What a chunk leaves to the merge phase.
+struct Chunk<T> {
+    /// Distances of the reuses within the chunk.
+    hist: Hist,
+    /// Addresses in the order of their first access in the chunk.
+    firsts: Vec<T>,
+    /// The position of the last access of every address of the chunk.
+    lasts: FxHashMap<T, usize>,
+}
+
+fn local<T: Eq + Hash + Clone>(chunk: &[T], start: usize) -> Chunk<T> {
+    let mut stack = LRUSplay::new();
+    let mut out = Chunk {
+        hist: Hist::new(),
+        firsts: vec![],
+        lasts: FxHashMap::default(),
+    };
+    for (i, x) in chunk.iter().enumerate() {
+        match stack.rec_access(x.clone()) {
+            Some(d) => out.hist.add_dist(Some(d)),
+            None => out.firsts.push(x.clone()),
+        }
+        out.lasts.insert(x.clone(), start + i);
+    }
+    out
+}
+
+/// The reuse distance histogram of `trace`, the same as an `olken::LRUSplay` gives,
+/// computed with `threads` threads.
+pub fn parallel_hist<T>(trace: &[T], threads: usize) -> Hist
+where
+    T: Eq + Hash + Clone + Send + Sync,
+{
+    let chunk_len = trace.len().div_ceil(threads.max(1)).max(1);
+    let chunks: Vec<Chunk<T>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = trace
+            .chunks(chunk_len)
+            .enumerate()
+            .map(|(c, chunk)| scope.spawn(move || local(chunk, c * chunk_len)))
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+
+    let mut hist = Hist::new();
+    let mut marks = Fenwick::new(trace.len());
+    let mut last: FxHashMap<T, usize> = FxHashMap::default();
+    for (c, chunk) in chunks.into_iter().enumerate() {
+        hist.merge(&chunk.hist);
+        let start = c * chunk_len;
+        // depth in the stack at the start of the chunk
+        let depths: Vec<Option<usize>> = chunk
+            .firsts
+            .iter()
+            .map(|x| {
+                last.get(x)
+                    .map(|&l| (marks.prefix(start) - marks.prefix(l)) as usize)
+            })
+            .collect();
+        // count, for each first access, the earlier ones that were above it
+        let mut ranks: Vec<usize> = depths.iter().flatten().copied().collect();
+        ranks.sort_unstable();
+        let mut above = Fenwick::new(ranks.len());
+        for (k, depth) in depths.iter().enumerate() {
+            hist.add_dist(depth.map(|d| {
+                let r = ranks.binary_search(&d).unwrap();
+                let moved_up = above.prefix(r) as usize;
+                above.add(r, 1);
+                k + d - moved_up
+            }));
+        }
+        for (x, pos) in chunk.lasts {
+            if let Some(old) = last.insert(x, pos) {
+                marks.add(old, -1);
+            }
+            marks.add(pos, 1);
+        }
+    }
+    hist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequential(trace: &[usize]) -> Vec<(Option<usize>, usize)> {
+        let mut stack = LRUSplay::new();
+        let mut hist = Hist::new();
+        trace
+            .iter()
+            .for_each(|&x| hist.add_dist(stack.rec_access(x)));
+        hist.to_vec()
+    }
+
+    #[test]
+    fn cross_chunk() {
+        // a b c | b a
+        let trace = [0, 1, 2, 1, 0];
+        assert_eq!(
+            parallel_hist(&trace, 2).to_vec(),
+            [(Some(2), 1), (Some(3), 1), (None, 3)]
+        );
+    }
+
+    #[test]
+    fn same_as_olken() {
+        let mut x: u64 = 7;
+        let mut trace = Vec::new();
+        for i in 0..5000 {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            // random addresses mixed with cyclic sweeps
+            trace.push(if i % 1000 < 300 {
+                i % 97
+            } else {
+                (x >> 33) as usize % 400
+            });
+        }
+        let expected = sequential(&trace);
+        for threads in [1, 2, 3, 8, 64] {
+            assert_eq!(parallel_hist(&trace, threads).to_vec(), expected);
+        }
+        assert_eq!(
+            parallel_hist(&trace[..3], 8).to_vec(),
+            sequential(&trace[..3])
+        );
+        assert!(parallel_hist::<usize>(&[], 4).to_vec().is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Mattson's stack algorithms for any policy given by a priority.
+//!
+//! Every entry of the stack has a priority, fixed when it was last accessed.  An access
+//! moves its entry to the top with a new priority; the old top is carried down, and at
+//! each position above the accessed entry the carried entry and the one in place are
+//! compared, the higher priority staying.  The carried entry ends where the accessed one
+//! was, or at the bottom for a first access.  A cache of `c` lines that evicts the
+//! lowest priority holds the top `c` entries, so the depth of an access is its stack
+//! distance for every cache size at once.
+
+use crate::opt::{next_uses, NextUse};
+use crate::{Reuse, LRU};
+use fxhash::FxHashMap;
+use hist::Hist;
+use std::cmp::Reverse;
+use std::hash::Hash;
+
+/// The priority of data in a stack algorithm.  Higher priorities stay higher in the stack.
+pub trait Priority<T> {
+    type Key: Ord;
+
+    /// The priority of `val` accessed at logical time `time`, which it keeps until its
+    /// next access.
+    fn on_access(&mut self, val: &T, time: usize) -> Self::Key;
+
+    /// Forget the accesses so far.
+    fn reset(&mut self) {}
+}
+
+/// A stack algorithm simulator, giving the stack distance of every access like the LRU
+/// simulators.
+pub struct StackAlgorithm<T, P: Priority<T>> {
+    /// Data, priority and the logical time of the last access, top first.
+    stack: Vec<(T, P::Key, usize)>,
+    priority: P,
+    time: usize,
+}
+
+impl<T: PartialEq, P: Priority<T>> StackAlgorithm<T, P> {
+    pub fn new(priority: P) -> Self {
+        StackAlgorithm {
+            stack: vec![],
+            priority,
+            time: 0,
+        }
+    }
+}
+
+impl<T: PartialEq, P: Priority<T>> LRU<T> for StackAlgorithm<T, P> {
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        let time = self.time;
+        let key = self.priority.on_access(&val, time);
+        self.time += 1;
+        let pos = self.stack.iter().position(|(x, ..)| *x == val);
+        if pos == Some(0) {
+            let last = std::mem::replace(&mut self.stack[0].2, time);
+            self.stack[0].1 = key;
+            return Reuse {
+                distance: Some(1),
+                interval: Some(time - last),
+            };
+        }
+        if self.stack.is_empty() {
+            self.stack.push((val, key, time));
+            return Reuse::default();
+        }
+        let end = pos.unwrap_or(self.stack.len());
+        let mut carry = std::mem::replace(&mut self.stack[0], (val, key, time));
+        for entry in self.stack[1..end].iter_mut() {
+            if entry.1 < carry.1 {
+                std::mem::swap(entry, &mut carry);
+            }
+        }
+        let Some(p) = pos else {
+            self.stack.push(carry);
+            return Reuse::default();
+        };
+        let last = std::mem::replace(&mut self.stack[p], carry).2;
+        Reuse {
+            distance: Some(p + 1),
+            interval: Some(time - last),
+        }
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        self.stack
+            .iter()
+            .position(|(x, ..)| x == val)
+            .map(|p| p + 1)
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        match self.stack.iter().position(|(x, ..)| x == val) {
+            Some(p) => {
+                self.stack.remove(p);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.priority.reset();
+        self.time = 0;
+    }
+
+    fn distinct(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+/// Least recently used data have the lowest priority.
+pub struct Lru;
+
+impl<T> Priority<T> for Lru {
+    type Key = usize;
+
+    fn on_access(&mut self, _val: &T, time: usize) -> usize {
+        time
+    }
+}
+
+/// Most recently used data have the lowest priority.
+pub struct Mru;
+
+impl<T> Priority<T> for Mru {
+    type Key = Reverse<usize>;
+
+    fn on_access(&mut self, _val: &T, time: usize) -> Reverse<usize> {
+        Reverse(time)
+    }
+}
+
+/// Least frequently used data have the lowest priority, the least recently used first
+/// among those used as often.
+pub struct Lfu<T> {
+    counts: FxHashMap<T, usize>,
+}
+
+impl<T> Lfu<T> {
+    pub fn new() -> Self {
+        Lfu {
+            counts: FxHashMap::default(),
+        }
+    }
+}
+
+impl<T> Default for Lfu<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Eq + Hash + Clone> Priority<T> for Lfu<T> {
+    type Key = (usize, usize);
+
+    fn on_access(&mut self, val: &T, time: usize) -> (usize, usize) {
+        let count = self.counts.entry(val.clone()).or_insert(0);
+        *count += 1;
+        (*count, time)
+    }
+
+    fn reset(&mut self) {
+        self.counts.clear();
+    }
+}
+
+/// The stack distance histograms of LRU, MRU, LFU and OPT for `trace`, computed together
+/// in one pass after the pass of OPT over the trace to find next uses.
+pub fn policy_hists<T: Eq + Hash + Clone>(trace: &[T]) -> Vec<(&'static str, Hist)> {
+    let mut lru = StackAlgorithm::new(Lru);
+    let mut mru = StackAlgorithm::new(Mru);
+    let mut lfu = StackAlgorithm::new(Lfu::new());
+    let mut opt = StackAlgorithm::new(NextUse::new(next_uses(trace)));
+    let mut hists: Vec<_> = ["LRU", "MRU", "LFU", "OPT"]
+        .into_iter()
+        .map(|name| (name, Hist::new()))
+        .collect();
+    for x in trace {
+        let dists = [
+            lru.rec_access(x.clone()),
+            mru.rec_access(x.clone()),
+            lfu.rec_access(x.clone()),
+            opt.rec_access(x.clone()),
+        ];
+        for (hist, d) in hists.iter_mut().zip(dists) {
+            hist.1.add_dist(d);
+        }
+    }
+    hists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::LRUStack;
+
+    /// Misses of a cache of `size` lines evicting the lowest priority, simulated directly.
+    fn misses<P: Priority<u32>>(mut priority: P, trace: &[u32], size: usize) -> usize {
+        let mut cache: FxHashMap<u32, P::Key> = FxHashMap::default();
+        let mut misses = 0;
+        for (t, &x) in trace.iter().enumerate() {
+            if cache.insert(x, priority.on_access(&x, t)).is_none() {
+                misses += 1;
+                if cache.len() > size {
+                    let victim = *cache
+                        .iter()
+                        .filter(|(&y, _)| y != x)
+                        .min_by(|a, b| a.1.cmp(b.1))
+                        .unwrap()
+                        .0;
+                    cache.remove(&victim);
+                }
+            }
+        }
+        misses
+    }
+
+    fn distances<P: Priority<u32>>(priority: P, trace: &[u32]) -> Vec<Option<usize>> {
+        let mut stack = StackAlgorithm::new(priority);
+        trace.iter().map(|&x| stack.rec_access(x)).collect()
+    }
+
+    fn check_inclusion<P: Priority<u32>>(make: impl Fn() -> P, trace: &[u32]) {
+        let dists = distances(make(), trace);
+        for size in [1, 2, 3, 8, 20, 40] {
+            let stack_misses = dists
+                .iter()
+                .filter(|d| !d.is_some_and(|d| d <= size))
+                .count();
+            assert_eq!(stack_misses, misses(make(), trace, size), "{} lines", size);
+        }
+    }
+
+    fn test_trace() -> Vec<u32> {
+        (0..2000u32)
+            .map(|i| (i * 13 + i / 9 + i * i % 7) % 41)
+            .collect()
+    }
+
+    #[test]
+    fn lru_is_lru() {
+        let trace = test_trace();
+        let mut lru = LRUStack::new();
+        let expected: Vec<_> = trace.iter().map(|&x| lru.rec_access(x)).collect();
+        assert_eq!(distances(Lru, &trace), expected);
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut StackAlgorithm::new(Lru));
+    }
+
+    #[test]
+    fn mru() {
+        // with two lines, MRU keeps the older line and replaces the one just used
+        let dists = distances(Mru, &[0, 1, 2, 0, 1, 2]);
+        assert_eq!(dists, [None, None, None, Some(2), Some(3), Some(2)]);
+        check_inclusion(|| Mru, &test_trace());
+    }
+
+    #[test]
+    fn lfu() {
+        // 0 is used twice and stays on top of 1 and 2
+        let dists = distances(Lfu::new(), &[0, 0, 1, 2, 0, 1]);
+        assert_eq!(dists, [None, Some(1), None, None, Some(2), Some(3)]);
+        check_inclusion(Lfu::new, &test_trace());
+    }
+
+    #[test]
+    fn opt_and_all_policies() {
+        let trace = test_trace();
+        check_inclusion(|| NextUse::new(next_uses(&trace)), &trace);
+        let hists = policy_hists(&trace);
+        let names: Vec<_> = hists.iter().map(|h| h.0).collect();
+        assert_eq!(names, ["LRU", "MRU", "LFU", "OPT"]);
+        assert_eq!(hists[3].1.to_vec(), crate::opt::opt_hist(&trace).to_vec());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::Reuse;
 use fxhash::FxHashMap;
 use std::cell::UnsafeCell;
 use std::collections::hash_map::Entry::*;
@@ -230,6 +231,29 @@ impl SplayNode {
         }
         node
     }
+    /// The position of the end of the group of `node`, that an access would give, found
+    /// without changing the trees.  A subtree merged into a node is left with a marker
+    /// at its root.
+    unsafe fn order(mut node: NonNull<Self>) -> usize {
+        loop {
+            let mut root = node;
+            while let Some(parent) = root.as_ref().parent {
+                root = parent;
+            }
+            match root.as_ref().merged.as_ref() {
+                Some(merged) => node = NonNull::new_unchecked(merged.get()),
+                None => break,
+            }
+        }
+        let mut order = Self::left_count(node) + node.as_ref().size;
+        while let Some(parent) = node.as_ref().parent {
+            if Self::is_right_child(node) {
+                order += Self::left_count(parent) + parent.as_ref().size;
+            }
+            node = parent;
+        }
+        order
+    }
     unsafe fn remove_root_node(node: NonNull<Self>) -> Option<NonNull<Self>> {
         let left = Self::get_child(node, 0);
         let right = Self::get_child(node, 1);
@@ -255,16 +279,49 @@ impl SplayNode {
 }
 
 impl<T: Eq + Hash + Clone> crate::LRU<T> for LRUSplay<T> {
-    fn rec_access(&mut self, val: T) -> Option<usize> {
-        self.access(val)
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        self.reuse(val)
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        let (handle, _) = self.handles.get(val)?;
+        Some(unsafe { SplayNode::order(NonNull::new_unchecked(handle.get())) })
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        let Some((handle, _)) = self.handles.remove(val) else {
+            return false;
+        };
+        unsafe {
+            let node = NonNull::new_unchecked(handle.get());
+            let node = SplayNode::expose(node, self.error_bound, self.compression_threshold);
+            // a group of several keys only shrinks
+            self.root = match SplayNode::remove_root(node) {
+                None => Some(node.as_ref().this.upgrade().unwrap_unchecked()),
+                Some(root) => root.map(|root| root.as_ref().this.upgrade().unwrap_unchecked()),
+            };
+        }
+        true
+    }
+
+    fn reset(&mut self) {
+        self.root = None;
+        self.handles.clear();
+        self.time = 0;
+    }
+
+    fn distinct(&self) -> usize {
+        self.handles.len()
     }
 }
 
 pub struct LRUSplay<A> {
     root: Option<Rc<UnsafeCell<SplayNode>>>,
-    handles: FxHashMap<A, Rc<UnsafeCell<SplayNode>>>,
+    /// The node of every key and the logical time of its last access.
+    handles: FxHashMap<A, (Rc<UnsafeCell<SplayNode>>, usize)>,
     error_bound: f64,
     compression_threshold: usize,
+    time: usize,
 }
 
 impl<A> Default for LRUSplay<A>
@@ -286,19 +343,28 @@ where
             handles: FxHashMap::default(),
             error_bound,
             compression_threshold,
+            time: 0,
         }
     }
     pub fn access(&mut self, key: A) -> Option<usize> {
+        self.reuse(key).distance
+    }
+    pub fn reuse(&mut self, key: A) -> Reuse {
+        let time = self.time;
+        self.time += 1;
         unsafe {
             match self.handles.entry(key) {
                 Occupied(mut entry) => {
-                    let node = entry.get().clone();
+                    let (node, last) = entry.get().clone();
                     let node = NonNull::new_unchecked(node.get());
                     let (count, handle) =
                         SplayNode::access(node, self.error_bound, self.compression_threshold);
                     self.root = Some(handle.clone());
-                    entry.insert(handle);
-                    Some(count)
+                    entry.insert((handle, time));
+                    Reuse {
+                        distance: Some(count),
+                        interval: Some(time - last),
+                    }
                 }
                 Vacant(entry) => {
                     let handle = SplayNode::empty();
@@ -311,8 +377,8 @@ where
                     SplayNode::set_child(node, 1, root);
                     SplayNode::maintain(node);
                     self.root = Some(handle.clone());
-                    entry.insert(handle);
-                    None
+                    entry.insert((handle, time));
+                    Reuse::default()
                 }
             }
         }
@@ -395,4 +461,20 @@ mod tests {
             .collect();
         assert_eq!(&dists, &access);
     }
+
+    #[test]
+    fn trait_methods() {
+        use crate::LRU;
+        crate::tests::check_trait(&mut LRUSplay::default());
+        // with compression, depths agree with the approximate distances of accesses
+        let mut analyzer = LRUSplay::new(0.3, 32);
+        for i in 0..20_000u64 {
+            let key = (i * 37 + i / 3 + i * i % 11) % 3000;
+            let depth = analyzer.depth(&key);
+            assert_eq!(depth, analyzer.access(key), "access {}", i);
+            if i % 7 == 0 {
+                analyzer.invalidate(&((key + 5) % 3000));
+            }
+        }
+    }
 }
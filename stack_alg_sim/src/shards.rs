@@ -0,0 +1,236 @@
+//! SHARDS, spatially hashed sampling of reuse distances (Waldspurger et al., FAST '15).
+//!
+//! A key is sampled when its hash modulo `MODULUS` is below a threshold, so a sampled key
+//! has all of its accesses sampled, at the rate threshold / `MODULUS`.  Only the sampled
+//! accesses go to the LRU simulator, whose distances count sampled keys: divided by the
+//! rate they estimate the distances in the whole trace, and every sampled access stands
+//! for 1 / rate accesses.
+//!
+//! At a fixed rate the simulator grows with the footprint.  The fixed-size mode keeps at
+//! most a given number of sampled keys: when there is one more, the keys of the largest
+//! hash leave the simulator and the threshold drops to that hash.  An access is scaled by
+//! the rate at the time it was sampled.
+
+use crate::LRU;
+use fxhash::FxHashMap;
+use hist::Hist;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+/// Hashes are taken modulo `MODULUS`, which bounds how finely the rate can be set.
+pub const MODULUS: u64 = 1 << 24;
+
+fn spatial_hash<T: Hash>(val: &T) -> u64 {
+    // fxhash only multiplies, so mix the high bits into the low ones (murmur3's finalizer)
+    let mut h = fxhash::hash64(val);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h % MODULUS
+}
+
+/// The sampled keys of the fixed-size mode.
+struct Bound<T> {
+    max_keys: usize,
+    /// Sampled keys by hash.
+    keys: BTreeMap<u64, Vec<T>>,
+    live: usize,
+}
+
+/// Reuse distances estimated from the sampled accesses to `L`.
+pub struct Shards<T, L> {
+    lru: L,
+    threshold: u64,
+    bound: Option<Bound<T>>,
+    /// The estimated number of accesses at every distance.
+    counts: FxHashMap<Option<usize>, f64>,
+    accesses: usize,
+    sampled: usize,
+}
+
+impl<T: Eq + Hash + Clone, L: LRU<T>> Shards<T, L> {
+    /// Sample the keys at `rate`, in (0, 1].
+    pub fn fixed_rate(lru: L, rate: f64) -> Self {
+        assert!(
+            rate > 0.0 && rate <= 1.0,
+            "sampling rate {} not in (0, 1]",
+            rate
+        );
+        Shards {
+            lru,
+            threshold: ((rate * MODULUS as f64).round() as u64).max(1),
+            bound: None,
+            counts: FxHashMap::default(),
+            accesses: 0,
+            sampled: 0,
+        }
+    }
+
+    /// Sample at most `max_keys` keys, starting at rate 1 and lowering it as more come.
+    pub fn fixed_size(lru: L, max_keys: usize) -> Self {
+        assert!(max_keys > 0, "fixed-size sampling needs a key");
+        Shards {
+            bound: Some(Bound {
+                max_keys,
+                keys: BTreeMap::new(),
+                live: 0,
+            }),
+            ..Shards::fixed_rate(lru, 1.0)
+        }
+    }
+
+    /// The current sampling rate, the fraction of the keys sampled.
+    pub fn rate(&self) -> f64 {
+        self.threshold as f64 / MODULUS as f64
+    }
+
+    /// All accesses so far, sampled or not.
+    pub fn accesses(&self) -> usize {
+        self.accesses
+    }
+
+    /// The accesses given to the LRU simulator.
+    pub fn sampled(&self) -> usize {
+        self.sampled
+    }
+
+    /// Access `val`.  Returns `None` if it is not sampled, and its estimated reuse
+    /// distance otherwise.
+    pub fn access(&mut self, val: T) -> Option<Option<usize>> {
+        self.accesses += 1;
+        let hash = spatial_hash(&val);
+        if hash >= self.threshold {
+            return None;
+        }
+        self.sampled += 1;
+        let rate = self.rate();
+        let key = self.bound.is_some().then(|| val.clone());
+        let dist = self
+            .lru
+            .rec_access(val)
+            .map(|d| (d as f64 / rate).round() as usize);
+        *self.counts.entry(dist).or_insert(0.0) += 1.0 / rate;
+        if let (None, Some(key)) = (dist, key) {
+            self.add_key(hash, key);
+        }
+        Some(dist)
+    }
+
+    /// Record a new sampled key of the fixed-size mode, and evict the keys of the largest
+    /// hashes while there are too many.
+    fn add_key(&mut self, hash: u64, key: T) {
+        let bound = self.bound.as_mut().unwrap();
+        bound.keys.entry(hash).or_default().push(key);
+        bound.live += 1;
+        while bound.live > bound.max_keys {
+            let (hash, keys) = bound.keys.pop_last().unwrap();
+            for key in keys.iter() {
+                self.lru.invalidate(key);
+            }
+            bound.live -= keys.len();
+            self.threshold = hash;
+        }
+    }
+
+    /// The estimated reuse distance histogram of all accesses so far.
+    pub fn hist(&self) -> Hist {
+        let mut hist = Hist::new();
+        for (&d, &count) in self.counts.iter() {
+            let count = count.round() as usize;
+            if count > 0 {
+                hist.add_dists(d, count);
+            }
+        }
+        hist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fenwick::LRUFenwick;
+    use crate::olken::LRUSplay;
+
+    /// A loop over 4000 keys mixed with accesses spread over 20000.
+    fn test_trace() -> Vec<u64> {
+        let mut x = 12345u64;
+        (0..200_000u64)
+            .map(|i| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                if i % 3 == 0 {
+                    (x >> 33) % 20000
+                } else {
+                    i % 4000
+                }
+            })
+            .collect()
+    }
+
+    /// The miss ratio of a cache of every size in `sizes`.
+    fn mrc(hist: &Hist, sizes: &[usize]) -> Vec<f64> {
+        let total: usize = hist.to_vec().iter().map(|c| c.1).sum();
+        sizes
+            .iter()
+            .map(|&size| hist.misses(size) as f64 / total as f64)
+            .collect()
+    }
+
+    fn exact_hist(trace: &[u64]) -> Hist {
+        let mut lru = LRUSplay::new();
+        let mut hist = Hist::new();
+        trace.iter().for_each(|&x| hist.add_dist(lru.rec_access(x)));
+        hist
+    }
+
+    fn assert_close(approx: &Hist, exact: &Hist, error: f64) {
+        // sampling blurs the steep drop of the miss ratio just below the loop
+        let sizes = [100, 1000, 2000, 5000, 10000, 20000];
+        for (a, e) in mrc(approx, &sizes).into_iter().zip(mrc(exact, &sizes)) {
+            assert!((a - e).abs() < error, "miss ratio {} for {}", a, e);
+        }
+    }
+
+    #[test]
+    fn rate_one_is_exact() {
+        let trace = test_trace();
+        let mut shards = Shards::fixed_rate(LRUSplay::new(), 1.0);
+        trace
+            .iter()
+            .for_each(|&x| assert!(shards.access(x).is_some()));
+        assert_eq!(shards.hist().to_vec(), exact_hist(&trace).to_vec());
+    }
+
+    #[test]
+    fn fixed_rate() {
+        let trace = test_trace();
+        let mut shards = Shards::fixed_rate(LRUFenwick::new(), 0.05);
+        trace.iter().for_each(|&x| {
+            shards.access(x);
+        });
+        assert_eq!(shards.accesses(), trace.len());
+        let fraction = shards.sampled() as f64 / trace.len() as f64;
+        assert!((fraction - 0.05).abs() < 0.02, "sampled {}", fraction);
+        assert_close(&shards.hist(), &exact_hist(&trace), 0.05);
+    }
+
+    #[test]
+    fn fixed_size() {
+        let trace = test_trace();
+        let mut shards = Shards::fixed_size(LRUSplay::new(), 1000);
+        trace.iter().for_each(|&x| {
+            shards.access(x);
+        });
+        // about 1000 of the 20000 keys are left
+        assert!(
+            shards.rate() > 0.03 && shards.rate() < 0.07,
+            "rate {}",
+            shards.rate()
+        );
+        assert!(shards.bound.as_ref().unwrap().live <= 1000);
+        assert_close(&shards.hist(), &exact_hist(&trace), 0.05);
+    }
+}
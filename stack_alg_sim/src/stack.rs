@@ -1,7 +1,11 @@
+use crate::Reuse;
 use std::collections::LinkedList;
 #[derive(Debug)]
 pub struct LRUStack<T> {
     pub stack: LinkedList<T>,
+    /// The logical time of the last access of every entry of `stack`.
+    times: LinkedList<usize>,
+    time: usize,
 }
 
 impl<T: PartialEq> Default for LRUStack<T> {
@@ -11,8 +15,46 @@ impl<T: PartialEq> Default for LRUStack<T> {
 }
 
 impl<T: PartialEq + Clone> crate::LRU<T> for LRUStack<T> {
-    fn rec_access(&mut self, val: T) -> Option<usize> {
-        self.rec_access_impl(val)
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        self.rec_reuse_impl(val)
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        self.stack.iter().position(|x| x == val).map(|x| x + 1)
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        match self.stack.iter().position(|x| x == val) {
+            Some(pos) => {
+                self.stack.remove(pos);
+                self.times.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = LRUStack::new();
+    }
+
+    fn distinct(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+impl<T: PartialEq + Clone> crate::Snapshot<T> for LRUStack<T> {
+    fn snapshot(&self) -> Vec<T> {
+        self.stack.iter().cloned().collect()
+    }
+
+    fn restore(stack: Vec<T>) -> Self {
+        let n = stack.len();
+        LRUStack {
+            stack: stack.into_iter().collect(),
+            times: (0..n).rev().collect(),
+            time: n,
+        }
     }
 }
 
@@ -20,19 +62,33 @@ impl<T: PartialEq> LRUStack<T> {
     pub fn new() -> LRUStack<T> {
         LRUStack {
             stack: LinkedList::new(),
+            times: LinkedList::new(),
+            time: 0,
         }
     }
 
     pub fn rec_access_impl(&mut self, val: T) -> Option<usize> {
+        self.rec_reuse_impl(val).distance
+    }
+
+    pub fn rec_reuse_impl(&mut self, val: T) -> Reuse {
         let pos = self.stack.iter().position(|x| *x == val);
+        let time = self.time;
+        self.time += 1;
 
+        let mut reuse = Reuse::default();
         if let Some(rd) = pos {
             self.stack.remove(rd);
+            reuse = Reuse {
+                distance: Some(rd + 1),
+                interval: Some(time - self.times.remove(rd)),
+            };
         }
 
         self.stack.push_front(val);
+        self.times.push_front(time);
 
-        pos.map(|x| x + 1)
+        reuse
     }
 }
 
@@ -75,4 +131,14 @@ mod tests {
 
         assert_eq!(dists, [None, None, None, Some(1), Some(2), Some(3)]);
     }
+
+    #[test]
+    fn snapshot() {
+        crate::tests::check_snapshot::<LRUStack<char>>();
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut LRUStack::new());
+    }
 }
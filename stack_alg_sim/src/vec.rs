@@ -1,6 +1,11 @@
+use crate::Reuse;
+
 #[derive(Debug)]
 pub struct LRUVec<T> {
     pub stack: Vec<Option<Box<T>>>,
+    /// The logical time of the last access of every entry of `stack`.
+    times: Vec<usize>,
+    time: usize,
 }
 
 impl<T: PartialEq> Default for LRUVec<T> {
@@ -10,8 +15,49 @@ impl<T: PartialEq> Default for LRUVec<T> {
 }
 
 impl<T: PartialEq + Clone> crate::LRU<T> for LRUVec<T> {
-    fn rec_access(&mut self, val: T) -> Option<usize> {
-        self.rec_access_impl(val)
+    fn rec_reuse(&mut self, val: T) -> Reuse {
+        self.rec_reuse_impl(val)
+    }
+
+    fn depth(&self, val: &T) -> Option<usize> {
+        self.position(val).map(|x| x + 1)
+    }
+
+    fn invalidate(&mut self, val: &T) -> bool {
+        match self.position(val) {
+            Some(pos) => {
+                self.stack.remove(pos);
+                self.times.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = LRUVec::new();
+    }
+
+    fn distinct(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+impl<T: PartialEq + Clone> crate::Snapshot<T> for LRUVec<T> {
+    fn snapshot(&self) -> Vec<T> {
+        self.stack
+            .iter()
+            .map(|x| (**x.as_ref().unwrap()).clone())
+            .collect()
+    }
+
+    fn restore(stack: Vec<T>) -> Self {
+        let n = stack.len();
+        LRUVec {
+            stack: stack.into_iter().map(|x| Some(Box::new(x))).collect(),
+            times: (0..n).rev().collect(),
+            time: n,
+        }
     }
 }
 
@@ -19,10 +65,42 @@ impl<T: PartialEq> LRUVec<T> {
     pub fn new() -> LRUVec<T> {
         LRUVec {
             stack: Vec::<Option<Box<T>>>::new(),
+            times: vec![],
+            time: 0,
         }
     }
 
+    fn position(&self, val: &T) -> Option<usize> {
+        self.stack
+            .iter()
+            .position(|x| **x.as_ref().unwrap() == *val)
+    }
+
     pub fn rec_access_impl(&mut self, val: T) -> Option<usize> {
+        self.rec_reuse_impl(val).distance
+    }
+
+    pub fn rec_reuse_impl(&mut self, val: T) -> Reuse {
+        let time = self.time;
+        self.time += 1;
+        let distance = self.move_to_front(val);
+        let interval = match distance {
+            Some(d) => {
+                let last = self.times[d - 1];
+                self.times.copy_within(0..d - 1, 1);
+                self.times[0] = time;
+                Some(time - last)
+            }
+            None => {
+                self.times.insert(0, time);
+                None
+            }
+        };
+        Reuse { distance, interval }
+    }
+
+    /// Move `val` to the top of the stack and return its depth before.
+    fn move_to_front(&mut self, val: T) -> Option<usize> {
         if self.stack.is_empty() {
             self.stack.push(Some(Box::new(val)));
             return None;
@@ -88,4 +166,14 @@ mod tests {
 
         assert_eq!(dists, [None, None, None, Some(1), Some(2), Some(3)]);
     }
+
+    #[test]
+    fn snapshot() {
+        crate::tests::check_snapshot::<LRUVec<char>>();
+    }
+
+    #[test]
+    fn trait_methods() {
+        crate::tests::check_trait(&mut LRUVec::new());
+    }
 }
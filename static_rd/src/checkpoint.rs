@@ -0,0 +1,546 @@
+//! Checkpoints of a reuse distance run, so that a run that takes hours can be continued
+//! after a crash with the same results.
+//!
+//! A checkpoint file holds, after the magic `b"DACECKPT"` and a version, a header that
+//! identifies the run (the program hash of the tree, the LRU simulator and free-form
+//! parameters), then varints for the position of the `TraceIter` (logical time, iteration
+//! vector, active calls and cursors), the LRU stack from most to least recently used, the
+//! partial histogram, and the records and bytes of the two side files.
+//!
+//! The distance and address lists of the accesses grow with the trace, so they are not in
+//! the checkpoint: they are appended to the side files `<path>.dists` and
+//! `<path>.accesses` at every checkpoint, and on resume the side files are cut back to the
+//! lengths the checkpoint recorded.  Optional values are written as 0 for `None` and
+//! `x + 1` for `Some(x)`, signed values as zigzag varints.
+
+use crate::sink::{AccessList, ReuseDistance, TraceSink};
+use dace::ast::Node;
+use dace::iter::{SavedCursor, TraceIter, TraceState};
+use dace::record::Layout;
+use hist::Hist;
+use list_serializable::ListSerializable;
+use stack_alg_sim::Snapshot;
+use trace_file::{put_varint, read_varint, unzigzag, zigzag};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const MAGIC: &[u8; 8] = b"DACECKPT";
+pub const VERSION: u64 = 2;
+
+/// What a checkpoint was taken of.  A run only resumes from a checkpoint of the same
+/// program, simulator and parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunInfo {
+    /// `Node::program_hash` of the traced tree.
+    pub program_hash: u64,
+    /// The type of the LRU simulator.
+    pub simulator: String,
+    /// Free-form parameters of the run, e.g. `("N", "1024")`.
+    pub params: Vec<(String, String)>,
+}
+
+/// The length of a side file at a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SideLen {
+    pub records: usize,
+    pub bytes: u64,
+}
+
+/// Everything a run needs to continue from an access, besides the side files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub info: RunInfo,
+    pub state: TraceState,
+    /// The LRU stack, most recently used first.
+    pub stack: Vec<usize>,
+    pub hist: Vec<(Option<usize>, usize)>,
+    pub dists: SideLen,
+    pub accesses: SideLen,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Writes varints to a stream and counts the bytes written.
+struct Encoder<W: Write> {
+    out: W,
+    buf: Vec<u8>,
+    bytes: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(out: W) -> Self {
+        Encoder {
+            out,
+            buf: Vec::with_capacity(10),
+            bytes: 0,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.bytes += bytes.len() as u64;
+        self.out.write_all(bytes)
+    }
+
+    fn varint(&mut self, x: u64) -> io::Result<()> {
+        self.buf.clear();
+        put_varint(&mut self.buf, x);
+        self.bytes += self.buf.len() as u64;
+        self.out.write_all(&self.buf)
+    }
+
+    fn usize(&mut self, x: usize) -> io::Result<()> {
+        self.varint(x as u64)
+    }
+
+    fn signed(&mut self, x: i64) -> io::Result<()> {
+        self.varint(zigzag(x))
+    }
+
+    fn option(&mut self, x: Option<usize>) -> io::Result<()> {
+        self.varint(x.map_or(0, |x| x as u64 + 1))
+    }
+
+    fn string(&mut self, s: &str) -> io::Result<()> {
+        self.usize(s.len())?;
+        self.bytes(s.as_bytes())
+    }
+}
+
+fn read_usize<R: Read>(r: &mut R) -> io::Result<usize> {
+    Ok(read_varint(r)? as usize)
+}
+
+fn read_signed<R: Read>(r: &mut R) -> io::Result<i64> {
+    Ok(unzigzag(read_varint(r)?))
+}
+
+fn read_option<R: Read>(r: &mut R) -> io::Result<Option<usize>> {
+    Ok(match read_varint(r)? {
+        0 => None,
+        x => Some(x as usize - 1),
+    })
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    let len = read_varint(r)?;
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("a string is not UTF-8"))
+}
+
+fn read_vec<R: Read, T>(
+    r: &mut R,
+    mut read: impl FnMut(&mut R) -> io::Result<T>,
+) -> io::Result<Vec<T>> {
+    let len = read_usize(r)?;
+    (0..len).map(|_| read(r)).collect()
+}
+
+fn read_side_len<R: Read>(r: &mut R) -> io::Result<SideLen> {
+    Ok(SideLen {
+        records: read_usize(r)?,
+        bytes: read_varint(r)?,
+    })
+}
+
+impl Checkpoint {
+    pub fn write<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut enc = Encoder::new(out);
+        enc.bytes(MAGIC)?;
+        enc.varint(VERSION)?;
+        enc.bytes(&self.info.program_hash.to_le_bytes())?;
+        enc.string(&self.info.simulator)?;
+        enc.usize(self.info.params.len())?;
+        for (key, value) in self.info.params.iter() {
+            enc.string(key)?;
+            enc.string(value)?;
+        }
+        let state = &self.state;
+        enc.usize(state.time)?;
+        enc.usize(state.ivec.len())?;
+        for &i in state.ivec.iter() {
+            enc.signed(i as i64)?;
+        }
+        enc.usize(state.calls.len())?;
+        for &(node, start) in state.calls.iter() {
+            enc.usize(node)?;
+            enc.usize(start)?;
+        }
+        enc.usize(state.cursors.len())?;
+        for cursor in state.cursors.iter() {
+            match *cursor {
+                SavedCursor::Block { owner, pos } => {
+                    enc.varint(0)?;
+                    enc.option(owner)?;
+                    enc.usize(pos)?;
+                }
+                SavedCursor::Loop { node, ub, pos } => {
+                    enc.varint(1)?;
+                    enc.usize(node)?;
+                    enc.signed(ub as i64)?;
+                    enc.usize(pos)?;
+                }
+                SavedCursor::Return => enc.varint(2)?,
+            }
+        }
+        enc.usize(self.stack.len())?;
+        for &x in self.stack.iter() {
+            enc.usize(x)?;
+        }
+        enc.usize(self.hist.len())?;
+        for &(d, cnt) in self.hist.iter() {
+            enc.option(d)?;
+            enc.usize(cnt)?;
+        }
+        for side in [self.dists, self.accesses] {
+            enc.usize(side.records)?;
+            enc.varint(side.bytes)?;
+        }
+        enc.out.flush()
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let r = &mut r;
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        if read_varint(r)? != VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let mut hash = [0u8; 8];
+        r.read_exact(&mut hash)?;
+        let info = RunInfo {
+            program_hash: u64::from_le_bytes(hash),
+            simulator: read_string(r)?,
+            params: read_vec(r, |r| Ok((read_string(r)?, read_string(r)?)))?,
+        };
+        let time = read_usize(r)?;
+        let ivec = read_vec(r, |r| Ok(read_signed(r)? as i32))?;
+        let calls = read_vec(r, |r| Ok((read_usize(r)?, read_usize(r)?)))?;
+        let cursors = read_vec(r, |r| match read_varint(r)? {
+            0 => Ok(SavedCursor::Block {
+                owner: read_option(r)?,
+                pos: read_usize(r)?,
+            }),
+            1 => Ok(SavedCursor::Loop {
+                node: read_usize(r)?,
+                ub: read_signed(r)? as i32,
+                pos: read_usize(r)?,
+            }),
+            2 => Ok(SavedCursor::Return),
+            _ => Err(invalid("unknown cursor kind")),
+        })?;
+        Ok(Checkpoint {
+            info,
+            state: TraceState {
+                cursors,
+                ivec,
+                calls,
+                time,
+            },
+            stack: read_vec(r, read_usize)?,
+            hist: read_vec(r, |r| Ok((read_option(r)?, read_usize(r)?)))?,
+            dists: read_side_len(r)?,
+            accesses: read_side_len(r)?,
+        })
+    }
+
+    /// Write the checkpoint to `path` through a temporary file, so that a crash while
+    /// saving leaves the previous checkpoint intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        self.write(BufWriter::new(File::create(&tmp)?))?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Checkpoint::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// A list that grows with the trace, appended to a side file at every checkpoint.
+struct SideFile {
+    path: PathBuf,
+    out: Encoder<BufWriter<File>>,
+    records: usize,
+}
+
+impl SideFile {
+    fn path(ckpt: &Path, name: &str) -> PathBuf {
+        let mut path = ckpt.as_os_str().to_owned();
+        path.push(".");
+        path.push(name);
+        PathBuf::from(path)
+    }
+
+    fn create(ckpt: &Path, name: &str) -> io::Result<Self> {
+        let path = SideFile::path(ckpt, name);
+        let file = File::create(&path)?;
+        Ok(SideFile {
+            path,
+            out: Encoder::new(BufWriter::new(file)),
+            records: 0,
+        })
+    }
+
+    /// Cut the side file back to `len` and read its records.
+    fn reopen<T>(
+        ckpt: &Path,
+        name: &str,
+        len: SideLen,
+        mut read: impl FnMut(&mut BufReader<File>) -> io::Result<T>,
+    ) -> io::Result<(Self, Vec<T>)> {
+        let path = SideFile::path(ckpt, name);
+        let file = OpenOptions::new().read(true).append(true).open(&path)?;
+        if file.metadata()?.len() < len.bytes {
+            return Err(invalid("a side file is shorter than its checkpoint"));
+        }
+        file.set_len(len.bytes)?;
+        let mut input = BufReader::new(file);
+        let records = (0..len.records)
+            .map(|_| read(&mut input))
+            .collect::<io::Result<_>>()?;
+        let mut out = Encoder::new(BufWriter::new(input.into_inner()));
+        out.bytes = len.bytes;
+        let side = SideFile {
+            path,
+            out,
+            records: len.records,
+        };
+        Ok((side, records))
+    }
+
+    /// Append the records after the ones already in the file, and return its length.
+    fn append<T: Copy>(
+        &mut self,
+        all: &[T],
+        mut write: impl FnMut(&mut Encoder<BufWriter<File>>, T) -> io::Result<()>,
+    ) -> io::Result<SideLen> {
+        for &x in all[self.records..].iter() {
+            write(&mut self.out, x)?;
+        }
+        self.out.out.flush()?;
+        self.records = all.len();
+        Ok(SideLen {
+            records: self.records,
+            bytes: self.out.bytes,
+        })
+    }
+}
+
+fn write_dist(
+    enc: &mut Encoder<BufWriter<File>>,
+    (addr, d): (usize, Option<usize>),
+) -> io::Result<()> {
+    enc.usize(addr)?;
+    enc.option(d)
+}
+
+/// `trace_shared` that saves a checkpoint to `path` every `every` accesses.  With `resume`
+/// it continues from the checkpoint at `path` if there is one, and gives the same results
+/// as a run without interruption.  A checkpoint of another program, simulator or `params`
+/// is an `InvalidInput` error.  The checkpoint and its side files are removed when the
+/// trace is done.
+#[allow(clippy::type_complexity)]
+pub fn trace_resumable<T: Snapshot<usize>>(
+    code: &Arc<Node>,
+    path: &Path,
+    every: usize,
+    resume: bool,
+    params: &[(String, String)],
+) -> io::Result<(
+    Hist,
+    ListSerializable<(usize, Option<usize>)>,
+    ListSerializable<usize>,
+)> {
+    let result = trace_until::<T>(code, path, every, resume, params, usize::MAX)?;
+    Ok(result.unwrap())
+}
+
+/// `trace_resumable` that stops without cleaning up after `limit` accesses, as a crash
+/// would, and then returns `None`.
+#[allow(clippy::type_complexity)]
+fn trace_until<T: Snapshot<usize>>(
+    code: &Arc<Node>,
+    path: &Path,
+    every: usize,
+    resume: bool,
+    params: &[(String, String)],
+    limit: usize,
+) -> io::Result<
+    Option<(
+        Hist,
+        ListSerializable<(usize, Option<usize>)>,
+        ListSerializable<usize>,
+    )>,
+> {
+    assert!(every > 0, "checkpoints must be at least one access apart");
+    let info = RunInfo {
+        program_hash: code.program_hash(),
+        simulator: std::any::type_name::<T>().to_string(),
+        params: params.to_vec(),
+    };
+    let (mut iter, mut rd, mut list, mut dists_file, mut accesses_file) = if resume && path.exists()
+    {
+        let ckpt = Checkpoint::load(path)?;
+        if ckpt.info != info {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "checkpoint {} is of another run: {:?}, not {:?}",
+                    path.display(),
+                    ckpt.info,
+                    info
+                ),
+            ));
+        }
+        let mut rd = ReuseDistance::with_list(T::restore(ckpt.stack));
+        ckpt.hist
+            .into_iter()
+            .for_each(|(d, cnt)| rd.hist.add_dists(d, cnt));
+        let (dists_file, dists) = SideFile::reopen(path, "dists", ckpt.dists, |r| {
+            Ok((read_usize(r)?, read_option(r)?))
+        })?;
+        let dist_rd = rd.dist_rd.as_mut().unwrap();
+        dists.into_iter().for_each(|x| dist_rd.add(x));
+        let (accesses_file, accesses) =
+            SideFile::reopen(path, "accesses", ckpt.accesses, read_usize)?;
+        let mut list = AccessList::new();
+        accesses.into_iter().for_each(|x| list.accesses.add(x));
+        let iter = TraceIter::resume(code, Layout::default(), &ckpt.state);
+        (iter, rd, list, dists_file, accesses_file)
+    } else {
+        let rd = ReuseDistance::with_list(T::restore(vec![]));
+        let dists_file = SideFile::create(path, "dists")?;
+        let accesses_file = SideFile::create(path, "accesses")?;
+        (
+            TraceIter::new(code),
+            rd,
+            AccessList::new(),
+            dists_file,
+            accesses_file,
+        )
+    };
+    let mut left = limit;
+    loop {
+        let mut done = true;
+        for rec in iter.by_ref().take(every.min(left)) {
+            rd.on_access(&rec);
+            list.on_access(&rec);
+            done = false;
+            left -= 1;
+        }
+        if left == 0 {
+            return Ok(None);
+        }
+        if done {
+            break;
+        }
+        Checkpoint {
+            info: info.clone(),
+            state: iter.state(),
+            stack: rd.analyzer().snapshot(),
+            hist: rd.hist.to_vec(),
+            dists: dists_file.append(rd.dist_rd.as_ref().unwrap().get_vec(), write_dist)?,
+            accesses: accesses_file.append(list.accesses.get_vec(), Encoder::usize)?,
+        }
+        .save(path)?;
+    }
+    for file in [path, &dists_file.path, &accesses_file.path] {
+        if file.exists() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(Some((rd.hist, rd.dist_rd.unwrap(), list.accesses)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace::trace_shared;
+    use dace::arybase::set_arybase;
+    use stack_alg_sim::olken::LRUSplay;
+    use stack_alg_sim::stack::LRUStack;
+
+    // i = 0, 6 { j = 0, i { A[i][j] B[j] } }
+    fn triangle(n: usize) -> Arc<Node> {
+        let mut iloop = Node::new_single_loop("i", 0, n as i32);
+        let mut jloop = Node::new_single_loop_dyn_ub("j", 0, |i| i[0]);
+        let mut aref = Node::new_ref("A", vec![n, n], |ij| vec![ij[0] as i64, ij[1] as i64]);
+        let mut bref = Node::new_ref("B", vec![n], |ij| vec![ij[1] as i64]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+        iloop
+    }
+
+    fn params(n: &str) -> Vec<(String, String)> {
+        vec![("N".to_string(), n.to_string())]
+    }
+
+    #[test]
+    fn resume_after_crash() {
+        let iloop = triangle(6);
+        let expected = trace_shared(&iloop, LRUSplay::new());
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("resume_after_crash_{}.ckpt", std::process::id()));
+        // a run that crashed after 7 accesses, 3 after its last checkpoint and in the
+        // middle of appending to a side file
+        let crashed =
+            trace_until::<LRUSplay<usize>>(&iloop, &path, 4, false, &params("6"), 7).unwrap();
+        assert!(crashed.is_none());
+        let ckpt = Checkpoint::load(&path).unwrap();
+        assert_eq!(ckpt.state.time, 4);
+        assert_eq!((ckpt.dists.records, ckpt.accesses.records), (4, 4));
+        let mut side = OpenOptions::new()
+            .append(true)
+            .open(SideFile::path(&path, "dists"))
+            .unwrap();
+        side.write_all(&[0x85]).unwrap();
+
+        let resumed =
+            trace_resumable::<LRUSplay<usize>>(&iloop, &path, 4, true, &params("6")).unwrap();
+        assert!(!path.exists());
+        assert!(!SideFile::path(&path, "dists").exists());
+        let fresh =
+            trace_resumable::<LRUSplay<usize>>(&iloop, &path, 3, true, &params("6")).unwrap();
+        for (hist, dists, accesses) in [resumed, fresh] {
+            assert_eq!(hist.to_vec(), expected.0.to_vec());
+            assert_eq!(dists.get_vec(), expected.1.get_vec());
+            assert_eq!(accesses.get_vec(), expected.2.get_vec());
+        }
+    }
+
+    #[test]
+    fn resume_another_run() {
+        let iloop = triangle(6);
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("resume_another_run_{}.ckpt", std::process::id()));
+        trace_until::<LRUSplay<usize>>(&iloop, &path, 4, false, &params("6"), 7).unwrap();
+
+        let other_size =
+            trace_resumable::<LRUSplay<usize>>(&iloop, &path, 4, true, &params("7")).err();
+        let other_lru =
+            trace_resumable::<LRUStack<usize>>(&iloop, &path, 4, true, &params("6")).err();
+        let other_program =
+            trace_resumable::<LRUSplay<usize>>(&triangle(7), &path, 4, true, &params("6")).err();
+        for err in [other_size, other_lru, other_program] {
+            assert_eq!(err.unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+        // the same run still resumes, and cleans up
+        trace_resumable::<LRUSplay<usize>>(&iloop, &path, 4, true, &params("6")).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -1,6 +1,9 @@
 #![feature(get_mut_unchecked)]
 
+pub mod checkpoint;
+pub mod sink;
 pub mod trace;
 pub use stack_alg_sim::{
-    olken::LRUSplay, scale_tree::LRUSplay as LRUScaleTree, stack::LRUStack, vec::LRUVec, LRU,
+    bounded::Bounded, fenwick::LRUFenwick, olken::LRUSplay, scale_tree::LRUSplay as LRUScaleTree,
+    stack::LRUStack, vec::LRUVec, LRU,
 };
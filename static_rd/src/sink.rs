@@ -0,0 +1,167 @@
+use dace::ast::{LoopStmt, Node};
+use dace::record::AccessRecord;
+use hist::Hist;
+use list_serializable::ListSerializable;
+use stack_alg_sim::LRU;
+use trace_file::{Header, TraceWriter};
+
+use std::io::{self, Write};
+
+/// A consumer of the events of one trace enumeration.  `trace::trace_to` feeds the same
+/// traversal to any number of sinks, in the order they are given.
+pub trait TraceSink {
+    /// An access, with records numbered by logical time.
+    fn on_access(&mut self, rec: &AccessRecord);
+
+    /// A loop is entered, before its first iteration.  `ivec` holds the indices of the
+    /// enclosing loops.
+    fn on_loop_enter(&mut self, _aloop: &LoopStmt, _ivec: &[i32]) {}
+
+    /// A loop is left after its last iteration.
+    fn on_loop_exit(&mut self, _aloop: &LoopStmt, _ivec: &[i32]) {}
+
+    /// The trace has ended.
+    fn finish(&mut self) {}
+}
+
+/// Reuse distance histogram of element addresses from an LRU stack simulator, optionally
+/// with the distance of every access.
+pub struct ReuseDistance<T: LRU<usize>> {
+    analyzer: T,
+    pub hist: Hist,
+    pub dist_rd: Option<ListSerializable<(usize, Option<usize>)>>,
+}
+
+impl<T: LRU<usize>> ReuseDistance<T> {
+    pub fn new(analyzer: T) -> Self {
+        ReuseDistance {
+            analyzer,
+            hist: Hist::new(),
+            dist_rd: None,
+        }
+    }
+
+    /// Also keep the `(address, distance)` pair of every access.
+    pub fn with_list(analyzer: T) -> Self {
+        ReuseDistance {
+            dist_rd: Some(ListSerializable::new()),
+            ..ReuseDistance::new(analyzer)
+        }
+    }
+
+    pub fn analyzer(&self) -> &T {
+        &self.analyzer
+    }
+}
+
+impl<T: LRU<usize>> TraceSink for ReuseDistance<T> {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        let rd = self.analyzer.rec_access(rec.elem);
+        self.hist.add_dist(rd);
+        if let Some(dist_rd) = &mut self.dist_rd {
+            dist_rd.add((rec.elem, rd));
+        }
+    }
+}
+
+/// The element addresses of all accesses, in order.
+#[derive(Default)]
+pub struct AccessList {
+    pub accesses: ListSerializable<usize>,
+}
+
+impl AccessList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TraceSink for AccessList {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        self.accesses.add(rec.elem);
+    }
+}
+
+/// Writes the element addresses, and the ref ids if the header asks for them, to a
+/// binary trace file.  Write errors stop the output and are returned by `into_inner`.
+pub struct TraceFile<W: Write> {
+    writer: TraceWriter<W>,
+    ref_ids: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceFile<W> {
+    pub fn new(out: W, header: &Header) -> io::Result<Self> {
+        Ok(TraceFile {
+            writer: TraceWriter::new(out, header)?,
+            ref_ids: header.ref_ids,
+            error: None,
+        })
+    }
+
+    /// A trace file of `code`, whose header has the hash of the tree.
+    pub fn for_program(
+        out: W,
+        code: &Node,
+        ref_ids: bool,
+        params: Vec<(String, String)>,
+    ) -> io::Result<Self> {
+        let header = Header {
+            ref_ids,
+            program_hash: code.program_hash(),
+            params,
+        };
+        TraceFile::new(out, &header)
+    }
+
+    /// The output, or the first error met while writing it.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.writer.into_inner(),
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TraceFile<W> {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        if self.error.is_none() {
+            let ref_id = self.ref_ids.then_some(rec.ref_id);
+            self.error = self.writer.write(rec.elem, ref_id).err();
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.error.is_none() {
+            self.error = self.writer.finish().err();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dace::ast::AccessKind;
+    use stack_alg_sim::stack::LRUStack;
+
+    #[test]
+    fn reuse_distance() {
+        let mut rd = ReuseDistance::with_list(LRUStack::new());
+        for (time, elem) in [1, 2, 2, 1].into_iter().enumerate() {
+            let rec = AccessRecord {
+                time,
+                ref_id: 0,
+                ary_id: 0,
+                ivec: [time as i32][..].into(),
+                index: elem,
+                elem,
+                addr: elem * 8,
+                line: elem / 8,
+                kind: AccessKind::Read,
+            };
+            rd.on_access(&rec);
+        }
+        assert_eq!(rd.hist.to_vec(), [(Some(1), 1), (Some(2), 1), (None, 2)]);
+        assert_eq!(rd.dist_rd.unwrap().get_vec()[2], (2, Some(1)));
+    }
+}
@@ -1,138 +1,434 @@
 use dace::arybase::set_arybase;
-use dace::ast::{AryRef, LoopBound, Node, Stmt};
+use dace::ast::{LoopStmt, Node, Stmt};
+use dace::compile::compile;
+use dace::iter::TraceIter;
+use dace::record::{AccessRecord, CallFrame, Layout};
+
+use crate::sink::{AccessList, ReuseDistance, TraceSink};
 use hist::Hist;
 use list_serializable::ListSerializable;
 
+use stack_alg_sim::opt::opt_hist;
+use stack_alg_sim::shards::Shards;
 use stack_alg_sim::LRU;
 
 use std::ptr::null;
-use std::rc::Rc;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+
+/// Events a worker of `trace_to_parallel` sends at a time.
+const BATCH_LEN: usize = 1 << 12;
+/// Batches a worker may send ahead of the replay before it waits, which bounds the
+/// events buffered per worker.
+const BATCHES_AHEAD: usize = 4;
+/// Chunks of the outermost loop per worker, to even out triangular loops.
+const CHUNKS_PER_THREAD: usize = 16;
 
-fn access2addr(ary_ref: &AryRef, ivec: &[i32]) -> usize {
-    let ary_index = (ary_ref.sub)(ivec);
-    if ary_index.len() != ary_ref.dim.len() {
-        panic!("array index and dimension do not match");
+/// What the traversal carries besides the iteration vector.
+struct Emitter<'s, 'k, 'a> {
+    time: usize,
+    layout: Layout,
+    out: Out<'s, 'k, 'a>,
+}
+
+/// Where the events go: straight to the sinks, or in batches from a worker of
+/// `trace_to_parallel` to the thread that replays them.
+enum Out<'s, 'k, 'a> {
+    Sinks(&'s mut [&'k mut dyn TraceSink]),
+    Batches {
+        batch: Vec<Event<'a>>,
+        len: usize,
+        tx: SyncSender<Vec<Event<'a>>>,
+    },
+}
+
+enum Event<'a> {
+    Access(AccessRecord),
+    Enter(&'a LoopStmt, Vec<i32>),
+    Exit(&'a LoopStmt, Vec<i32>),
+}
+
+impl<'a> Emitter<'_, '_, 'a> {
+    fn access(&mut self, rec: AccessRecord) {
+        match &mut self.out {
+            Out::Sinks(sinks) => sinks.iter_mut().for_each(|sink| sink.on_access(&rec)),
+            Out::Batches { .. } => self.send(Event::Access(rec)),
+        }
     }
 
-    let offset = ary_index
-        .iter()
-        .zip(ary_ref.dim.iter())
-        .fold(0, |acc, (&i, &d)| acc * d + i);
+    fn enter(&mut self, aloop: &'a LoopStmt, ivec: &[i32]) {
+        match &mut self.out {
+            Out::Sinks(sinks) => sinks
+                .iter_mut()
+                .for_each(|sink| sink.on_loop_enter(aloop, ivec)),
+            Out::Batches { .. } => self.send(Event::Enter(aloop, ivec.to_vec())),
+        }
+    }
+
+    fn exit(&mut self, aloop: &'a LoopStmt, ivec: &[i32]) {
+        match &mut self.out {
+            Out::Sinks(sinks) => sinks
+                .iter_mut()
+                .for_each(|sink| sink.on_loop_exit(aloop, ivec)),
+            Out::Batches { .. } => self.send(Event::Exit(aloop, ivec.to_vec())),
+        }
+    }
+
+    /// Add an event to the batch, and send the batch when it is full.
+    fn send(&mut self, event: Event<'a>) {
+        let Out::Batches { batch, len, .. } = &mut self.out else {
+            unreachable!()
+        };
+        batch.push(event);
+        if batch.len() == *len {
+            self.flush();
+        }
+    }
 
-    ary_ref.base.unwrap() + offset
+    /// Send the events of the batch, if any.  A send fails only when the replay has
+    /// stopped, which the scope of the workers reports.
+    fn flush(&mut self) {
+        let Out::Batches { batch, len, tx } = &mut self.out else {
+            unreachable!()
+        };
+        if !batch.is_empty() {
+            let full = std::mem::replace(batch, Vec::with_capacity(*len));
+            let _ = tx.send(full);
+        }
+    }
 }
 
-fn trace_rec_impl<T: LRU<usize>>(
-    code: &Rc<Node>,
+fn trace_rec_impl<'a>(
+    code: &'a Arc<Node>,
     ivec: &mut Vec<i32>,
-    sim: &mut T,
-    hist: &mut Hist,
-    data_accesses: &mut ListSerializable<usize>,
-    dist_rd: &mut ListSerializable<(usize, Option<usize>)>,
+    frame: Option<&CallFrame>,
+    out: &mut Emitter<'_, '_, 'a>,
 ) {
     match &code.stmt {
         Stmt::Ref(ary_ref) => {
-            let addr = access2addr(ary_ref, ivec);
-            data_accesses.add(addr);
-            let rd = sim.rec_access(addr);
-            dist_rd.add((addr, rd));
-            hist.add_dist(rd);
+            let Some(rec) = AccessRecord::new(ary_ref, ivec, frame, out.time, &out.layout) else {
+                return;
+            };
+            out.time += 1;
+            out.access(rec);
         }
         Stmt::Loop(aloop) => {
-            let mut i = match &aloop.lb {
-                LoopBound::Fixed(lb) => *lb,
-                LoopBound::Dynamic(lb) => lb(ivec),
-                LoopBound::Affine { a, b } => {
-                    a.iter()
-                        .copied()
-                        .zip(ivec.iter().copied())
-                        .map(|(x, y)| x * y)
-                        .sum::<i32>()
-                        + *b
-                }
-            };
-            let ub = match &aloop.ub {
-                LoopBound::Fixed(ub) => *ub,
-                LoopBound::Dynamic(ub) => ub(ivec),
-                LoopBound::Affine { a, b } => {
-                    a.iter()
-                        .copied()
-                        .zip(ivec.iter().copied())
-                        .map(|(x, y)| x * y)
-                        .sum::<i32>()
-                        + *b
-                }
-            };
+            let mut i = aloop.lb.eval(ivec);
+            let ub = aloop.ub.eval(ivec);
 
+            out.enter(aloop, ivec);
             while (aloop.test)(i, ub) {
                 ivec.push(i);
                 for code in aloop.body.iter() {
-                    trace_rec_impl(code, ivec, sim, hist, data_accesses, dist_rd);
+                    trace_rec_impl(code, ivec, frame, out);
                 }
                 ivec.pop();
                 i = (aloop.step)(i);
             }
+            out.exit(aloop, ivec);
         }
-        Stmt::Block(blk) => blk
-            .iter()
-            .for_each(|s| trace_rec_impl(s, ivec, sim, hist, data_accesses, dist_rd)),
+        Stmt::Block(blk) => blk.iter().for_each(|s| trace_rec_impl(s, ivec, frame, out)),
         Stmt::Branch(stmt) => {
             if (stmt.cond)(ivec) {
-                trace_rec_impl(&stmt.then_body, ivec, sim, hist, data_accesses, dist_rd)
+                trace_rec_impl(&stmt.then_body, ivec, frame, out)
             } else if let Some(else_body) = &stmt.else_body {
-                trace_rec_impl(else_body, ivec, sim, hist, data_accesses, dist_rd)
+                trace_rec_impl(else_body, ivec, frame, out)
+            }
+        }
+        Stmt::Call(call) => {
+            let callee_frame = CallFrame::new(call, frame);
+            let mut callee_ivec = call.scalars.iter().map(|s| s.eval(ivec)).collect();
+            trace_rec_impl(
+                &call.callee.body,
+                &mut callee_ivec,
+                Some(&callee_frame),
+                out,
+            )
+        }
+    }
+}
+
+/// Enumerate the accesses of a tree whose array bases have been set, feeding every event
+/// to each of `sinks` and calling `finish` on them at the end.
+pub fn trace_to(code: &Arc<Node>, sinks: &mut [&mut dyn TraceSink]) {
+    trace_to_with_layout(code, Layout::default(), sinks)
+}
+
+/// Like `trace_to`, with the byte and cache-line addresses of the records computed for
+/// `layout`.
+pub fn trace_to_with_layout(code: &Arc<Node>, layout: Layout, sinks: &mut [&mut dyn TraceSink]) {
+    let mut out = Emitter {
+        time: 0,
+        layout,
+        out: Out::Sinks(&mut *sinks),
+    };
+    trace_rec_impl(code, &mut Vec::<i32>::new(), None, &mut out);
+    sinks.iter_mut().for_each(|sink| sink.finish());
+}
+
+/// Like `trace_to`, with the iterations of the outermost loop split into chunks that
+/// `threads` worker threads enumerate.  The events of the chunks are fed to the sinks in
+/// order, with logical times shifted by the accesses of the chunks before, so the sinks
+/// see exactly what `trace_to` gives them.  Workers stream their events in batches and
+/// wait when they are `BATCHES_AHEAD` batches ahead of the replay, so the events held
+/// at a time are bounded by the number of threads, not by the size of the trace.  If the
+/// tree is a block, each loop in it is split in turn and the other statements are
+/// traced on the calling thread.
+pub fn trace_to_parallel(code: &Arc<Node>, threads: usize, sinks: &mut [&mut dyn TraceSink]) {
+    trace_to_parallel_batched(code, threads.max(1), BATCH_LEN, sinks);
+    sinks.iter_mut().for_each(|sink| sink.finish());
+}
+
+fn trace_to_parallel_batched(
+    code: &Arc<Node>,
+    threads: usize,
+    batch: usize,
+    sinks: &mut [&mut dyn TraceSink],
+) {
+    let stmts = match &code.stmt {
+        Stmt::Block(blk) => blk.as_slice(),
+        _ => std::slice::from_ref(code),
+    };
+    let mut time = 0;
+    for code in stmts {
+        time = match &code.stmt {
+            Stmt::Loop(aloop) => parallel_loop(aloop, threads, batch, time, sinks),
+            _ => {
+                let mut out = Emitter {
+                    time,
+                    layout: Layout::default(),
+                    out: Out::Sinks(&mut *sinks),
+                };
+                trace_rec_impl(code, &mut vec![], None, &mut out);
+                out.time
             }
+        };
+    }
+}
+
+/// Trace the outermost loop `aloop` on `threads` workers, starting at logical time
+/// `time`, and return the time after it.  Worker w enumerates chunks w, w + threads, ...
+/// in order, and an empty batch ends each chunk.
+fn parallel_loop(
+    aloop: &LoopStmt,
+    threads: usize,
+    batch: usize,
+    mut time: usize,
+    sinks: &mut [&mut dyn TraceSink],
+) -> usize {
+    let ub = aloop.ub.eval(&[]);
+    let mut indices = vec![];
+    let mut i = aloop.lb.eval(&[]);
+    while (aloop.test)(i, ub) {
+        indices.push(i);
+        i = (aloop.step)(i);
+    }
+    let chunk_len = indices.len().div_ceil(CHUNKS_PER_THREAD * threads).max(1);
+    let chunks: Vec<&[i32]> = indices.chunks(chunk_len).collect();
+    let threads = threads.min(chunks.len()).max(1);
+
+    sinks
+        .iter_mut()
+        .for_each(|sink| sink.on_loop_enter(aloop, &[]));
+    std::thread::scope(|scope| {
+        let receivers: Vec<Receiver<Vec<Event>>> = (0..threads)
+            .map(|w| {
+                let (tx, rx) = sync_channel(BATCHES_AHEAD);
+                let mine: Vec<&[i32]> = chunks.iter().skip(w).step_by(threads).copied().collect();
+                scope.spawn(move || {
+                    for chunk in mine {
+                        trace_chunk(aloop, chunk, batch, &tx);
+                        if tx.send(vec![]).is_err() {
+                            return;
+                        }
+                    }
+                });
+                rx
+            })
+            .collect();
+        for k in 0..chunks.len() {
+            let mut accesses = 0;
+            // a closed channel means the worker panicked, which the scope reports
+            while let Ok(events) = receivers[k % threads].recv() {
+                if events.is_empty() {
+                    break;
+                }
+                accesses += replay(events, time, sinks);
+            }
+            time += accesses;
+        }
+    });
+    sinks
+        .iter_mut()
+        .for_each(|sink| sink.on_loop_exit(aloop, &[]));
+    time
+}
+
+/// Feed `events` to the sinks with times shifted by `time`, and return the number of
+/// accesses among them.
+fn replay(events: Vec<Event>, time: usize, sinks: &mut [&mut dyn TraceSink]) -> usize {
+    let mut accesses = 0;
+    for event in events {
+        match event {
+            Event::Access(mut rec) => {
+                accesses += 1;
+                rec.time += time;
+                sinks.iter_mut().for_each(|sink| sink.on_access(&rec));
+            }
+            Event::Enter(inner, ivec) => sinks
+                .iter_mut()
+                .for_each(|sink| sink.on_loop_enter(inner, &ivec)),
+            Event::Exit(inner, ivec) => sinks
+                .iter_mut()
+                .for_each(|sink| sink.on_loop_exit(inner, &ivec)),
         }
     }
+    accesses
+}
+
+/// Send the events of the outermost loop `aloop` over the iterations `chunk` in batches
+/// of `batch`, with logical times counted from the start of the chunk.
+fn trace_chunk<'a>(
+    aloop: &'a LoopStmt,
+    chunk: &[i32],
+    batch: usize,
+    tx: &SyncSender<Vec<Event<'a>>>,
+) {
+    let mut out = Emitter {
+        time: 0,
+        layout: Layout::default(),
+        out: Out::Batches {
+            batch: Vec::with_capacity(batch),
+            len: batch,
+            tx: tx.clone(),
+        },
+    };
+    let mut ivec = vec![];
+    for &i in chunk {
+        ivec.push(i);
+        aloop
+            .body
+            .iter()
+            .for_each(|code| trace_rec_impl(code, &mut ivec, None, &mut out));
+        ivec.pop();
+    }
+    out.flush();
 }
 
 #[allow(clippy::type_complexity)]
 pub fn trace<T: LRU<usize>>(
-    code: &mut Rc<Node>,
-    mut analyzer: T,
+    code: &mut Arc<Node>,
+    analyzer: T,
 ) -> (
     Hist,
     ListSerializable<(usize, Option<usize>)>,
     ListSerializable<usize>,
 ) {
-    let mut accesses_count: ListSerializable<usize> = ListSerializable::<usize>::new();
-    let mut dist_rd: ListSerializable<(usize, Option<usize>)> =
-        ListSerializable::<(usize, Option<usize>)>::new();
-    let mut hist = Hist::new();
     set_arybase(code);
     println!("{:?}", code);
-    trace_rec_impl(
-        code,
-        &mut Vec::<i32>::new(),
-        &mut analyzer,
-        &mut hist,
-        &mut accesses_count,
-        &mut dist_rd,
-    );
-    (hist, dist_rd, accesses_count)
+    trace_shared(code, analyzer)
+}
+
+/// Like `trace`, for a tree whose array bases have already been set with `set_arybase`.
+/// The tree is only read, so clones of one `Arc<Node>` can be traced on several threads,
+/// e.g. with different LRU algorithms.
+#[allow(clippy::type_complexity)]
+pub fn trace_shared<T: LRU<usize>>(
+    code: &Arc<Node>,
+    analyzer: T,
+) -> (
+    Hist,
+    ListSerializable<(usize, Option<usize>)>,
+    ListSerializable<usize>,
+) {
+    let mut rd = ReuseDistance::with_list(analyzer);
+    let mut accesses = AccessList::new();
+    trace_to(code, &mut [&mut rd, &mut accesses]);
+    (rd.hist, rd.dist_rd.unwrap(), accesses.accesses)
+}
+
+/// `trace` with the accesses enumerated on `threads` threads by `trace_to_parallel`.
+/// The LRU analysis still sees the whole trace in order, so the results are the same.
+#[allow(clippy::type_complexity)]
+pub fn trace_parallel<T: LRU<usize>>(
+    code: &mut Arc<Node>,
+    analyzer: T,
+    threads: usize,
+) -> (
+    Hist,
+    ListSerializable<(usize, Option<usize>)>,
+    ListSerializable<usize>,
+) {
+    set_arybase(code);
+    let mut rd = ReuseDistance::with_list(analyzer);
+    let mut accesses = AccessList::new();
+    trace_to_parallel(code, threads, &mut [&mut rd, &mut accesses]);
+    (rd.hist, rd.dist_rd.unwrap(), accesses.accesses)
+}
+
+/// The reuse distance histogram of a tree whose array bases have been set, computed from
+/// a streaming `TraceIter` without keeping the trace.  Memory is bounded by `analyzer`.
+pub fn trace_hist<T: LRU<usize>>(code: &Arc<Node>, mut analyzer: T) -> Hist {
+    let mut hist = Hist::new();
+    TraceIter::new(code).for_each(|rec| hist.add_dist(analyzer.rec_access(rec.elem)));
+    hist
+}
+
+/// `trace_hist` with the accesses enumerated by the compiled loop program of the tree,
+/// which is much faster when the subscripts are affine, e.g. for trees made by `dace!`.
+pub fn trace_hist_compiled<T: LRU<usize>>(code: &Arc<Node>, mut analyzer: T) -> Hist {
+    let mut hist = Hist::new();
+    compile(code).run(|elem, _| hist.add_dist(analyzer.rec_access(elem)));
+    hist
+}
+
+/// The histogram of the OPT stack distances of a tree whose array bases have been set.
+/// OPT looks ahead, so the trace is kept: one pass enumerates it and `opt_hist` makes two
+/// more over it.  The cost of `stack_alg_sim::opt`, about trace length × footprint,
+/// limits this in practice to trees of a few million accesses.
+pub fn trace_hist_opt(code: &Arc<Node>) -> Hist {
+    let trace: Vec<usize> = TraceIter::new(code).map(|rec| rec.elem).collect();
+    opt_hist(&trace)
+}
+
+/// The reuse distance histogram of a tree whose array bases have been set, estimated by
+/// `shards` from a sample of the elements, and the sampling rate at the end.
+pub fn trace_hist_shards<T: LRU<usize>>(
+    code: &Arc<Node>,
+    mut shards: Shards<usize, T>,
+) -> (Hist, f64) {
+    TraceIter::new(code).for_each(|rec| {
+        shards.access(rec.elem);
+    });
+    (shards.hist(), shards.rate())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use dace::ast::{Boundary, LoopBound, Subprogram};
     use stack_alg_sim::stack::LRUStack;
 
     #[test]
     fn test_access2addr() {
-        let mut aij_node =
-            Node::new_ref("x", vec![10, 10], |ij| vec![ij[0] as usize, ij[1] as usize]);
-        let mutable = unsafe { Rc::get_mut_unchecked(&mut aij_node) };
+        let mut aij_node = Node::new_ref("x", vec![10, 10], |ij| vec![ij[0] as i64, ij[1] as i64]);
+        let mutable = unsafe { Arc::get_mut_unchecked(&mut aij_node) };
         *mutable.ref_only_mut_ref(|a| &mut a.base).unwrap() = Some(0);
+        *mutable.ref_only_mut_ref(|a| &mut a.ary_id).unwrap() = Some(0);
+        *mutable.ref_only_mut_ref(|a| &mut a.ref_id).unwrap() = Some(0);
+        let access2addr = |aij, ivec: &[i32]| {
+            AccessRecord::new(aij, ivec, None, 0, &Layout::default()).map(|rec| rec.elem)
+        };
         if let Stmt::Ref(aij) = &aij_node.stmt {
-            assert_eq!(access2addr(aij, &[0, 0]), 0);
-            assert_eq!(access2addr(aij, &[9, 9]), 99);
+            assert_eq!(access2addr(aij, &[0, 0]), Some(0));
+            assert_eq!(access2addr(aij, &[9, 9]), Some(99));
         }
     }
 
     #[test]
     fn loop_a_i() {
         // i = 0, 10 { a[i] }
-        let mut aref = Node::new_ref("A", vec![10], |i| vec![i[0] as usize]);
+        let mut aref = Node::new_ref("A", vec![10], |i| vec![i[0] as i64]);
         let mut aloop = Node::new_single_loop("i", 0, 10);
         Node::extend_loop_body(&mut aloop, &mut aref);
 
@@ -155,4 +451,263 @@ mod test {
         assert_eq!(hist.to_vec()[1], (None, 1));
         println!("{}", hist);
     }
+
+    #[test]
+    fn loop_halo() {
+        // i = 0, 10 { a[i-1] a[i+1] }, skipping the accesses outside a[0..10]
+        let mut left =
+            Node::new_ref_with_boundary("A", vec![10], Boundary::Skip, |i| vec![i[0] as i64 - 1]);
+        let mut right =
+            Node::new_ref_with_boundary("A", vec![10], Boundary::Skip, |i| vec![i[0] as i64 + 1]);
+        let mut aloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut aloop, &mut left);
+        Node::extend_loop_body(&mut aloop, &mut right);
+
+        let result = trace(&mut aloop, LRUStack::new());
+        assert_eq!(result.2.get_vec().len(), 18);
+        assert_eq!(result.2.get_vec()[..3], [1, 0, 2]);
+    }
+
+    #[test]
+    fn call_matches_inline() {
+        // axpy(y, x; o) = i = 0, 4 { y[o+i] x[i] }
+        let mut body = Node::new_single_loop("i", 0, 4);
+        let mut y = Node::new_ref("y", vec![8], |oi| vec![(oi[0] + oi[1]) as i64]);
+        let mut x = Node::new_ref("x", vec![4], |oi| vec![oi[1] as i64]);
+        Node::extend_loop_body(&mut body, &mut y);
+        Node::extend_loop_body(&mut body, &mut x);
+        let axpy = Subprogram::new("axpy", vec![("y", vec![8]), ("x", vec![4])], 1, body);
+        // halves(u, v) = axpy(u, v; 0) axpy(u, v; 4)
+        let halves = Node::new_node(Stmt::Block(vec![
+            Node::new_call(&axpy, &["u", "v"], vec![LoopBound::Fixed(0)]),
+            Node::new_call(&axpy, &["u", "v"], vec![LoopBound::Fixed(4)]),
+        ]));
+        let halves = Subprogram::new("halves", vec![("u", vec![8]), ("v", vec![4])], 0, halves);
+
+        // t = 0, 2 { X[0] halves(Y, X) }
+        let mut called = Node::new_single_loop("t", 0, 2);
+        let mut x0 = Node::new_ref("X", vec![4], |_| vec![0]);
+        let mut call = Node::new_call(&halves, &["Y", "X"], vec![]);
+        Node::extend_loop_body(&mut called, &mut x0);
+        Node::extend_loop_body(&mut called, &mut call);
+
+        // t = 0, 2 { X[0] i = 0, 8 { Y[i] X[i%4] } }
+        let mut inline = Node::new_single_loop("t", 0, 2);
+        let mut iloop = Node::new_single_loop("i", 0, 8);
+        let mut x0 = Node::new_ref("X", vec![4], |_| vec![0]);
+        let mut y = Node::new_ref("Y", vec![8], |ti| vec![ti[1] as i64]);
+        let mut x = Node::new_ref("X", vec![4], |ti| vec![ti[1] as i64 % 4]);
+        Node::extend_loop_body(&mut iloop, &mut y);
+        Node::extend_loop_body(&mut iloop, &mut x);
+        Node::extend_loop_body(&mut inline, &mut x0);
+        Node::extend_loop_body(&mut inline, &mut iloop);
+
+        let called = trace(&mut called, LRUStack::new());
+        let inline = trace(&mut inline, LRUStack::new());
+        assert_eq!(called.2.get_vec().len(), 34);
+        assert_eq!(called.2.get_vec(), inline.2.get_vec());
+        assert_eq!(called.0.to_vec(), inline.0.to_vec());
+    }
+
+    #[test]
+    fn streaming_hist() {
+        // i = 0, 10 { j = 0, i { a[j] b[i-j] } }
+        let mut aref = Node::new_ref("A", vec![10], |ij| vec![ij[1] as i64]);
+        let mut bref = Node::new_ref("B", vec![10], |ij| vec![(ij[0] - ij[1]) as i64]);
+        let mut jloop = Node::new_single_loop_dyn_ub("j", 0, |i| i[0]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+
+        let (hist, _, accesses) = trace(&mut iloop, crate::LRUSplay::new());
+        assert_eq!(
+            TraceIter::new(&iloop)
+                .map(|rec| rec.elem)
+                .collect::<Vec<_>>(),
+            *accesses.get_vec()
+        );
+        assert_eq!(
+            trace_hist(&iloop, crate::LRUSplay::new()).to_vec(),
+            hist.to_vec()
+        );
+        assert_eq!(
+            trace_hist_compiled(&iloop, crate::LRUSplay::new()).to_vec(),
+            hist.to_vec()
+        );
+        let opt = trace_hist_opt(&iloop);
+        assert_eq!(opt.to_vec(), opt_hist(accesses.get_vec()).to_vec());
+        for size in 1..=20 {
+            assert!(opt.misses(size) <= hist.misses(size));
+        }
+        assert_eq!(opt.misses(20), hist.misses(20));
+    }
+
+    #[test]
+    fn several_sinks() {
+        // i = 0, 4 { j = 0, 2 { a[j] } }
+        let mut aref = Node::new_ref("A", vec![2], |ij| vec![ij[1] as i64]);
+        let mut jloop = Node::new_single_loop("j", 0, 2);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+
+        #[derive(Default)]
+        struct Loops {
+            entered: usize,
+            exited: usize,
+            finished: bool,
+        }
+        impl TraceSink for Loops {
+            fn on_access(&mut self, _rec: &AccessRecord) {}
+            fn on_loop_enter(&mut self, _aloop: &dace::ast::LoopStmt, _ivec: &[i32]) {
+                self.entered += 1;
+            }
+            fn on_loop_exit(&mut self, _aloop: &dace::ast::LoopStmt, _ivec: &[i32]) {
+                self.exited += 1;
+            }
+            fn finish(&mut self) {
+                self.finished = true;
+            }
+        }
+
+        let mut loops = Loops::default();
+        let mut rd = ReuseDistance::new(LRUStack::new());
+        trace_to(&iloop, &mut [&mut loops, &mut rd]);
+        assert_eq!((loops.entered, loops.exited, loops.finished), (5, 5, true));
+        assert_eq!(rd.hist.to_vec(), [(Some(2), 6), (None, 2)]);
+    }
+
+    #[test]
+    fn parallel_trace() {
+        // i = 0, 10 { j = 0, i { a[j] b[i-j] } if i % 3 == 0 { a[i] } }
+        let mut aref = Node::new_ref("A", vec![10], |ij| vec![ij[1] as i64]);
+        let mut bref = Node::new_ref("B", vec![10], |ij| vec![(ij[0] - ij[1]) as i64]);
+        let mut jloop = Node::new_single_loop_dyn_ub("j", 0, |i| i[0]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        let mut branch = Node::new_node(Stmt::Branch(dace::ast::BranchStmt {
+            cond: Box::new(|i| i[0] % 3 == 0),
+            then_body: Node::new_ref("A", vec![10], |i| vec![i[0] as i64]),
+            else_body: None,
+        }));
+        Node::extend_loop_body(&mut iloop, &mut branch);
+
+        /// Every event, as text.
+        #[derive(Default)]
+        struct Events(Vec<String>);
+        impl TraceSink for Events {
+            fn on_access(&mut self, rec: &AccessRecord) {
+                self.0
+                    .push(format!("{} {} {:?}", rec.time, rec.elem, rec.ivec));
+            }
+            fn on_loop_enter(&mut self, aloop: &dace::ast::LoopStmt, ivec: &[i32]) {
+                self.0.push(format!("enter {} {:?}", aloop.iv, ivec));
+            }
+            fn on_loop_exit(&mut self, aloop: &dace::ast::LoopStmt, ivec: &[i32]) {
+                self.0.push(format!("exit {} {:?}", aloop.iv, ivec));
+            }
+        }
+
+        let (hist, dists, accesses) = trace(&mut iloop, LRUStack::new());
+        let mut expected = Events::default();
+        trace_to(&iloop, &mut [&mut expected]);
+        for threads in [1, 3, 16] {
+            let (p_hist, p_dists, p_accesses) =
+                trace_parallel(&mut iloop, LRUStack::new(), threads);
+            assert_eq!(p_hist.to_vec(), hist.to_vec());
+            assert_eq!(p_dists.get_vec(), dists.get_vec());
+            assert_eq!(p_accesses.get_vec(), accesses.get_vec());
+            let mut events = Events::default();
+            trace_to_parallel(&iloop, threads, &mut [&mut events]);
+            assert_eq!(events.0, expected.0);
+            // batches smaller than a chunk
+            let mut events = Events::default();
+            trace_to_parallel_batched(&iloop, threads, 3, &mut [&mut events]);
+            assert_eq!(events.0, expected.0);
+        }
+
+        // a block root splits each of its loops
+        let mut block = Node::new_node(Stmt::Block(vec![
+            iloop.clone(),
+            Node::new_ref("B", vec![10], |_| vec![0]),
+            iloop.clone(),
+        ]));
+        set_arybase(&mut block);
+        let mut expected = Events::default();
+        trace_to(&block, &mut [&mut expected]);
+        for threads in [1, 4] {
+            let mut events = Events::default();
+            trace_to_parallel_batched(&block, threads, 5, &mut [&mut events]);
+            assert_eq!(events.0, expected.0);
+        }
+    }
+
+    #[test]
+    fn trace_file_round_trip() {
+        // i = 0, 10 { j = 0, 10 { a[i] b[j] } }
+        let mut aref = Node::new_ref("A", vec![10], |ij| vec![ij[0] as i64]);
+        let mut bref = Node::new_ref("B", vec![10], |ij| vec![ij[1] as i64]);
+        let mut jloop = Node::new_single_loop("j", 0, 10);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+
+        let params = vec![("N".to_string(), "10".to_string())];
+        let mut file =
+            crate::sink::TraceFile::for_program(Vec::new(), &iloop, true, params.clone()).unwrap();
+        let mut rd = ReuseDistance::new(LRUStack::new());
+        trace_to(&iloop, &mut [&mut file, &mut rd]);
+        let bytes = file.into_inner().unwrap();
+
+        let reader = trace_file::TraceReader::new(bytes.as_slice()).unwrap();
+        let header = reader.header();
+        assert_eq!(header.program_hash, iloop.program_hash());
+        assert_eq!((header.ref_ids, &header.params), (true, &params));
+        let entries: Vec<_> = reader.map(Result::unwrap).collect();
+        let expected: Vec<_> = TraceIter::new(&iloop)
+            .map(|rec| (rec.elem, Some(rec.ref_id)))
+            .collect();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.addr, e.ref_id))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        let reader = trace_file::TraceReader::new(bytes.as_slice()).unwrap();
+        let replayed = trace_file::replay(reader, &mut LRUStack::new()).unwrap();
+        assert_eq!(replayed.to_vec(), rd.hist.to_vec());
+    }
+
+    #[test]
+    fn trace_on_threads() {
+        // i = 0, 10 { j = 0, 10 { a[i] b[j] } }
+        let mut aref = Node::new_ref("A", vec![10], |ij| vec![ij[0] as i64]);
+        let mut bref = Node::new_ref("B", vec![10], |ij| vec![ij[1] as i64]);
+        let mut jloop = Node::new_single_loop("j", 0, 10);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut bref);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+
+        let stack = std::thread::spawn({
+            let code = Arc::clone(&iloop);
+            move || trace_shared(&code, LRUStack::new()).0.to_vec()
+        });
+        let splay = std::thread::spawn({
+            let code = Arc::clone(&iloop);
+            move || trace_shared(&code, crate::LRUSplay::new()).0.to_vec()
+        });
+        let stack = stack.join().unwrap();
+        assert_eq!(stack, splay.join().unwrap());
+        assert_eq!(stack, trace(&mut iloop, LRUStack::new()).0.to_vec());
+    }
 }
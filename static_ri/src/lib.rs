@@ -0,0 +1,6 @@
+//! Reuse interval tracing of loop trees.  An `RiTracer` is a trace sink of
+//! `static_rd::trace` that gives each access its reuse interval at element or cache-line
+//! granularity, which any number of `RiOutput`s consume, e.g. a `Hist` and a `Tsv` file.
+
+pub mod output;
+pub mod trace;
@@ -1,7 +1,6 @@
-mod ri_utils;
-mod sampling;
 use dace::arybase;
 use dace_tests::polybench;
+use static_ri::trace::{tracing_ri, Granularity};
 use std::{collections::HashMap, time::Instant};
 use tracing_subscriber::EnvFilter;
 
@@ -15,7 +14,7 @@ fn main() {
     let start = Instant::now();
     // let hist = static_rd::trace::trace(&mut trace);
     // let hist = static_rd::trace::tracing_ri(&mut trace);
-    let _hist = sampling::tracing_ri(&mut trace);
+    let _hist = tracing_ri(&mut trace, Granularity::Line);
     let mut ans = HashMap::new();
     arybase::sample_collect(&trace, &mut wrapping_loop, &mut ans, &mut ref_coutner);
     let _samples: HashMap<usize, std::collections::BTreeSet<Vec<usize>>> =
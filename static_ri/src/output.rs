@@ -0,0 +1,71 @@
+use crate::trace::RiRecord;
+use hist::Hist;
+
+use std::io::{self, Write};
+
+/// A consumer of the reuse intervals of one run.  An `RiTracer` feeds every access to all
+/// of its outputs, in the order they are given.
+pub trait RiOutput {
+    fn on_ri(&mut self, rec: &RiRecord);
+
+    /// The trace has ended.  Outputs that write somewhere report their errors here.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Histogram of the reuse intervals, with `None` for first accesses.
+impl RiOutput for Hist {
+    fn on_ri(&mut self, rec: &RiRecord) {
+        self.add_dist(rec.ri);
+    }
+}
+
+/// All records, in order.
+impl RiOutput for Vec<RiRecord> {
+    fn on_ri(&mut self, rec: &RiRecord) {
+        self.push(rec.clone());
+    }
+}
+
+/// Tab-separated lines of ref id, reuse interval (-1 for a first access), address and
+/// logical time, after a header line.  Write errors stop the output and are returned by
+/// `finish`.
+pub struct Tsv<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tsv<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(b"Label\tReuse Interval\tTag\tLogical Time\n")?;
+        Ok(Tsv { out, error: None })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> RiOutput for Tsv<W> {
+    fn on_ri(&mut self, rec: &RiRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let ri = rec.ri.map_or(-1, |ri| ri as i64);
+        if let Err(e) = writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}",
+            rec.ref_id, ri, rec.addr, rec.time
+        ) {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
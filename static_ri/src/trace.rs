@@ -0,0 +1,231 @@
+use crate::output::RiOutput;
+use dace::arybase::set_arybase;
+use dace::ast::{Node, Stmt};
+use dace::record::AccessRecord;
+use fxhash::FxHashMap;
+use hist::Hist;
+use static_rd::sink::TraceSink;
+use static_rd::trace::trace_to;
+use std::io;
+use std::sync::Arc;
+use tracing::debug;
+
+/// The unit of data whose reuse is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// Array elements.
+    Element,
+    /// Cache lines of the tracer's `Layout`.
+    Line,
+}
+
+/// One access with its reuse interval: the logical time since the previous access to the
+/// same element or line of the same array, `None` for the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiRecord {
+    pub time: usize,
+    pub ref_id: usize,
+    /// Element or cache-line address, by the granularity of the tracer.
+    pub addr: usize,
+    pub ri: Option<usize>,
+}
+
+/// Reuse interval tracer, a trace sink that gives each access its reuse interval and
+/// forwards it to all of its outputs, in the order they are given.  Intervals are in the
+/// logical time of the records, and lines are those of the layout the trace is generated
+/// with.  Errors of the outputs are kept until `into_result`.
+pub struct RiTracer<'a> {
+    granularity: Granularity,
+    /// Last access time of every (array id, address).
+    last_access: FxHashMap<(usize, usize), usize>,
+    outputs: Vec<&'a mut dyn RiOutput>,
+    error: Option<io::Error>,
+}
+
+impl<'a> RiTracer<'a> {
+    pub fn new(granularity: Granularity, outputs: Vec<&'a mut dyn RiOutput>) -> Self {
+        RiTracer {
+            granularity,
+            last_access: Default::default(),
+            outputs,
+            error: None,
+        }
+    }
+
+    /// Record `rec` as the next access and return its reuse interval.
+    pub fn access(&mut self, rec: &AccessRecord) -> RiRecord {
+        let addr = match self.granularity {
+            Granularity::Element => rec.elem,
+            Granularity::Line => rec.line,
+        };
+        let prev = self.last_access.insert((rec.ary_id, addr), rec.time);
+        RiRecord {
+            time: rec.time,
+            ref_id: rec.ref_id,
+            addr,
+            ri: prev.map(|t| rec.time - t),
+        }
+    }
+
+    /// The first error of the outputs when they were finished.
+    pub fn into_result(self) -> io::Result<()> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+impl TraceSink for RiTracer<'_> {
+    fn on_access(&mut self, rec: &AccessRecord) {
+        let rec = self.access(rec);
+        debug!("ri record: {:?}", rec);
+        self.outputs.iter_mut().for_each(|out| out.on_ri(&rec));
+    }
+
+    fn finish(&mut self) {
+        for out in &mut self.outputs {
+            if let Err(e) = out.finish() {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+}
+
+/// The reuse interval histogram of `code` at `granularity`.
+pub fn tracing_ri(code: &mut Arc<Node>, granularity: Granularity) -> Hist {
+    set_arybase(code);
+    let mut hist = Hist::new();
+    let mut tracer = RiTracer::new(granularity, vec![&mut hist]);
+    trace_to(code, &mut [&mut tracer]);
+    tracer.into_result().expect("a histogram cannot fail");
+    hist
+}
+
+/// Print the tree, one node per line indented by its depth.
+pub fn print_tree(node: &Arc<Node>, level: usize) {
+    print!("{:indent$}", "", indent = level * 2);
+    match &node.stmt {
+        Stmt::Ref(aref) => match aref.ref_id {
+            Some(id) => println!("Ref(id: {}): {:?}", id, aref),
+            None => println!("Ref(no id): {:?}", aref),
+        },
+        Stmt::Loop(aloop) => println!("Loop: {:?}", aloop),
+        Stmt::Block(blk) => println!("Block: {:?}", blk),
+        Stmt::Branch(stmt) => println!("Branch: {:?}", stmt),
+        Stmt::Call(call) => println!("Call: {:?}", call),
+    }
+
+    match &node.stmt {
+        Stmt::Loop(aloop) => {
+            for child in &aloop.body {
+                print_tree(child, level + 1);
+            }
+        }
+        Stmt::Block(blk) => {
+            for child in blk {
+                print_tree(child, level + 1);
+            }
+        }
+        Stmt::Branch(stmt) => {
+            print_tree(&stmt.then_body, level + 1);
+            if let Some(else_body) = &stmt.else_body {
+                print_tree(else_body, level + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output::Tsv;
+    use stack_alg_sim::stack::LRUStack;
+    use static_rd::sink::ReuseDistance;
+
+    // i = 0, 4 { j = 0, i { A[j] A[i] } }
+    fn triangle() -> Arc<Node> {
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        let mut jloop = Node::new_single_loop_dyn_ub("j", 0, |i| i[0]);
+        let mut aj = Node::new_ref("A", vec![16], |ij| vec![ij[1] as i64]);
+        let mut ai = Node::new_ref("A", vec![16], |ij| vec![ij[0] as i64]);
+        Node::extend_loop_body(&mut jloop, &mut aj);
+        Node::extend_loop_body(&mut jloop, &mut ai);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        iloop
+    }
+
+    #[test]
+    fn dynamic_bounds() {
+        let mut code = triangle();
+        set_arybase(&mut code);
+        let mut recs = vec![];
+        let mut tracer = RiTracer::new(Granularity::Element, vec![&mut recs]);
+        trace_to(&code, &mut [&mut tracer]);
+        tracer.into_result().unwrap();
+        // (A[0] A[1]) (A[0] A[2] A[1] A[2]) (A[0] A[3] A[1] A[3] A[2] A[3])
+        let addrs: Vec<_> = recs.iter().map(|r| r.addr).collect();
+        assert_eq!(addrs, [0, 1, 0, 2, 1, 2, 0, 3, 1, 3, 2, 3]);
+        let ris: Vec<_> = recs.iter().map(|r| r.ri).collect();
+        let expected = [
+            None,
+            None,
+            Some(2),
+            None,
+            Some(3),
+            Some(2),
+            Some(4),
+            None,
+            Some(4),
+            Some(2),
+            Some(5),
+            Some(2),
+        ];
+        assert_eq!(ris, expected);
+        assert!(recs.iter().enumerate().all(|(t, r)| r.time == t));
+    }
+
+    #[test]
+    fn runs_are_independent() {
+        let first = tracing_ri(&mut triangle(), Granularity::Element);
+        let second = tracing_ri(&mut triangle(), Granularity::Element);
+        assert_eq!(first.to_vec(), second.to_vec());
+        // A[0..4] fits in one cache line
+        let lines = tracing_ri(&mut triangle(), Granularity::Line);
+        assert_eq!(lines.to_vec(), [(Some(1), 11), (None, 1)]);
+    }
+
+    #[test]
+    fn tsv_output() {
+        let mut code = triangle();
+        set_arybase(&mut code);
+        let mut tsv = Tsv::new(vec![]).unwrap();
+        let mut hist = Hist::new();
+        let mut tracer = RiTracer::new(Granularity::Element, vec![&mut tsv, &mut hist]);
+        trace_to(&code, &mut [&mut tracer]);
+        tracer.into_result().unwrap();
+        let text = String::from_utf8(tsv.into_inner()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 13);
+        assert_eq!(lines[0], "Label\tReuse Interval\tTag\tLogical Time");
+        assert_eq!(lines[1], "0\t-1\t0\t0");
+        assert_eq!(lines[3], "0\t2\t0\t2");
+        assert_eq!(hist.to_vec().last(), Some(&(None, 4)));
+    }
+
+    #[test]
+    fn beside_other_sinks() {
+        // i = 0, 4 { j = 0, 2 { a[j] } }
+        let mut aref = Node::new_ref("A", vec![2], |ij| vec![ij[1] as i64]);
+        let mut jloop = Node::new_single_loop("j", 0, 2);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        set_arybase(&mut iloop);
+        let mut rd = ReuseDistance::new(LRUStack::new());
+        let mut hist = Hist::new();
+        let mut ri = RiTracer::new(Granularity::Element, vec![&mut hist]);
+        trace_to(&iloop, &mut [&mut rd, &mut ri]);
+        ri.into_result().unwrap();
+        assert_eq!(rd.hist.to_vec(), [(Some(2), 6), (None, 2)]);
+        assert_eq!(hist.to_vec(), [(Some(2), 6), (None, 2)]);
+    }
+}
@@ -0,0 +1,487 @@
+//! A compact binary format for address traces.
+//!
+//! A file is a header followed by chunks of records:
+//!
+//! ```text
+//! magic     b"DACETRAC"
+//! version   u16, little endian
+//! flags     u8, bit 0 set if records carry ref ids
+//! hash      u64, little endian, identifies the traced program
+//! chunk len varint, the most records in a chunk
+//! params    varint count, then (key, value) pairs of varint-length UTF-8 strings
+//! chunk*    varint record count, varint payload length, payload
+//! end       a chunk with record count 0
+//! ```
+//!
+//! In a payload each record is the zigzag varint of the difference between its address
+//! and the previous address of the chunk (0 for the first), followed by the varint ref id
+//! if the file has ref ids.  Chunks can thus be decoded independently, and a file that
+//! ends before the end marker is reported as truncated.  A chunk with more records than
+//! the header allows, or a longer payload than its records can take, is invalid, so a
+//! corrupt length cannot make the reader allocate without bound.
+
+use hist::Hist;
+use stack_alg_sim::LRU;
+
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"DACETRAC";
+pub const VERSION: u16 = 2;
+/// Records per chunk unless set with `TraceWriter::with_chunk_len`.
+pub const DEFAULT_CHUNK_LEN: usize = 1 << 16;
+/// The most records per chunk a file may declare.
+pub const MAX_CHUNK_LEN: usize = 1 << 24;
+/// The most payload bytes of a record: two 64-bit varints.
+const MAX_RECORD_BYTES: usize = 20;
+
+const FLAG_REF_IDS: u8 = 1;
+
+/// What a trace file says about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Header {
+    /// Whether every record carries a ref id.
+    pub ref_ids: bool,
+    /// A hash of the traced program, so that traces of different programs are not mixed.
+    pub program_hash: u64,
+    /// Free-form parameters of the run, e.g. `("N", "1024")`.
+    pub params: Vec<(String, String)>,
+}
+
+/// One access of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub addr: usize,
+    pub ref_id: Option<usize>,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Append `x` to `buf` as a little-endian base-128 varint.
+pub fn put_varint(buf: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        buf.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+pub fn zigzag(x: i64) -> u64 {
+    ((x << 1) ^ (x >> 63)) as u64
+}
+
+pub fn unzigzag(x: u64) -> i64 {
+    (x >> 1) as i64 ^ -((x & 1) as i64)
+}
+
+/// Decode a varint from the front of `buf`, advancing it.
+fn take_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut x = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid("varint runs past the end of a chunk"))?;
+        *buf = rest;
+        x |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(invalid("varint is longer than 64 bits"))
+}
+
+/// Read one varint written by `put_varint`.
+pub fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut x = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        x |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(invalid("varint is longer than 64 bits"))
+}
+
+/// Read a string written as its length and bytes.  The length is not trusted: only the
+/// bytes that are there are read.
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    let len = read_varint(r)?;
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid("a string runs past the end of the header"));
+    }
+    String::from_utf8(bytes).map_err(|e| invalid(e.to_string()))
+}
+
+/// Streams records into a trace file.  `finish` must be called to write the last chunk
+/// and the end marker.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    ref_ids: bool,
+    chunk_len: usize,
+    count: usize,
+    prev: usize,
+    payload: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, header: &Header) -> io::Result<Self> {
+        TraceWriter::with_chunk_len(out, header, DEFAULT_CHUNK_LEN)
+    }
+
+    pub fn with_chunk_len(mut out: W, header: &Header, chunk_len: usize) -> io::Result<Self> {
+        assert!(
+            (1..=MAX_CHUNK_LEN).contains(&chunk_len),
+            "chunks must hold 1 to {} records",
+            MAX_CHUNK_LEN
+        );
+        let mut buf = MAGIC.to_vec();
+        buf.extend(VERSION.to_le_bytes());
+        buf.push(if header.ref_ids { FLAG_REF_IDS } else { 0 });
+        buf.extend(header.program_hash.to_le_bytes());
+        put_varint(&mut buf, chunk_len as u64);
+        put_varint(&mut buf, header.params.len() as u64);
+        for (key, value) in header.params.iter() {
+            for s in [key, value] {
+                put_varint(&mut buf, s.len() as u64);
+                buf.extend(s.as_bytes());
+            }
+        }
+        out.write_all(&buf)?;
+        Ok(TraceWriter {
+            out,
+            ref_ids: header.ref_ids,
+            chunk_len,
+            count: 0,
+            prev: 0,
+            payload: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Append a record.  `ref_id` must be given exactly when the header has ref ids.
+    pub fn write(&mut self, addr: usize, ref_id: Option<usize>) -> io::Result<()> {
+        assert!(!self.finished, "write after finish");
+        put_varint(
+            &mut self.payload,
+            zigzag((addr as i64).wrapping_sub(self.prev as i64)),
+        );
+        match (self.ref_ids, ref_id) {
+            (true, Some(id)) => put_varint(&mut self.payload, id as u64),
+            (false, None) => {}
+            (true, None) => panic!("the trace has ref ids but the record has none"),
+            (false, Some(_)) => panic!("the trace has no ref ids but the record has one"),
+        }
+        self.prev = addr;
+        self.count += 1;
+        if self.count == self.chunk_len {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        let mut frame = Vec::with_capacity(20);
+        put_varint(&mut frame, self.count as u64);
+        put_varint(&mut frame, self.payload.len() as u64);
+        self.out.write_all(&frame)?;
+        self.out.write_all(&self.payload)?;
+        self.payload.clear();
+        self.count = 0;
+        self.prev = 0;
+        Ok(())
+    }
+
+    /// Write the pending records and the end marker, and flush the output.  Calling it
+    /// again has no effect.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.flush_chunk()?;
+            self.out.write_all(&[0])?;
+            self.out.flush()?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// The output, after `finish`.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.finish()?;
+        Ok(self.out)
+    }
+}
+
+/// Streams the records of a trace file.  Iteration stops after the end marker; a read
+/// error or a truncated file is returned as the last item.
+pub struct TraceReader<R: Read> {
+    input: R,
+    header: Header,
+    chunk_len: usize,
+    payload: Vec<u8>,
+    pos: usize,
+    left: usize,
+    prev: usize,
+    done: bool,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut fixed = [0u8; 19];
+        input.read_exact(&mut fixed)?;
+        if &fixed[..8] != MAGIC {
+            return Err(invalid("not a trace file"));
+        }
+        let version = u16::from_le_bytes([fixed[8], fixed[9]]);
+        if version != VERSION {
+            return Err(invalid(format!(
+                "trace format version {version} is not supported"
+            )));
+        }
+        let flags = fixed[10];
+        let program_hash = u64::from_le_bytes(fixed[11..19].try_into().unwrap());
+        let chunk_len = read_varint(&mut input)?;
+        if chunk_len == 0 || chunk_len > MAX_CHUNK_LEN as u64 {
+            return Err(invalid(format!("chunk length {chunk_len} is out of range")));
+        }
+        let nparams = read_varint(&mut input)?;
+        let params = (0..nparams)
+            .map(|_| Ok((read_string(&mut input)?, read_string(&mut input)?)))
+            .collect::<io::Result<_>>()?;
+        Ok(TraceReader {
+            input,
+            header: Header {
+                ref_ids: flags & FLAG_REF_IDS != 0,
+                program_hash,
+                params,
+            },
+            chunk_len: chunk_len as usize,
+            payload: Vec::new(),
+            pos: 0,
+            left: 0,
+            prev: 0,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Load the next chunk.  Returns false at the end marker.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let count = read_varint(&mut self.input)? as usize;
+        if count == 0 {
+            return Ok(false);
+        }
+        if count > self.chunk_len {
+            return Err(invalid(format!(
+                "chunk of {count} records is longer than {}",
+                self.chunk_len
+            )));
+        }
+        let len = read_varint(&mut self.input)?;
+        if len > (count * MAX_RECORD_BYTES) as u64 {
+            return Err(invalid(format!(
+                "payload of {len} bytes is too long for {count} records"
+            )));
+        }
+        self.payload.resize(len as usize, 0);
+        self.input.read_exact(&mut self.payload)?;
+        self.pos = 0;
+        self.left = count;
+        self.prev = 0;
+        Ok(true)
+    }
+
+    fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        if self.left == 0 && !self.next_chunk()? {
+            return Ok(None);
+        }
+        let mut buf = &self.payload[self.pos..];
+        let before = buf.len();
+        let delta = unzigzag(take_varint(&mut buf)?);
+        let ref_id = if self.header.ref_ids {
+            Some(take_varint(&mut buf)? as usize)
+        } else {
+            None
+        };
+        self.pos += before - buf.len();
+        self.left -= 1;
+        self.prev = (self.prev as i64).wrapping_add(delta) as usize;
+        Ok(Some(Entry {
+            addr: self.prev,
+            ref_id,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// Run every address of a trace file through `analyzer` and return the reuse distance
+/// histogram.
+pub fn replay<R: Read, T: LRU<usize>>(
+    reader: TraceReader<R>,
+    analyzer: &mut T,
+) -> io::Result<Hist> {
+    let mut hist = Hist::new();
+    for entry in reader {
+        hist.add_dist(analyzer.rec_access(entry?.addr));
+    }
+    Ok(hist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stack_alg_sim::stack::LRUStack;
+
+    fn header(ref_ids: bool) -> Header {
+        Header {
+            ref_ids,
+            program_hash: 0x1234_5678_9abc_def0,
+            params: vec![("N".to_string(), "16".to_string())],
+        }
+    }
+
+    fn write_all(header: &Header, entries: &[Entry], chunk_len: usize) -> Vec<u8> {
+        let mut w = TraceWriter::with_chunk_len(Vec::new(), header, chunk_len).unwrap();
+        for e in entries {
+            w.write(e.addr, e.ref_id).unwrap();
+        }
+        w.into_inner().unwrap()
+    }
+
+    #[test]
+    fn varints() {
+        for x in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, x);
+            assert_eq!(take_varint(&mut buf.as_slice()).unwrap(), x);
+        }
+        for x in [0, -1, 1, i64::MIN, i64::MAX] {
+            assert_eq!(unzigzag(zigzag(x)), x);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries: Vec<_> = [5, 6, 7, 3, 1 << 40, 0, 7]
+            .iter()
+            .enumerate()
+            .map(|(i, &addr)| Entry {
+                addr,
+                ref_id: Some(i % 3),
+            })
+            .collect();
+        for chunk_len in [1, 3, 100] {
+            let bytes = write_all(&header(true), &entries, chunk_len);
+            let reader = TraceReader::new(bytes.as_slice()).unwrap();
+            assert_eq!(*reader.header(), header(true));
+            let read: Vec<_> = reader.map(Result::unwrap).collect();
+            assert_eq!(read, entries);
+        }
+    }
+
+    #[test]
+    fn strided_trace_is_small() {
+        let entries: Vec<_> = (0..10000)
+            .map(|i| Entry {
+                addr: 1000 + 8 * i,
+                ref_id: None,
+            })
+            .collect();
+        let bytes = write_all(&header(false), &entries, DEFAULT_CHUNK_LEN);
+        assert!(bytes.len() < 10100);
+        let read: Vec<_> = TraceReader::new(bytes.as_slice())
+            .unwrap()
+            .map(|e| e.unwrap().addr)
+            .collect();
+        assert_eq!(read, entries.iter().map(|e| e.addr).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncated() {
+        let entries: Vec<_> = (0..10).map(|addr| Entry { addr, ref_id: None }).collect();
+        let bytes = write_all(&header(false), &entries, 4);
+        let cut = &bytes[..bytes.len() - 3];
+        let read: Vec<_> = TraceReader::new(cut).unwrap().collect();
+        assert_eq!(read.len(), 9);
+        assert!(read[..8].iter().all(Result::is_ok));
+        assert!(read[8].is_err());
+    }
+
+    #[test]
+    fn corrupt_chunk_lengths() {
+        let entries: Vec<_> = (0..10).map(|addr| Entry { addr, ref_id: None }).collect();
+        let bytes = write_all(&header(false), &entries, 4);
+        let start = TraceReader::new(bytes.as_slice()).unwrap();
+        let chunk = bytes.len() - start.input.len();
+        // the first chunk starts with its record count 4 and payload length 4
+        assert_eq!(bytes[chunk..chunk + 2], [4, 4]);
+        for (pos, byte) in [(chunk, 5), (chunk + 1, 0x7f)] {
+            let mut bad = bytes.clone();
+            bad[pos] = byte;
+            let err = TraceReader::new(bad.as_slice())
+                .unwrap()
+                .find_map(Result::err)
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn corrupt_header_strings() {
+        let bytes = write_all(&header(false), &[], 4);
+        // the fixed fields, the chunk length 4 and one parameter, then the key "N"
+        assert_eq!(bytes[19..23], [4, 1, 1, b'N']);
+        for len in [2, 1 << 40, u64::MAX] {
+            let mut bad = bytes[..21].to_vec();
+            put_varint(&mut bad, len);
+            bad.extend(&bytes[22..]);
+            let err = TraceReader::new(bad.as_slice()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn not_a_trace() {
+        let err = TraceReader::new(&b"DACETRAX and more bytes"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn replay_lru() {
+        let entries: Vec<_> = [1, 2, 2, 1]
+            .into_iter()
+            .map(|addr| Entry { addr, ref_id: None })
+            .collect();
+        let bytes = write_all(&header(false), &entries, 2);
+        let hist = replay(
+            TraceReader::new(bytes.as_slice()).unwrap(),
+            &mut LRUStack::new(),
+        )
+        .unwrap();
+        assert_eq!(hist.to_vec(), [(Some(1), 1), (Some(2), 1), (None, 2)]);
+    }
+}